@@ -0,0 +1,52 @@
+-- Append-only audit trail of every PMS request.
+CREATE TABLE IF NOT EXISTS pms_audit_log (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    mode VARCHAR(32) NOT NULL,
+    room_number VARCHAR(64) NULL,
+    old_room VARCHAR(64) NULL,
+    folio_number VARCHAR(64) NULL,
+    params TEXT NOT NULL,
+    outcome VARCHAR(16) NOT NULL,
+    code VARCHAR(64) NULL,
+    message TEXT NOT NULL,
+    idempotency_key VARCHAR(128) NULL,
+    fingerprint CHAR(64) NULL,
+    created_at DATETIME NOT NULL,
+    INDEX idx_pms_audit_room (room_number),
+    INDEX idx_pms_audit_old_room (old_room),
+    INDEX idx_pms_audit_folio (folio_number),
+    INDEX idx_pms_audit_created (created_at),
+    INDEX idx_pms_audit_idempotency (idempotency_key),
+    INDEX idx_pms_audit_fingerprint (fingerprint)
+);
+
+-- Several guests per room: one hotel_rooms row per guest, logging in as
+-- `username` (the room number, or `{room}-{n}` for a guest's own credential).
+-- Run once, and drop any UNIQUE index on hotel_rooms.room_number as well.
+ALTER TABLE hotel_rooms ADD COLUMN username VARCHAR(64) NULL;
+UPDATE hotel_rooms SET username = room_number WHERE username IS NULL;
+CREATE INDEX idx_hotel_rooms_username ON hotel_rooms (username);
+
+-- Devices allowed per guest credential (radcheck Simultaneous-Use), NULL for no limit.
+ALTER TABLE services ADD COLUMN simultaneous_use INT NULL;
+
+-- Hashed guest passwords (RADIUS_PASSWORD_STORAGE=ssha512 or crypt) are up to
+-- 106 characters. MODIFY restates the whole column: the bridge always writes
+-- a password, so it is NOT NULL here; write NULL instead if an existing
+-- hotel_rooms.password allows it and other tools rely on that.
+ALTER TABLE hotel_rooms MODIFY password VARCHAR(255) NOT NULL;
+
+-- Several hotels on one bridge (PROPERTIES): rows carry the property id,
+-- NULL for a single-property install.
+ALTER TABLE hotel_rooms ADD COLUMN property_id VARCHAR(64) NULL;
+CREATE INDEX idx_hotel_rooms_property_room ON hotel_rooms (property_id, room_number);
+ALTER TABLE pms_audit_log ADD COLUMN property_id VARCHAR(64) NULL;
+CREATE INDEX idx_pms_audit_property ON pms_audit_log (property_id);
+
+-- One row per guest folio and room, so a repeated checkin fails instead of
+-- adding the guest twice. Remove any such duplicates before running it.
+-- Needs MySQL 8.0.13 or later for the expressions in the index.
+CREATE UNIQUE INDEX idx_hotel_rooms_guest ON hotel_rooms ((COALESCE(property_id, '')), room_number, (COALESCE(folio_number, '')));
+
+-- Response sent for each successful request, returned again to its retries.
+ALTER TABLE pms_audit_log ADD COLUMN response TEXT NULL;
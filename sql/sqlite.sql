@@ -0,0 +1,45 @@
+-- Append-only audit trail of every PMS request.
+CREATE TABLE IF NOT EXISTS pms_audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mode TEXT NOT NULL,
+    room_number TEXT,
+    old_room TEXT,
+    folio_number TEXT,
+    params TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    code TEXT,
+    message TEXT NOT NULL,
+    idempotency_key TEXT,
+    fingerprint TEXT,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_pms_audit_room ON pms_audit_log (room_number);
+CREATE INDEX IF NOT EXISTS idx_pms_audit_old_room ON pms_audit_log (old_room);
+CREATE INDEX IF NOT EXISTS idx_pms_audit_folio ON pms_audit_log (folio_number);
+CREATE INDEX IF NOT EXISTS idx_pms_audit_created ON pms_audit_log (created_at);
+CREATE INDEX IF NOT EXISTS idx_pms_audit_idempotency ON pms_audit_log (idempotency_key);
+CREATE INDEX IF NOT EXISTS idx_pms_audit_fingerprint ON pms_audit_log (fingerprint);
+
+-- Several guests per room: one hotel_rooms row per guest, logging in as
+-- `username` (the room number, or `{room}-{n}` for a guest's own credential).
+-- Run once. A UNIQUE room_number column needs the table rebuilt without it.
+ALTER TABLE hotel_rooms ADD COLUMN username TEXT;
+UPDATE hotel_rooms SET username = room_number WHERE username IS NULL;
+CREATE INDEX IF NOT EXISTS idx_hotel_rooms_username ON hotel_rooms (username);
+
+-- Devices allowed per guest credential (radcheck Simultaneous-Use), NULL for no limit.
+ALTER TABLE services ADD COLUMN simultaneous_use INTEGER;
+
+-- Several hotels on one bridge (PROPERTIES): rows carry the property id,
+-- NULL for a single-property install.
+ALTER TABLE hotel_rooms ADD COLUMN property_id TEXT;
+CREATE INDEX IF NOT EXISTS idx_hotel_rooms_property_room ON hotel_rooms (property_id, room_number);
+ALTER TABLE pms_audit_log ADD COLUMN property_id TEXT;
+CREATE INDEX IF NOT EXISTS idx_pms_audit_property ON pms_audit_log (property_id);
+
+-- One row per guest folio and room, so a repeated checkin fails instead of
+-- adding the guest twice. Remove any such duplicates before running it.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_hotel_rooms_guest ON hotel_rooms (COALESCE(property_id, ''), room_number, COALESCE(folio_number, ''));
+
+-- Response sent for each successful request, returned again to its retries.
+ALTER TABLE pms_audit_log ADD COLUMN response TEXT;
@@ -1,7 +1,11 @@
+use crate::application::errors::ErrorResponse;
+use crate::application::utils::csv_utils::parse_records;
+use crate::domain::entities::AuditEntry;
+use chrono::NaiveDateTime;
 use salvo::oapi::{ToParameters, ToSchema};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Clone, ToParameters)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, ToParameters)]
 #[salvo(parameters(default_parameter_in = Query))]
 pub struct PmsQueryParams {
     pub mode: String,
@@ -14,10 +18,453 @@ pub struct PmsQueryParams {
     pub codate: Option<String>,
     pub cotime: Option<String>,
     pub gtype: Option<String>,
+    /// `own` or `shared`: how a guest joining an occupied room logs in.
+    pub credential: Option<String>,
+    /// Client-chosen key for one logical request; a retry with the same key
+    /// gets the original response. Also accepted as the `Idempotency-Key` header.
+    pub idempotency_key: Option<String>,
+    /// Hotel of a multi-property install; also taken from the
+    /// `/properties/{property}` path or the client's bound property.
+    pub property: Option<String>,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
+impl PmsQueryParams {
+    /// Copy safe to persist or log: the password is masked.
+    pub fn redacted(&self) -> Self {
+        Self {
+            pass: self.pass.as_ref().map(|_| "***".to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+/// JSON body accepted by `POST /vhp/events`, tagged by `mode`.
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum PmsEvent {
+    Checkin(PmsCheckinEvent),
+    Checkout(PmsCheckoutEvent),
+    Update(PmsUpdateEvent),
+}
+
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+pub struct PmsCheckinEvent {
+    pub room: String,
+    pub name: Option<String>,
+    pub pass: String,
+    pub rsvno: Option<String>,
+    pub cidate: String,
+    pub codate: String,
+    pub cotime: Option<String>,
+    pub gtype: Option<String>,
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+pub struct PmsCheckoutEvent {
+    pub room: String,
+    /// Checks out only this guest when the room has several.
+    pub rsvno: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+pub struct PmsUpdateEvent {
+    pub room: String,
+    pub oldroom: Option<String>,
+    pub name: Option<String>,
+    pub pass: String,
+    pub rsvno: Option<String>,
+    pub cidate: String,
+    pub codate: String,
+    pub cotime: Option<String>,
+    pub gtype: Option<String>,
+    /// `own` or `shared`: how the guest logs in after moving into an
+    /// occupied room.
+    pub credential: Option<String>,
+}
+
+impl From<PmsEvent> for PmsQueryParams {
+    fn from(event: PmsEvent) -> Self {
+        match event {
+            PmsEvent::Checkin(e) => PmsQueryParams {
+                mode: "checkin".into(),
+                room: Some(e.room),
+                oldroom: None,
+                name: e.name,
+                pass: Some(e.pass),
+                rsvno: e.rsvno,
+                cidate: Some(e.cidate),
+                codate: Some(e.codate),
+                cotime: e.cotime,
+                gtype: e.gtype,
+                credential: e.credential,
+                idempotency_key: None,
+                property: None,
+            },
+            PmsEvent::Checkout(e) => PmsQueryParams {
+                mode: "checkout".into(),
+                room: Some(e.room),
+                oldroom: None,
+                name: None,
+                pass: None,
+                rsvno: e.rsvno,
+                cidate: None,
+                codate: None,
+                cotime: None,
+                gtype: None,
+                credential: None,
+                idempotency_key: None,
+                property: None,
+            },
+            PmsEvent::Update(e) => PmsQueryParams {
+                mode: "update".into(),
+                room: Some(e.room),
+                oldroom: e.oldroom,
+                name: e.name,
+                pass: Some(e.pass),
+                rsvno: e.rsvno,
+                cidate: Some(e.cidate),
+                codate: Some(e.codate),
+                cotime: e.cotime,
+                gtype: e.gtype,
+                credential: e.credential,
+                idempotency_key: None,
+                property: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PmsResponse {
     pub status: String,
+    /// Machine-readable error code, only present on errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     pub message: String,
+    /// Request field at fault, with code `invalid_field`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Disconnect-Request results for the guest's open sessions, present
+    /// when a NAS is configured and the operation ended a credential.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disconnects: Option<Vec<SessionDisconnect>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SessionDisconnect {
+    pub nas: String,
+    pub session_id: String,
+    /// `ack`, `nak`, `timeout`, `unknown_nas` or `error`.
+    pub result: String,
+}
+
+impl PmsResponse {
+    pub fn success(message: impl Into<String>) -> Self {
+        Self {
+            status: "success".into(),
+            code: None,
+            message: message.into(),
+            field: None,
+            disconnects: None,
+        }
+    }
+
+    pub fn with_disconnects(mut self, disconnects: Option<Vec<SessionDisconnect>>) -> Self {
+        self.disconnects = disconnects;
+        self
+    }
+
+    pub fn error(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            status: "error".into(),
+            code: Some(code.into()),
+            message: message.into(),
+            field: None,
+            disconnects: None,
+        }
+    }
+
+    /// Error response for `err`, without internal details.
+    pub fn from_error(err: &ErrorResponse) -> Self {
+        Self {
+            field: err.field().map(str::to_string),
+            ..Self::error(err.code(), err.public_message())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct HistoryQueryParams {
+    /// Room number, also matched against the room a guest moved from.
+    pub room: Option<String>,
+    /// PMS folio / reservation number (`rsvno`).
+    pub folio: Option<String>,
+    /// Start of the range, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS`.
+    pub from: Option<String>,
+    /// End of the range (inclusive for a bare date).
+    pub to: Option<String>,
+    /// Maximum number of entries, newest first. Defaults to 100.
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub id: Option<i64>,
+    pub mode: String,
+    pub room_number: Option<String>,
+    pub old_room: Option<String>,
+    pub folio_number: Option<String>,
+    /// PMS parameters as received, password redacted.
+    pub params: serde_json::Value,
+    pub outcome: String,
+    pub code: Option<String>,
+    pub message: String,
+    pub idempotency_key: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<AuditEntry> for HistoryEntry {
+    fn from(entry: AuditEntry) -> Self {
+        Self {
+            id: entry.id,
+            params: serde_json::from_str(&entry.params)
+                .unwrap_or(serde_json::Value::String(entry.params)),
+            mode: entry.mode,
+            room_number: entry.room_number,
+            old_room: entry.old_room,
+            folio_number: entry.folio_number,
+            outcome: entry.outcome,
+            code: entry.code,
+            message: entry.message,
+            idempotency_key: entry.idempotency_key,
+            created_at: entry.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct RoomListParams {
+    /// Hotel service name, e.g. `premium`.
+    pub service: Option<String>,
+    /// `today` or `YYYY-MM-DD`: only guests checking out that day.
+    pub checkout: Option<String>,
+}
+
+/// An occupied room as shown to hotel staff.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RoomView {
+    pub room: String,
+    pub guests: Vec<GuestView>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GuestView {
+    /// RADIUS username the guest logs in with.
+    pub username: String,
+    pub name: Option<String>,
+    pub folio_number: Option<String>,
+    /// Hotel service name, absent when the service is no longer active.
+    pub service: Option<String>,
+    pub checkin_date: NaiveDateTime,
+    pub checkout_date: NaiveDateTime,
+}
+
+/// Body of `POST /admin/rooms/{room}/checkout`; may be omitted.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct AdminCheckoutRequest {
+    /// Checks out only this guest when the room has several.
+    pub rsvno: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AdminPasswordRequest {
+    pub pass: String,
+    /// Required when the room has several guests.
+    pub rsvno: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AdminExtendRequest {
+    /// New checkout date, ISO 8601 or one of `PMS_DATE_FORMATS`.
+    pub codate: String,
+    /// New checkout time, `HH:MM[:SS]`; defaults to the usual checkout time.
+    pub cotime: Option<String>,
+    /// Required when the room has several guests.
+    pub rsvno: Option<String>,
+}
+
+/// Body of `POST /portal/login`: the room plus the guest's last name,
+/// reservation number, or both.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PortalLoginRequest {
+    pub room: String,
+    pub last_name: Option<String>,
+    pub rsvno: Option<String>,
+    /// Hotel of a multi-property install, unless named in the path.
+    pub property: Option<String>,
+}
+
+/// Credential a captive portal logs the guest in with.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PortalSession {
+    /// RADIUS username.
+    pub username: String,
+    /// Absent when passwords are stored hashed and the guest did not log in
+    /// with theirs.
+    pub password: Option<String>,
+    /// Hotel service name, absent when the service is no longer active.
+    pub service: Option<String>,
+    /// Checkout, when the credential stops working.
+    pub expires_at: NaiveDateTime,
+}
+
+/// One in-house guest of a PMS snapshot, as in a `checkin` request.
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct SnapshotGuest {
+    pub room: String,
+    pub name: Option<String>,
+    /// Needed only to check in a guest missing on our side.
+    pub pass: Option<String>,
+    pub rsvno: Option<String>,
+    pub cidate: String,
+    pub codate: String,
+    pub cotime: Option<String>,
+    pub gtype: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotBody {
+    List(Vec<SnapshotGuest>),
+    Wrapped { guests: Vec<SnapshotGuest> },
+}
+
+impl SnapshotGuest {
+    /// Parses a JSON array (or `{"guests": [...]}`), or CSV whose header
+    /// row names the columns, e.g. `room,name,rsvno,cidate,codate`.
+    pub fn parse_snapshot(body: &str) -> anyhow::Result<Vec<Self>> {
+        if body.trim_start().starts_with(['[', '{']) {
+            return Ok(match serde_json::from_str(body)? {
+                SnapshotBody::List(guests) | SnapshotBody::Wrapped { guests } => guests,
+            });
+        }
+
+        let mut records = parse_records(body)?.into_iter();
+        let header: Vec<String> = records
+            .next()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|h| h.to_lowercase())
+            .collect();
+        let column = |name: &str| header.iter().position(|h| h == name);
+        let required =
+            |name: &str| column(name).ok_or_else(|| anyhow::anyhow!("missing column {}", name));
+        let (room, cidate, codate) = (required("room")?, required("cidate")?, required("codate")?);
+
+        Ok(records
+            .map(|record| {
+                let get = |i: Option<usize>| {
+                    i.and_then(|i| record.get(i))
+                        .filter(|v| !v.is_empty())
+                        .cloned()
+                };
+                SnapshotGuest {
+                    room: get(Some(room)).unwrap_or_default(),
+                    name: get(column("name")),
+                    pass: get(column("pass")),
+                    rsvno: get(column("rsvno")),
+                    cidate: get(Some(cidate)).unwrap_or_default(),
+                    codate: get(Some(codate)).unwrap_or_default(),
+                    cotime: get(column("cotime")),
+                    gtype: get(column("gtype")),
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct ReconcileParams {
+    /// Apply the fixes; without it only the report is returned.
+    pub apply: Option<bool>,
+}
+
+/// Differences between a PMS snapshot and `hotel_rooms` / RADIUS tables.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ReconcileReport {
+    /// Whether the fixes were written; `false` for a dry run.
+    pub applied: bool,
+    /// Guests that already match the snapshot.
+    pub in_sync: usize,
+    /// In the snapshot but not checked in here.
+    pub missing: Vec<ReconcileItem>,
+    /// Checked in here but no longer in the snapshot.
+    pub stale: Vec<ReconcileItem>,
+    pub mismatched: Vec<ReconcileItem>,
+    /// Hotel credentials no guest uses.
+    pub orphaned: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disconnects: Option<Vec<SessionDisconnect>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReconcileItem {
+    pub room: String,
+    pub username: Option<String>,
+    pub folio_number: Option<String>,
+    /// What differs, e.g. `checkout 2025-11-22 13:00:00 -> 2025-11-23 13:00:00`.
+    pub details: Vec<String>,
+}
+
+/// Result of `/health/live` or `/health/ready`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthReport {
+    /// `ok` when every check passed, `error` otherwise.
+    pub status: String,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<HealthCheck>) -> Self {
+        let healthy = checks.iter().all(|c| c.status == "ok");
+        Self {
+            status: if healthy { "ok" } else { "error" }.into(),
+            checks,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthCheck {
+    /// `database` or `hotel_service`.
+    pub name: String,
+    /// `ok` or `error`.
+    pub status: String,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_events_carry_the_credential_choice() {
+        let event: PmsEvent = serde_json::from_str(
+            r#"{"mode":"update","room":"205","oldroom":"101","pass":"x","cidate":"20/11/2025","codate":"22/11/2025","credential":"own"}"#,
+        )
+        .unwrap();
+        let query = PmsQueryParams::from(event);
+        assert_eq!(query.mode, "update");
+        assert_eq!(query.credential.as_deref(), Some("own"));
+    }
 }
@@ -1,3 +1,4 @@
+use crate::domain::errors::StorageError;
 use anyhow::Error as AnyhowError;
 use thiserror::Error;
 
@@ -6,15 +7,152 @@ pub enum ErrorResponse {
     #[error("validation error: {0}")]
     Validation(String),
 
+    /// A request field is missing or malformed.
+    #[error("{message}")]
+    InvalidField {
+        field: &'static str,
+        message: String,
+    },
+
     #[error("not found: {0}")]
     NotFound(String),
 
+    /// The request clashes with the current state: an occupied room, a
+    /// reused idempotency key, or a concurrent write to the same rows.
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+
+    /// The database could not be reached in time; worth retrying.
+    #[error("database unavailable: {0}")]
+    Unavailable(String),
+
+    /// No active hotel service to put guests on.
+    #[error("service not configured: {0}")]
+    NotConfigured(String),
+
     #[error("internal error: {0}")]
     InternalServerErr(String),
 }
 
+impl ErrorResponse {
+    pub fn invalid_field(field: &'static str, message: impl Into<String>) -> Self {
+        ErrorResponse::InvalidField {
+            field,
+            message: message.into(),
+        }
+    }
+
+    /// Stable code returned to the PMS in `PmsResponse.code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorResponse::Validation(_) => "validation_error",
+            ErrorResponse::InvalidField { .. } => "invalid_field",
+            ErrorResponse::NotFound(_) => "not_found",
+            ErrorResponse::Conflict(_) => "conflict",
+            ErrorResponse::Forbidden(_) => "forbidden",
+            ErrorResponse::Unavailable(_) => "database_unavailable",
+            ErrorResponse::NotConfigured(_) => "service_not_configured",
+            ErrorResponse::InternalServerErr(_) => "internal_error",
+        }
+    }
+
+    /// Full message, for logs and the audit trail.
+    pub fn message(&self) -> &str {
+        match self {
+            ErrorResponse::Validation(msg)
+            | ErrorResponse::InvalidField { message: msg, .. }
+            | ErrorResponse::NotFound(msg)
+            | ErrorResponse::Conflict(msg)
+            | ErrorResponse::Forbidden(msg)
+            | ErrorResponse::Unavailable(msg)
+            | ErrorResponse::NotConfigured(msg)
+            | ErrorResponse::InternalServerErr(msg) => msg,
+        }
+    }
+
+    /// Message safe to send to the caller: internal errors may quote SQL.
+    pub fn public_message(&self) -> &str {
+        match self {
+            ErrorResponse::InternalServerErr(_) => "internal server error",
+            _ => self.message(),
+        }
+    }
+
+    /// Request field at fault, for `PmsResponse.field`.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            ErrorResponse::InvalidField { field, .. } => Some(field),
+            _ => None,
+        }
+    }
+}
+
 impl From<AnyhowError> for ErrorResponse {
+    /// Repository errors the infrastructure classified as a [`StorageError`]
+    /// become a conflict or an unavailable database, with the driver's
+    /// message kept to the log, or a missing service under its own message;
+    /// anything else is internal.
     fn from(err: AnyhowError) -> Self {
-        ErrorResponse::InternalServerErr(err.to_string())
+        match err.downcast_ref::<StorageError>() {
+            Some(StorageError::Conflict | StorageError::Stale) => {
+                tracing::warn!("{:#}", err);
+                ErrorResponse::Conflict("conflicting concurrent update, retry the request".into())
+            }
+            Some(StorageError::Unavailable) => {
+                tracing::warn!("{:#}", err);
+                ErrorResponse::Unavailable("database unavailable, retry later".into())
+            }
+            Some(StorageError::InactiveService) => ErrorResponse::NotConfigured(err.to_string()),
+            None => match err.downcast::<ErrorResponse>() {
+                Ok(err) => err,
+                Err(err) => ErrorResponse::InternalServerErr(format!("{:#}", err)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn classified_storage_errors_keep_driver_details_private() {
+        let conflict = anyhow!("duplicate key value violates unique constraint \"radcheck_pkey\"")
+            .context(StorageError::Conflict);
+        let err = ErrorResponse::from(conflict);
+        assert_eq!(err.code(), "conflict");
+        assert!(!err.public_message().contains("radcheck"));
+
+        let err = ErrorResponse::from(anyhow!("pool timed out").context(StorageError::Unavailable));
+        assert_eq!(err.code(), "database_unavailable");
+
+        let err = ErrorResponse::from(
+            AnyhowError::new(StorageError::InactiveService)
+                .context("service Basic is no longer an active hotel service"),
+        );
+        assert_eq!(err.code(), "service_not_configured");
+        assert_eq!(
+            err.public_message(),
+            "service Basic is no longer an active hotel service"
+        );
+
+        let err = ErrorResponse::from(anyhow!("no such table: radcheck"));
+        assert_eq!(err.code(), "internal_error");
+        assert_eq!(err.message(), "no such table: radcheck");
+        assert_eq!(err.public_message(), "internal server error");
+    }
+
+    #[test]
+    fn wrapped_service_errors_survive_anyhow() {
+        let err = ErrorResponse::from(AnyhowError::new(ErrorResponse::invalid_field(
+            "codate",
+            "invalid checkout date 31/02/2025",
+        )));
+        assert_eq!(err.code(), "invalid_field");
+        assert_eq!(err.field(), Some("codate"));
     }
 }
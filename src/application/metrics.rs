@@ -0,0 +1,232 @@
+use crate::domain::entities::GuestProfile;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Buckets for repository calls, from a cached lookup to a slow transaction.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// Prometheus metrics served on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    /// Audited requests by mode and outcome: `success`, `replay`, or the
+    /// `ErrorResponse` code.
+    pub requests: IntCounterVec,
+    /// `BookingRepository` call latency by operation and `ok` / `error`.
+    pub repository_calls: HistogramVec,
+    pub active_rooms: IntGauge,
+    pub active_guests: IntGauge,
+    /// sqlx pool connections by state, `idle` or `in_use`.
+    pub db_connections: IntGaugeVec,
+    pub db_max_connections: IntGauge,
+    /// In-use connections over the pool maximum.
+    pub db_utilisation: Gauge,
+    /// Guests checked out by the sweeper since startup.
+    pub expired_checkouts: IntCounter,
+    /// Sweeps that failed to load overdue rooms since startup.
+    pub failed_sweeps: IntCounter,
+    /// Guest portal logins by outcome: `success`, `failure`, `locked`, or
+    /// the `ErrorResponse` code.
+    pub portal_logins: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("vhp".into()), None).expect("❌ invalid metrics registry");
+
+        let requests = IntCounterVec::new(
+            Opts::new("pms_requests_total", "PMS requests by mode and outcome"),
+            &["mode", "outcome"],
+        )
+        .unwrap();
+        let repository_calls = HistogramVec::new(
+            HistogramOpts::new(
+                "repository_call_duration_seconds",
+                "BookingRepository call latency",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["operation", "outcome"],
+        )
+        .unwrap();
+        let active_rooms = IntGauge::new("active_rooms", "Rooms with at least one guest").unwrap();
+        let active_guests = IntGauge::new("active_guests", "Guests checked in").unwrap();
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let db_max_connections =
+            IntGauge::new("db_pool_max_connections", "Database pool size limit").unwrap();
+        let db_utilisation = Gauge::new(
+            "db_pool_utilisation",
+            "Share of the database pool limit in use",
+        )
+        .unwrap();
+        let expired_checkouts = IntCounter::new(
+            "expired_checkouts_total",
+            "Guests checked out by the expiry sweeper",
+        )
+        .unwrap();
+        let failed_sweeps =
+            IntCounter::new("failed_sweeps_total", "Expiry sweeps that failed").unwrap();
+        let portal_logins = IntCounterVec::new(
+            Opts::new("portal_logins_total", "Guest portal logins by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+
+        let metrics = Self {
+            registry,
+            requests,
+            repository_calls,
+            active_rooms,
+            active_guests,
+            db_connections,
+            db_max_connections,
+            db_utilisation,
+            expired_checkouts,
+            failed_sweeps,
+            portal_logins,
+        };
+        metrics.register().expect("❌ failed to register metrics");
+        metrics
+    }
+
+    fn register(&self) -> Result<()> {
+        self.registry.register(Box::new(self.requests.clone()))?;
+        self.registry
+            .register(Box::new(self.repository_calls.clone()))?;
+        self.registry
+            .register(Box::new(self.active_rooms.clone()))?;
+        self.registry
+            .register(Box::new(self.active_guests.clone()))?;
+        self.registry
+            .register(Box::new(self.db_connections.clone()))?;
+        self.registry
+            .register(Box::new(self.db_max_connections.clone()))?;
+        self.registry
+            .register(Box::new(self.db_utilisation.clone()))?;
+        self.registry
+            .register(Box::new(self.expired_checkouts.clone()))?;
+        self.registry
+            .register(Box::new(self.failed_sweeps.clone()))?;
+        self.registry
+            .register(Box::new(self.portal_logins.clone()))?;
+        Ok(())
+    }
+
+    pub fn record_request(&self, mode: &str, outcome: &str) {
+        self.requests.with_label_values(&[mode, outcome]).inc();
+    }
+
+    pub fn record_portal_login(&self, outcome: &str) {
+        self.portal_logins.with_label_values(&[outcome]).inc();
+    }
+
+    /// Run a repository call in a `repository` span, recording its latency
+    /// under `operation`.
+    pub async fn time_repository<T>(
+        &self,
+        operation: &str,
+        call: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let result = call
+            .instrument(tracing::debug_span!("repository", operation))
+            .await;
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        let elapsed = started.elapsed();
+        self.repository_calls
+            .with_label_values(&[operation, outcome])
+            .observe(elapsed.as_secs_f64());
+        tracing::debug!(
+            operation,
+            outcome,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "repository call finished"
+        );
+        result
+    }
+
+    /// Totals over the guests of each property; room numbers repeat across
+    /// properties.
+    pub fn set_occupancy(&self, properties: &[Vec<GuestProfile>]) {
+        let (mut rooms, mut guests) = (0, 0);
+        for property in properties {
+            let numbers: HashSet<&str> = property.iter().map(|g| g.room_number.as_str()).collect();
+            rooms += numbers.len() as i64;
+            guests += property.len() as i64;
+        }
+        self.active_rooms.set(rooms);
+        self.active_guests.set(guests);
+    }
+
+    pub fn set_pool(&self, size: u32, idle: usize, max: u32) {
+        let in_use = (size as i64 - idle as i64).max(0);
+        self.db_connections
+            .with_label_values(&["idle"])
+            .set(idle as i64);
+        self.db_connections
+            .with_label_values(&["in_use"])
+            .set(in_use);
+        self.db_max_connections.set(max as i64);
+        self.db_utilisation.set(if max == 0 {
+            0.0
+        } else {
+            in_use as f64 / max as f64
+        });
+    }
+
+    /// Text exposition format, with its content type.
+    pub fn render(&self) -> Result<(String, String)> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        Ok((
+            encoder.format_type().to_string(),
+            String::from_utf8(buffer)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_recorded_metrics() {
+        METRICS.record_request("metrics_test", "validation_error");
+        METRICS
+            .time_repository("metrics_test_call", async { Ok(()) })
+            .await
+            .unwrap();
+        let failed: Result<()> = METRICS
+            .time_repository("metrics_test_call", async { Err(anyhow::anyhow!("down")) })
+            .await;
+        assert!(failed.is_err());
+        METRICS.set_pool(4, 1, 10);
+
+        let (content_type, body) = METRICS.render().unwrap();
+        assert!(content_type.starts_with("text/plain"));
+        assert!(body.contains(
+            r#"vhp_pms_requests_total{mode="metrics_test",outcome="validation_error"} 1"#
+        ));
+        assert!(body.contains(
+            r#"vhp_repository_call_duration_seconds_count{operation="metrics_test_call",outcome="error"} 1"#
+        ));
+        assert!(body.contains(r#"vhp_db_pool_connections{state="in_use"} 3"#));
+        assert!(body.contains("vhp_db_pool_utilisation 0.3"));
+    }
+}
@@ -1,4 +1,7 @@
 pub mod dtos;
 pub mod errors;
+pub mod metrics;
 pub mod services;
+pub mod settings;
+pub mod sweeper;
 pub mod utils;
@@ -1,186 +1,3560 @@
-use crate::application::dtos::{PmsQueryParams, PmsResponse};
+use crate::application::dtos::{
+    AdminExtendRequest, AdminPasswordRequest, GuestView, HistoryEntry, HistoryQueryParams,
+    PmsQueryParams, PmsResponse, PortalLoginRequest, PortalSession, ReconcileItem, ReconcileReport,
+    RoomListParams, RoomView, SessionDisconnect, SnapshotGuest,
+};
 use crate::application::errors::ErrorResponse;
+use crate::application::metrics::METRICS;
+use crate::application::settings::{DateSettings, ServiceSettings};
 use crate::application::utils::{
-    datetime_utils::{parse_checkin_datetime, parse_checkout_datetime},
+    datetime_utils::parse_range_bound,
+    password_utils::{hash_password, verify_password},
     string_utils::{clean_password, get_formatted_name},
 };
-use crate::domain::{entities::Booking, repositories::BookingRepository};
+use crate::domain::{
+    entities::{
+        AuditEntry, AuditFilter, Booking, GuestFilter, GuestProfile, HotelService, PasswordStorage,
+        Property, RadiusAttribute, ReconcilePlan, StoredCredential,
+    },
+    errors::StorageError,
+    nas::NasClient,
+    repositories::BookingRepository,
+};
 use anyhow::Result;
-use chrono::Local;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-pub struct BookingService<R: BookingRepository> {
+/// Times a checkin is planned before a room that keeps changing under it is
+/// reported as a conflict. Each retry means another guest's change committed.
+const CHECKIN_ATTEMPTS: usize = 10;
+
+pub struct BookingService<R: BookingRepository + ?Sized> {
     repo: Arc<R>,
+    settings: Arc<ServiceSettings>,
+    nas: Option<Arc<dyn NasClient>>,
 }
 
-impl<R: BookingRepository> BookingService<R> {
-    pub fn new(repo: Arc<R>) -> Self {
-        Self { repo }
+impl<R: BookingRepository + ?Sized> BookingService<R> {
+    pub fn new(repo: Arc<R>, settings: Arc<ServiceSettings>) -> Self {
+        Self {
+            repo,
+            settings,
+            nas: None,
+        }
+    }
+
+    /// Disconnect guests' open sessions on checkout and room changes.
+    pub fn with_nas_client(mut self, nas: Option<Arc<dyn NasClient>>) -> Self {
+        self.nas = nas;
+        self
     }
 
+    #[tracing::instrument(
+        name = "pms",
+        skip_all,
+        fields(
+            mode = %query.mode,
+            room = query.room.as_deref(),
+            folio = query.rsvno.as_deref()
+        )
+    )]
     pub async fn process(&self, query: PmsQueryParams) -> Result<PmsResponse, ErrorResponse> {
-        match query.mode.as_str() {
+        let audit = query.redacted();
+        let fingerprint = request_fingerprint(&self.settings.idempotency.secret, &query);
+
+        if let Some(replayed) = self.find_replay(&query, &fingerprint).await {
+            self.record_audit(audit, &fingerprint, &replayed, true)
+                .await;
+            return replayed;
+        }
+
+        let result = match query.mode.as_str() {
             "checkin" => self.handle_checkin(query).await,
             "checkout" => self.handle_checkout(query).await,
             "update" => self.handle_update(query).await,
             mode => {
                 tracing::error!("invalid mode {}", mode);
-                Err(ErrorResponse::Validation(format!("invalid mode {}", mode)))
+                Err(ErrorResponse::invalid_field(
+                    "mode",
+                    format!("invalid mode {}", mode),
+                ))
+            }
+        };
+
+        self.record_audit(audit, &fingerprint, &result, false).await;
+        result
+    }
+
+    /// A PMS retry of a request that already succeeded within the dedupe
+    /// window, recognised by idempotency key or by `rsvno` plus payload,
+    /// answered with the original response. A repeated payload after
+    /// another change to the guest or room is processed again. Lookup
+    /// failures are logged and the request is processed normally.
+    async fn find_replay(
+        &self,
+        query: &PmsQueryParams,
+        fingerprint: &str,
+    ) -> Option<Result<PmsResponse, ErrorResponse>> {
+        let window = self.settings.idempotency.window_secs;
+        if window <= 0 || !matches!(query.mode.as_str(), "checkin" | "checkout" | "update") {
+            return None;
+        }
+
+        let non_empty = |v: &Option<String>| v.clone().filter(|s| !s.trim().is_empty());
+        let key = non_empty(&query.idempotency_key);
+        if key.is_none() && non_empty(&query.rsvno).is_none() {
+            return None;
+        }
+
+        let since = self.settings.dates.now() - chrono::Duration::seconds(window);
+        let entry = match self
+            .repo
+            .find_replay(key.as_deref(), fingerprint, since)
+            .await
+        {
+            Ok(entry) => entry?,
+            Err(err) => {
+                tracing::error!("replay lookup failed: {}", err);
+                return None;
+            }
+        };
+
+        if key.is_some() && entry.fingerprint.as_deref() != Some(fingerprint) {
+            return Some(Err(ErrorResponse::Conflict(format!(
+                "idempotency key {} was already used for a different request",
+                key.unwrap_or_default()
+            ))));
+        }
+
+        tracing::info!(
+            "replay of {} for room {:?} answered from audit entry {:?}",
+            query.mode,
+            query.room,
+            entry.id
+        );
+        let response = entry
+            .response
+            .as_deref()
+            .and_then(|r| serde_json::from_str(r).ok())
+            .unwrap_or_else(|| PmsResponse::success(entry.message));
+        Some(Ok(response))
+    }
+
+    /// Checks out guests more than `grace_secs` past their checkout that the
+    /// PMS never checked out, auditing each as mode `expire`. Returns the
+    /// usernames of the guests checked out.
+    #[tracing::instrument(name = "expiry", skip(self))]
+    pub async fn expire_overdue(&self, grace_secs: i64) -> Result<Vec<String>, ErrorResponse> {
+        let cutoff = self.settings.dates.now() - chrono::Duration::seconds(grace_secs);
+        let overdue = self.repo.overdue_rooms(cutoff).await?;
+
+        let mut expired = Vec::with_capacity(overdue.len());
+        for guest in overdue {
+            let query = PmsQueryParams {
+                mode: "expire".into(),
+                room: Some(guest.room_number.clone()),
+                rsvno: guest.folio_number.clone(),
+                codate: Some(guest.checkout_date.format("%Y-%m-%dT%H:%M:%S").to_string()),
+                ..Default::default()
+            };
+            let fingerprint = request_fingerprint(&self.settings.idempotency.secret, &query);
+
+            let result = self.checkout_guests(&guest.room_number, Some(&guest)).await;
+            match &result {
+                Ok(_) => {
+                    tracing::info!(
+                        "auto checkout of {} in room {} (checkout was {})",
+                        guest.username,
+                        guest.room_number,
+                        guest.checkout_date
+                    );
+                    expired.push(guest.username.clone());
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "auto checkout of {} in room {} failed: {}",
+                        guest.username,
+                        guest.room_number,
+                        err
+                    );
+                }
+            }
+            self.record_audit(query, &fingerprint, &result, false).await;
+        }
+
+        Ok(expired)
+    }
+
+    /// Audit trail entries, newest first.
+    pub async fn history(
+        &self,
+        params: HistoryQueryParams,
+    ) -> Result<Vec<HistoryEntry>, ErrorResponse> {
+        let non_empty =
+            |v: Option<String>| v.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        let bound = |field: &'static str, v: Option<String>, end: bool| {
+            non_empty(v)
+                .map(|s| parse_range_bound(&s, end))
+                .transpose()
+                .map_err(|err| ErrorResponse::invalid_field(field, err.to_string()))
+        };
+
+        let filter = AuditFilter {
+            room: non_empty(params.room),
+            folio_number: non_empty(params.folio),
+            from: bound("from", params.from, false)?,
+            to: bound("to", params.to, true)?,
+            limit: params.limit.unwrap_or(100).clamp(1, 1000),
+        };
+
+        let entries = self.repo.audit_history(&filter).await?;
+        Ok(entries.into_iter().map(HistoryEntry::from).collect())
+    }
+
+    /// Occupied rooms for hotel staff, optionally narrowed to one hotel
+    /// service or to guests checking out on a given day.
+    pub async fn list_rooms(&self, params: RoomListParams) -> Result<Vec<RoomView>, ErrorResponse> {
+        let services = self.repo.get_cron_hotel_service().await?;
+
+        let service_id = match non_empty(&params.service) {
+            Some(name) => Some(
+                services
+                    .iter()
+                    .find(|s| s.name.eq_ignore_ascii_case(name))
+                    .map(|s| s.id)
+                    .ok_or_else(|| {
+                        ErrorResponse::invalid_field("service", format!("unknown service {}", name))
+                    })?,
+            ),
+            None => None,
+        };
+
+        let day = match non_empty(&params.checkout) {
+            Some(v) if v.eq_ignore_ascii_case("today") => Some(self.settings.dates.now().date()),
+            Some(v) => Some(NaiveDate::parse_from_str(v, "%Y-%m-%d").map_err(|_| {
+                ErrorResponse::invalid_field("checkout", format!("invalid date {}", v))
+            })?),
+            None => None,
+        };
+
+        let filter = GuestFilter {
+            service_id,
+            checkout_from: day.map(|d| d.and_time(NaiveTime::MIN)),
+            checkout_to: day
+                .and_then(|d| d.succ_opt())
+                .map(|d| d.and_time(NaiveTime::MIN)),
+        };
+
+        let guests = self.repo.list_guests(&filter).await?;
+        Ok(room_views(guests, &services))
+    }
+
+    pub async fn room(&self, room: &str) -> Result<RoomView, ErrorResponse> {
+        let guests = self.repo.room_guests(room).await?;
+        if guests.is_empty() {
+            return Err(ErrorResponse::NotFound(format!("room {} not found", room)));
+        }
+        let services = self.repo.get_cron_hotel_service().await?;
+        Ok(room_views(guests, &services).remove(0))
+    }
+
+    /// Credential of the guest of `request.room` whose last name (or pass
+    /// derived from it) and reservation number match those given. `None`
+    /// when no guest matches, without telling an empty room apart.
+    #[tracing::instrument(name = "portal", skip_all, fields(room = %request.room))]
+    pub async fn portal_login(
+        &self,
+        request: &PortalLoginRequest,
+    ) -> Result<Option<PortalSession>, ErrorResponse> {
+        let room = request.room.trim();
+        let last_name = request
+            .last_name
+            .as_deref()
+            .map(clean_password)
+            .filter(|n| !n.is_empty());
+        let rsvno = non_empty(&request.rsvno);
+        if room.is_empty() || (last_name.is_none() && rsvno.is_none()) {
+            return Err(ErrorResponse::Validation(
+                "room and last_name or rsvno are required".into(),
+            ));
+        }
+
+        let storage = self.password_storage();
+        let guests = self.repo.room_guests(room).await?;
+        let Some(guest) = guests.iter().find(|g| {
+            rsvno.is_none_or(|r| g.folio_number.as_deref() == Some(r))
+                && last_name.as_deref().is_none_or(|n| {
+                    surname_matches(g.name.as_deref(), n)
+                        || verify_password(storage, n, &g.password)
+                })
+        }) else {
+            return Ok(None);
+        };
+
+        // A hashed password can only be handed back when the guest gave it.
+        let password = match storage {
+            PasswordStorage::Cleartext => Some(guest.password.clone()),
+            _ => last_name.filter(|n| verify_password(storage, n, &guest.password)),
+        };
+        let services = self.repo.get_cron_hotel_service().await?;
+        Ok(Some(PortalSession {
+            username: guest.username.clone(),
+            password,
+            service: services
+                .iter()
+                .find(|s| s.id == guest.service_id)
+                .map(|s| s.name.clone()),
+            expires_at: guest.checkout_date,
+        }))
+    }
+
+    /// Property the service acts for, `None` on a single-property install.
+    pub fn property(&self) -> Option<&Property> {
+        self.settings.property.as_ref()
+    }
+
+    /// Current time at the property, the clock bookings are recorded by.
+    pub fn now(&self) -> NaiveDateTime {
+        self.settings.dates.now()
+    }
+
+    /// Checks out a room, or one guest of it, on behalf of hotel staff.
+    pub async fn force_checkout(
+        &self,
+        room: &str,
+        rsvno: Option<String>,
+    ) -> Result<PmsResponse, ErrorResponse> {
+        self.process_admin(PmsQueryParams {
+            mode: "checkout".into(),
+            room: Some(room.to_string()),
+            rsvno,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Gives a guest a new password, keeping the rest of the stay.
+    pub async fn reset_password(
+        &self,
+        room: &str,
+        request: AdminPasswordRequest,
+    ) -> Result<PmsResponse, ErrorResponse> {
+        let guests = self.repo.room_guests(room).await?;
+        let guest = admin_guest(&guests, room, non_empty(&request.rsvno))?;
+        if guests
+            .iter()
+            .any(|g| g.username == guest.username && !std::ptr::eq(g, guest))
+        {
+            return Err(ErrorResponse::Validation(format!(
+                "credential {} is shared with other guests of room {}",
+                guest.username, room
+            )));
+        }
+
+        self.process_admin(PmsQueryParams {
+            pass: Some(request.pass),
+            ..stay_update(guest)
+        })
+        .await
+    }
+
+    /// Moves a guest's checkout later; their credential expires accordingly.
+    pub async fn extend_stay(
+        &self,
+        room: &str,
+        request: AdminExtendRequest,
+    ) -> Result<PmsResponse, ErrorResponse> {
+        let guests = self.repo.room_guests(room).await?;
+        let guest = admin_guest(&guests, room, non_empty(&request.rsvno))?;
+
+        let checkout = self.checkout_date(&request.codate, request.cotime.as_deref())?;
+        if checkout <= guest.checkout_date {
+            return Err(ErrorResponse::Validation(format!(
+                "new checkout {} is not after current checkout {}",
+                checkout, guest.checkout_date
+            )));
+        }
+
+        self.process_admin(PmsQueryParams {
+            codate: Some(request.codate),
+            cotime: request.cotime,
+            ..stay_update(guest)
+        })
+        .await
+    }
+
+    /// Runs a staff action through the PMS handlers. It is audited as
+    /// `admin_<mode>` and never answered from, or used for, PMS replays.
+    #[tracing::instrument(
+        name = "admin",
+        skip_all,
+        fields(
+            mode = %query.mode,
+            room = query.room.as_deref(),
+            folio = query.rsvno.as_deref()
+        )
+    )]
+    async fn process_admin(&self, query: PmsQueryParams) -> Result<PmsResponse, ErrorResponse> {
+        let tagged = PmsQueryParams {
+            mode: format!("admin_{}", query.mode),
+            ..query.clone()
+        };
+        let fingerprint = request_fingerprint(&self.settings.idempotency.secret, &tagged);
+
+        let result = match query.mode.as_str() {
+            "checkout" => self.handle_checkout(query).await,
+            "update" => self.update_guest(query, false).await,
+            mode => Err(ErrorResponse::invalid_field(
+                "mode",
+                format!("invalid mode {}", mode),
+            )),
+        };
+
+        self.record_audit(tagged.redacted(), &fingerprint, &result, false)
+            .await;
+        result
+    }
+
+    /// Compares a full PMS in-house snapshot with `hotel_rooms` and the hotel
+    /// credentials. With `apply` the fixes are written in one transaction:
+    /// stale guests and orphaned credentials are removed, mismatched guests
+    /// rewritten and missing guests checked in. The snapshot wins over room
+    /// capacity limits.
+    #[tracing::instrument(name = "reconcile", skip_all, fields(guests = snapshot.len(), apply))]
+    pub async fn reconcile(
+        &self,
+        snapshot: Vec<SnapshotGuest>,
+        apply: bool,
+    ) -> Result<ReconcileReport, ErrorResponse> {
+        if apply && snapshot.is_empty() {
+            return Err(ErrorResponse::Validation(
+                "refusing to apply an empty snapshot".into(),
+            ));
+        }
+
+        let stays = snapshot
+            .iter()
+            .enumerate()
+            .map(|(i, guest)| {
+                Stay::parse(guest, &self.settings.dates)
+                    .map_err(|err| ErrorResponse::Validation(format!("guest {}: {}", i + 1, err)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut listed = HashSet::new();
+        for stay in &stays {
+            if !listed.insert(stay.key()) {
+                return Err(ErrorResponse::Validation(format!(
+                    "{} is listed twice",
+                    stay.key()
+                )));
+            }
+        }
+
+        let mut current = self.repo.list_guests(&GuestFilter::default()).await?;
+        let credentials: HashMap<String, StoredCredential> = self
+            .repo
+            .hotel_credentials()
+            .await?
+            .into_iter()
+            .map(|c| (c.username.clone(), c))
+            .collect();
+
+        // Cleartext credentials are rewritten in the configured form; other
+        // hashed forms cannot be converted.
+        let storage = self.password_storage();
+        let converted: HashMap<&str, String> = credentials
+            .values()
+            .filter(|c| {
+                storage != PasswordStorage::Cleartext
+                    && c.password_attribute.as_deref()
+                        == Some(PasswordStorage::Cleartext.attribute())
+            })
+            .filter_map(|c| {
+                let password = c.password.as_deref()?;
+                Some((c.username.as_str(), self.stored_password(password)))
+            })
+            .collect();
+        for guest in &mut current {
+            if let Some(password) = converted.get(guest.username.as_str()) {
+                guest.password = password.clone();
+            }
+        }
+        let mut remaining = current.clone();
+        let services = self.repo.get_cron_hotel_service().await?;
+
+        let mut report = ReconcileReport::default();
+        let mut missing = Vec::new();
+        let mut mismatched = Vec::new();
+        for stay in stays {
+            let found = remaining.iter().position(|g| match &stay.folio {
+                Some(folio) => g.folio_number.as_ref() == Some(folio),
+                None => g.room_number == stay.room && g.folio_number.is_none(),
+            });
+            let Some(index) = found else {
+                report.missing.push(ReconcileItem {
+                    room: stay.room.clone(),
+                    username: None,
+                    folio_number: stay.folio.clone(),
+                    details: if stay.pass.is_none() {
+                        vec!["no pass to check in with".into()]
+                    } else {
+                        Vec::new()
+                    },
+                });
+                missing.push(stay);
+                continue;
+            };
+
+            let guest = remaining.remove(index);
+            let service = match stay.gtype.as_deref() {
+                Some(g) => Some(self.resolve_service(Some(g)).await?),
+                None => None,
+            };
+            let mut details = stay_differences(
+                &stay,
+                &guest,
+                service.as_ref(),
+                &services,
+                &credentials,
+                storage,
+            );
+            let stranded = credentials
+                .get(&guest.username)
+                .and_then(|c| c.password_attribute.as_deref())
+                .is_some_and(|attribute| {
+                    attribute != storage.attribute()
+                        && !converted.contains_key(guest.username.as_str())
+                });
+            if stranded {
+                details.push("check in again to convert the password".into());
+            }
+            if details.is_empty() {
+                report.in_sync += 1;
+            } else {
+                report.mismatched.push(ReconcileItem {
+                    room: guest.room_number.clone(),
+                    username: Some(guest.username.clone()),
+                    folio_number: guest.folio_number.clone(),
+                    details,
+                });
+                if !stranded {
+                    mismatched.push((stay, guest, service));
+                }
+            }
+        }
+
+        report.stale = remaining
+            .iter()
+            .map(|g| ReconcileItem {
+                room: g.room_number.clone(),
+                username: Some(g.username.clone()),
+                folio_number: g.folio_number.clone(),
+                details: vec![format!("checkout {}", g.checkout_date)],
+            })
+            .collect();
+
+        let in_use: HashSet<&str> = current.iter().map(|g| g.username.as_str()).collect();
+        report.orphaned = credentials
+            .keys()
+            .filter(|u| !in_use.contains(u.as_str()))
+            .cloned()
+            .collect();
+        report.orphaned.sort();
+
+        if !apply {
+            return Ok(report);
+        }
+
+        let plan = self
+            .reconcile_plan(current, remaining, mismatched, missing, &report.orphaned)
+            .await?;
+        let mut released = self.repo.apply_reconciliation(&plan).await?;
+
+        // Credentials left behind by guests moved to another room.
+        let after = self.repo.list_guests(&GuestFilter::default()).await?;
+        for (guest, booking) in &plan.updates {
+            if booking.username != guest.username
+                && !released.contains(&guest.username)
+                && after.iter().all(|g| g.username != guest.username)
+            {
+                released.push(guest.username.clone());
+            }
+        }
+
+        let mut disconnects: Option<Vec<SessionDisconnect>> = None;
+        for username in &released {
+            if let Some(results) = self.disconnect_sessions(username).await {
+                disconnects.get_or_insert_with(Vec::new).extend(results);
+            }
+        }
+
+        report.applied = true;
+        report.disconnects = disconnects;
+        let summary = format!(
+            "reconciled {} missing, {} stale, {} mismatched, {} orphaned",
+            report.missing.len(),
+            report.stale.len(),
+            report.mismatched.len(),
+            report.orphaned.len()
+        );
+        tracing::info!("{}", summary);
+        let query = PmsQueryParams {
+            mode: "reconcile".into(),
+            ..Default::default()
+        };
+        let fingerprint = request_fingerprint(&self.settings.idempotency.secret, &query);
+        self.record_audit(
+            query,
+            &fingerprint,
+            &Ok(PmsResponse::success(summary)),
+            false,
+        )
+        .await;
+
+        Ok(report)
+    }
+
+    /// The writes that make `hotel_rooms` match the snapshot. Usernames are
+    /// placed against the rooms as they will be once stale guests are gone.
+    async fn reconcile_plan(
+        &self,
+        current: Vec<GuestProfile>,
+        stale: Vec<GuestProfile>,
+        mismatched: Vec<(Stay, GuestProfile, Option<HotelService>)>,
+        missing: Vec<Stay>,
+        orphans: &[String],
+    ) -> Result<ReconcilePlan, ErrorResponse> {
+        let planned_against = current.clone();
+        let mut rooms: HashMap<String, Vec<GuestProfile>> = HashMap::new();
+        for guest in current.into_iter().filter(|g| !stale.contains(g)) {
+            rooms
+                .entry(guest.room_number.clone())
+                .or_default()
+                .push(guest);
+        }
+        let shared = self.settings.rooms.shared_credential;
+
+        let mut plan = ReconcilePlan {
+            checkouts: stale,
+            orphans: orphans.to_vec(),
+            ..Default::default()
+        };
+
+        for (mut stay, guest, service) in mismatched {
+            // A snapshot date without a time keeps the recorded checkin time.
+            if stay.checkin.date() == guest.checkin_date.date() {
+                stay.checkin = guest.checkin_date;
+            }
+            let occupants = rooms.entry(guest.room_number.clone()).or_default();
+            occupants.retain(|g| g != &guest);
+            let service = match service {
+                Some(service) => service,
+                None => self.current_service(&guest).await?,
+            };
+
+            let placement = if stay.room != guest.room_number {
+                let target = rooms.get(&stay.room).cloned().unwrap_or_default();
+                self.place_guest(
+                    &stay.room,
+                    &target,
+                    shared,
+                    guest.password.clone(),
+                    service,
+                    stay.checkout,
+                )
+                .await?
+            } else {
+                // Others sharing the credential keep their password and expiry.
+                Placement {
+                    username: guest.username.clone(),
+                    password: guest.password.clone(),
+                    service,
+                    expires: latest_checkout(occupants, &guest.username, stay.checkout),
+                }
+            };
+
+            let booking = self.stay_booking(&stay, placement, guest.name.clone());
+            rooms
+                .entry(booking.room_number.clone())
+                .or_default()
+                .push(booked_profile(&booking));
+            plan.updates.push((guest, booking));
+        }
+
+        for stay in missing {
+            let Some(pass) = stay.pass.as_deref() else {
+                return Err(ErrorResponse::Validation(format!(
+                    "pass is required to check in {}",
+                    stay.key()
+                )));
+            };
+            let service = self.resolve_service(stay.gtype.as_deref()).await?;
+            let occupants = rooms.get(&stay.room).cloned().unwrap_or_default();
+            let placement = self
+                .place_guest(
+                    &stay.room,
+                    &occupants,
+                    shared,
+                    self.stored_password(&clean_password(pass)),
+                    service,
+                    stay.checkout,
+                )
+                .await?;
+
+            let booking = self.stay_booking(&stay, placement, None);
+            rooms
+                .entry(booking.room_number.clone())
+                .or_default()
+                .push(booked_profile(&booking));
+            plan.checkins.push(booking);
+        }
+
+        let targets = plan
+            .updates
+            .iter()
+            .map(|(_, booking)| booking)
+            .chain(&plan.checkins);
+        for booking in targets {
+            let room = &booking.room_number;
+            plan.occupants.entry(room.clone()).or_insert_with(|| {
+                planned_against
+                    .iter()
+                    .filter(|g| &g.room_number == room)
+                    .cloned()
+                    .collect()
+            });
+        }
+
+        Ok(plan)
+    }
+
+    fn stay_booking(&self, stay: &Stay, placement: Placement, name: Option<String>) -> Booking {
+        let (check_attributes, reply_attributes) =
+            self.credential_attributes(&placement.service, placement.expires);
+        Booking {
+            room_number: stay.room.clone(),
+            username: placement.username,
+            password: placement.password,
+            name: stay.name.clone().or(name),
+            folio_number: stay.folio.clone(),
+            checkin_date: stay.checkin,
+            checkout_date: stay.checkout,
+            gtype: stay.gtype.clone(),
+            service: Some(placement.service),
+            check_attributes,
+            reply_attributes,
+            recorded_at: self.settings.dates.now(),
+        }
+    }
+
+    /// Append the request and its outcome to the audit trail. A failure here
+    /// is logged only; it must not change the answer sent to the PMS.
+    async fn record_audit(
+        &self,
+        query: PmsQueryParams,
+        fingerprint: &str,
+        result: &Result<PmsResponse, ErrorResponse>,
+        replayed: bool,
+    ) {
+        // The log keeps internal error text; the audit trail, which
+        // `/vhp/history` serves, only what the caller was told.
+        let (outcome, code, message) = match result {
+            Ok(resp) => {
+                let outcome = if replayed { "replay" } else { "success" };
+                tracing::info!(mode = %query.mode, outcome, "{}", resp.message);
+                (outcome, None, resp.message.clone())
+            }
+            Err(err) => {
+                tracing::warn!(mode = %query.mode, outcome = "error", code = err.code(), "{}", err.message());
+                (
+                    "error",
+                    Some(err.code().to_string()),
+                    err.public_message().to_string(),
+                )
+            }
+        };
+        METRICS.record_request(&query.mode, code.as_deref().unwrap_or(outcome));
+
+        let entry = AuditEntry {
+            id: None,
+            mode: query.mode.clone(),
+            room_number: query.room.clone(),
+            old_room: query.oldroom.clone(),
+            folio_number: query.rsvno.clone(),
+            params: serde_json::to_string(&query).unwrap_or_default(),
+            outcome: outcome.into(),
+            code,
+            message,
+            idempotency_key: query.idempotency_key.clone(),
+            fingerprint: Some(fingerprint.to_string()),
+            response: result
+                .as_ref()
+                .ok()
+                .and_then(|resp| serde_json::to_string(resp).ok()),
+            created_at: self.settings.dates.now(),
+        };
+
+        if let Err(err) = self.repo.record_audit(&entry).await {
+            tracing::error!(
+                "failed to write audit entry for room {:?}: {}",
+                entry.room_number,
+                err
+            );
+        }
+    }
+
+    pub async fn is_room_active(&self, room: &str) -> Result<bool, ErrorResponse> {
+        Ok(self.repo.is_room_active(room).await?)
+    }
+
+    /// Pick the hotel service for a guest type: the mapped service, then the
+    /// configured default, then the first active hotel service.
+    async fn resolve_service(&self, gtype: Option<&str>) -> Result<HotelService, ErrorResponse> {
+        let services = self.repo.get_cron_hotel_service().await?;
+        let find = |name: &str| services.iter().find(|s| s.name.eq_ignore_ascii_case(name));
+
+        let mapped = gtype.and_then(|g| self.settings.guest_types.service_for(g));
+        if let Some(name) = mapped.filter(|name| find(name).is_none()) {
+            tracing::warn!(
+                "service {} for gtype {:?} is not an active hotel service",
+                name,
+                gtype
+            );
+        }
+
+        let default = self.settings.guest_types.default_service.as_deref();
+        mapped
+            .and_then(find)
+            .or_else(|| default.and_then(find))
+            .or_else(|| services.first())
+            .cloned()
+            .ok_or_else(|| ErrorResponse::NotConfigured("No active hotel service found".into()))
+    }
+
+    /// The service a guest is on; resolved again when it is no longer an
+    /// active hotel service.
+    async fn current_service(&self, guest: &GuestProfile) -> Result<HotelService, ErrorResponse> {
+        let services = self.repo.get_cron_hotel_service().await?;
+        match services.into_iter().find(|s| s.id == guest.service_id) {
+            Some(service) => Ok(service),
+            None => {
+                tracing::warn!(
+                    "service {} of room {} is not an active hotel service",
+                    guest.service_id,
+                    guest.room_number
+                );
+                self.resolve_service(None).await
+            }
+        }
+    }
+
+    /// Where a guest arriving in `room` next to `guests` logs in: the room
+    /// number for the first guest, the room's credential when shared, or
+    /// the next free `{room}-{n}` username; any property realm appended.
+    async fn place_guest(
+        &self,
+        room: &str,
+        guests: &[GuestProfile],
+        shared: bool,
+        password: String,
+        service: HotelService,
+        checkout: NaiveDateTime,
+    ) -> Result<Placement, ErrorResponse> {
+        if guests.is_empty() {
+            return Ok(Placement {
+                username: self.settings.username(room),
+                password,
+                service,
+                expires: checkout,
+            });
+        }
+
+        if !shared {
+            let username = (2..)
+                .map(|n| self.settings.username(&format!("{}-{}", room, n)))
+                .find(|u| guests.iter().all(|g| &g.username != u))
+                .expect("unbounded username range");
+            return Ok(Placement {
+                username,
+                password,
+                service,
+                expires: checkout,
+            });
+        }
+
+        let room_username = self.settings.username(room);
+        let primary = guests
+            .iter()
+            .find(|g| g.username == room_username)
+            .unwrap_or(&guests[0]);
+        Ok(Placement {
+            username: primary.username.clone(),
+            password: primary.password.clone(),
+            service: self.current_service(primary).await?,
+            expires: latest_checkout(guests, &primary.username, checkout),
+        })
+    }
+
+    /// A guest may only join an occupied room with a folio of their own.
+    fn check_joining(
+        &self,
+        room: &str,
+        guests: &[GuestProfile],
+        rsvno: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        match rsvno {
+            None => Err(ErrorResponse::invalid_field(
+                "rsvno",
+                format!("rsvno is required to add a guest to room {}", room),
+            )),
+            Some(folio)
+                if guests
+                    .iter()
+                    .any(|g| g.folio_number.as_deref() == Some(folio)) =>
+            {
+                Err(ErrorResponse::Conflict(format!(
+                    "guest {} is already in room {}",
+                    folio, room
+                )))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn shares_credential(&self, credential: Option<&str>) -> Result<bool, ErrorResponse> {
+        match credential.map(str::trim).filter(|c| !c.is_empty()) {
+            None => Ok(self.settings.rooms.shared_credential),
+            Some(c) if c.eq_ignore_ascii_case("shared") => Ok(true),
+            Some(c) if c.eq_ignore_ascii_case("own") => Ok(false),
+            Some(c) => Err(ErrorResponse::invalid_field(
+                "credential",
+                format!("invalid credential {}", c),
+            )),
+        }
+    }
+
+    fn checkin_date(&self, cidate: &str) -> Result<NaiveDateTime, ErrorResponse> {
+        self.settings.dates.parse_checkin(cidate)
+    }
+
+    fn checkout_date(
+        &self,
+        codate: &str,
+        cotime: Option<&str>,
+    ) -> Result<NaiveDateTime, ErrorResponse> {
+        self.settings.dates.parse_checkout(codate, cotime)
+    }
+
+    fn password_storage(&self) -> PasswordStorage {
+        self.settings.radius.schema.password_storage
+    }
+
+    /// `password` in the configured storage form.
+    fn stored_password(&self, password: &str) -> String {
+        hash_password(self.password_storage(), password)
+    }
+
+    /// radcheck / radreply rows for a credential valid until `expires`.
+    fn credential_attributes(
+        &self,
+        service: &HotelService,
+        expires: NaiveDateTime,
+    ) -> (Vec<RadiusAttribute>, Vec<RadiusAttribute>) {
+        let mut check = self.settings.radius.check_attributes(expires);
+        if let Some(limit) = service.simultaneous_use {
+            check.push(RadiusAttribute::new(
+                "Simultaneous-Use",
+                ":=",
+                limit.to_string(),
+            ));
+        }
+        let reply = self
+            .settings
+            .radius
+            .reply_attributes(self.settings.dates.now(), expires);
+        (check, reply)
+    }
+
+    /// Send a Disconnect-Request for every open radacct session of `username`.
+    /// Failures are logged and reported, never propagated: the database
+    /// change is already committed.
+    async fn disconnect_sessions(&self, username: &str) -> Option<Vec<SessionDisconnect>> {
+        let nas = self.nas.as_ref()?;
+
+        let sessions = match self.repo.open_sessions(username).await {
+            Ok(s) => s,
+            Err(err) => {
+                tracing::error!("failed to load open sessions for {}: {}", username, err);
+                return Some(Vec::new());
             }
+        };
+
+        let mut results = Vec::with_capacity(sessions.len());
+        for session in sessions {
+            let result = match nas.disconnect(&session).await {
+                Ok(outcome) => {
+                    tracing::info!(
+                        "disconnect {} session {} on {}: {:?}",
+                        username,
+                        session.acct_session_id,
+                        session.nas_ip_address,
+                        outcome
+                    );
+                    outcome.as_str().to_string()
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "disconnect {} session {} on {} failed: {}",
+                        username,
+                        session.acct_session_id,
+                        session.nas_ip_address,
+                        err
+                    );
+                    "error".to_string()
+                }
+            };
+            results.push(SessionDisconnect {
+                nas: session.nas_ip_address,
+                session_id: session.acct_session_id,
+                result,
+            });
         }
+        Some(results)
     }
 
     async fn handle_checkin(&self, query: PmsQueryParams) -> Result<PmsResponse, ErrorResponse> {
         let room = match query.room.clone() {
             Some(r) if !r.is_empty() => r,
-            _ => return Err(ErrorResponse::Validation("room is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("room", "room is required")),
         };
 
         let pass_raw = match query.pass.clone() {
             Some(p) if !p.is_empty() => p,
-            _ => return Err(ErrorResponse::Validation("pass is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("pass", "pass is required")),
         };
 
         let cidate_str = match &query.cidate {
             Some(s) if !s.trim().is_empty() => s.trim(),
-            _ => return Err(ErrorResponse::Validation("cidate is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("cidate", "cidate is required")),
         };
 
         let codate_str = match &query.codate {
             Some(s) if !s.trim().is_empty() => s.trim(),
-            _ => return Err(ErrorResponse::Validation("codate is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("codate", "codate is required")),
         };
 
-        if self.repo.is_room_active(&room).await? {
-            return Err(ErrorResponse::Validation(format!(
-                "room {} is in use",
-                room
-            )));
-        }
+        let shared = self.shares_credential(query.credential.as_deref())?;
 
-        let checkin_datetime = parse_checkin_datetime(cidate_str)?;
-        let checkout_datetime = parse_checkout_datetime(codate_str, query.cotime.as_deref())?;
+        let checkin_datetime = self.checkin_date(cidate_str)?;
+        let checkout_datetime = self.checkout_date(codate_str, query.cotime.as_deref())?;
 
-        let pass = clean_password(&pass_raw);
+        let pass = self.stored_password(&clean_password(&pass_raw));
 
         let formatted_name = get_formatted_name(&query.name, &query.pass);
+        let service = self.resolve_service(query.gtype.as_deref()).await?;
 
-        let booking = Booking {
-            room_number: room,
-            password: pass,
-            name: Some(formatted_name.clone()),
-            checkin_date: checkin_datetime,
-            checkout_date: checkout_datetime,
-            folio_number: query.rsvno.clone(),
-            gtype: query.gtype,
-        };
+        // Another guest may check in between reading the room and locking
+        // it; the booking is then planned again next to that guest.
+        let mut attempt = 1;
+        let booking = loop {
+            let guests = self.repo.room_guests(&room).await?;
+            if !guests.is_empty() {
+                if guests.len() >= self.settings.rooms.max_guests {
+                    return Err(ErrorResponse::Conflict(format!("room {} is in use", room)));
+                }
+                self.check_joining(&room, &guests, non_empty(&query.rsvno))?;
+            }
+            let placement = self
+                .place_guest(
+                    &room,
+                    &guests,
+                    shared,
+                    pass.clone(),
+                    service.clone(),
+                    checkout_datetime,
+                )
+                .await?;
+            let (check_attributes, reply_attributes) =
+                self.credential_attributes(&placement.service, placement.expires);
 
-        self.repo.checkin_repo(&booking).await?;
+            let booking = Booking {
+                room_number: room.clone(),
+                username: placement.username,
+                password: placement.password,
+                name: Some(formatted_name.clone()),
+                checkin_date: checkin_datetime,
+                checkout_date: checkout_datetime,
+                folio_number: query.rsvno.clone(),
+                gtype: query.gtype.clone(),
+                service: Some(placement.service),
+                check_attributes,
+                reply_attributes,
+                recorded_at: self.settings.dates.now(),
+            };
 
-        Ok(PmsResponse {
-            status: "success".into(),
-            message: format!("room {} successfully checkin", booking.room_number),
-        })
+            match self.repo.checkin_repo(&booking, &guests).await {
+                Err(err) if attempt < CHECKIN_ATTEMPTS && is_stale(&err) => {
+                    tracing::debug!("room {} changed during checkin, planning again", room);
+                    attempt += 1;
+                }
+                result => {
+                    result?;
+                    break booking;
+                }
+            }
+        };
+
+        let msg = if booking.username == self.settings.username(&booking.room_number) {
+            format!("room {} successfully checkin", booking.room_number)
+        } else {
+            format!(
+                "room {} successfully checkin as {}",
+                booking.room_number, booking.username
+            )
+        };
+        Ok(PmsResponse::success(msg))
     }
 
     async fn handle_checkout(&self, query: PmsQueryParams) -> Result<PmsResponse, ErrorResponse> {
         let room = match query.room.clone() {
             Some(r) if !r.is_empty() => r,
-            _ => return Err(ErrorResponse::Validation("room is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("room", "room is required")),
         };
 
-        if !self.repo.is_room_active(&room).await? {
+        let guests = self.repo.room_guests(&room).await?;
+        if guests.is_empty() {
             return Err(ErrorResponse::NotFound(format!(
                 "room {} not found for checkout",
                 room
             )));
         }
 
+        // A folio must name a guest in the room; the last one out empties it.
+        let leaving = match non_empty(&query.rsvno) {
+            Some(folio) => {
+                let guest = find_guest(&guests, folio, &room)?;
+                (guests.len() > 1).then_some(guest)
+            }
+            None => None,
+        };
+
+        self.checkout_guests(&room, leaving).await
+    }
+
+    /// Remove one guest, or the whole room when `leaving` is `None`, and
+    /// disconnect every credential that no longer exists.
+    async fn checkout_guests(
+        &self,
+        room: &str,
+        leaving: Option<&GuestProfile>,
+    ) -> Result<PmsResponse, ErrorResponse> {
+        let now = self.settings.dates.now();
         let booking = Booking {
-            room_number: room,
+            room_number: room.to_string(),
+            username: leaving.map_or_else(|| self.settings.username(room), |g| g.username.clone()),
             password: "".into(),
             name: None,
-            checkin_date: Local::now().naive_local(),
-            checkout_date: Local::now().naive_local(),
-            folio_number: None,
+            checkin_date: now,
+            checkout_date: now,
+            folio_number: leaving.map(|g| g.folio_number.clone().unwrap_or_default()),
             gtype: None,
+            service: None,
+            check_attributes: Vec::new(),
+            reply_attributes: Vec::new(),
+            recorded_at: now,
         };
 
-        self.repo.checkout_repo(&booking).await?;
+        let released = self.repo.checkout_repo(&booking).await?;
 
-        Ok(PmsResponse {
-            status: "success".into(),
-            message: format!("room {} successfully checkout", booking.room_number),
-        })
+        let mut disconnects: Option<Vec<SessionDisconnect>> = None;
+        for username in &released {
+            if let Some(results) = self.disconnect_sessions(username).await {
+                disconnects.get_or_insert_with(Vec::new).extend(results);
+            }
+        }
+
+        let msg = match leaving {
+            Some(guest) => format!(
+                "guest {} successfully checkout from room {}",
+                guest.folio_number.as_deref().unwrap_or(&guest.username),
+                room
+            ),
+            None => format!("room {} successfully checkout", room),
+        };
+        Ok(PmsResponse::success(msg).with_disconnects(disconnects))
     }
 
     async fn handle_update(&self, query: PmsQueryParams) -> Result<PmsResponse, ErrorResponse> {
+        self.update_guest(query, true).await
+    }
+
+    /// Rewrite a guest's stay. Without `pass_required` a request with no
+    /// pass keeps the current password.
+    async fn update_guest(
+        &self,
+        query: PmsQueryParams,
+        pass_required: bool,
+    ) -> Result<PmsResponse, ErrorResponse> {
         let new_room = match query.room.clone() {
             Some(r) if !r.is_empty() => r,
-            _ => return Err(ErrorResponse::Validation("room is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("room", "room is required")),
         };
 
         let old_room_opt = query.oldroom.clone();
 
         let pass_raw = match query.pass.clone() {
-            Some(p) if !p.is_empty() => p,
-            _ => return Err(ErrorResponse::Validation("pass is required".into())),
+            Some(p) if !p.is_empty() => Some(p),
+            _ if !pass_required => None,
+            _ => return Err(ErrorResponse::invalid_field("pass", "pass is required")),
         };
 
         let cidate_str = match &query.cidate {
             Some(s) if !s.trim().is_empty() => s.trim(),
-            _ => return Err(ErrorResponse::Validation("cidate is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("cidate", "cidate is required")),
         };
 
         let codate_str = match &query.codate {
             Some(s) if !s.trim().is_empty() => s.trim(),
-            _ => return Err(ErrorResponse::Validation("codate is required".into())),
+            _ => return Err(ErrorResponse::invalid_field("codate", "codate is required")),
         };
 
         let old_room = old_room_opt.unwrap_or_else(|| new_room.clone());
         let is_change_room = old_room != new_room;
+        let rsvno = non_empty(&query.rsvno);
 
-        if !self.repo.is_room_active(&old_room).await? {
-            return Err(ErrorResponse::NotFound(format!(
-                "room {} not found for update",
-                old_room
-            )));
-        }
+        let guests = self.repo.room_guests(&old_room).await?;
+        let guest = match (guests.len(), rsvno) {
+            (0, _) => {
+                return Err(ErrorResponse::NotFound(format!(
+                    "room {} not found for update",
+                    old_room
+                )));
+            }
+            (_, Some(folio)) => find_guest(&guests, folio, &old_room)?,
+            (1, None) => &guests[0],
+            (_, None) => {
+                return Err(ErrorResponse::invalid_field(
+                    "rsvno",
+                    format!("rsvno is required to update a guest in room {}", old_room),
+                ));
+            }
+        };
 
-        if is_change_room && self.repo.is_room_active(&new_room).await? {
-            return Err(ErrorResponse::Validation(format!(
-                "target room {} is already in use",
-                new_room
-            )));
+        let target_guests = if is_change_room {
+            self.repo.room_guests(&new_room).await?
+        } else {
+            Vec::new()
+        };
+        if !target_guests.is_empty() {
+            if target_guests.len() >= self.settings.rooms.max_guests {
+                return Err(ErrorResponse::Conflict(format!(
+                    "target room {} is already in use",
+                    new_room
+                )));
+            }
+            self.check_joining(
+                &new_room,
+                &target_guests,
+                rsvno.or(guest.folio_number.as_deref()),
+            )?;
         }
 
-        let check_in_datetime = parse_checkin_datetime(cidate_str)?;
-        let checkout_datetime = parse_checkout_datetime(codate_str, query.cotime.as_deref())?;
+        let check_in_datetime = self.checkin_date(cidate_str)?;
+        let checkout_datetime = self.checkout_date(codate_str, query.cotime.as_deref())?;
 
-        let pass = clean_password(&pass_raw);
+        // An unchanged password keeps its stored form, salt included.
+        let pass = match pass_raw.as_deref().map(clean_password) {
+            Some(p) if !verify_password(self.password_storage(), &p, &guest.password) => {
+                self.stored_password(&p)
+            }
+            _ => guest.password.clone(),
+        };
         let formatted_name = get_formatted_name(&query.name, &query.pass);
 
+        // Only a gtype sent with the update may move the guest to another plan.
+        let service = match query.gtype.as_deref() {
+            Some(g) if !g.trim().is_empty() => self.resolve_service(Some(g)).await?,
+            _ => self.current_service(guest).await?,
+        };
+
+        let sharers = guests
+            .iter()
+            .filter(|g| g.username == guest.username && !std::ptr::eq(*g, guest))
+            .count();
+        let placement = if is_change_room {
+            let shared = self.shares_credential(query.credential.as_deref())?;
+            self.place_guest(
+                &new_room,
+                &target_guests,
+                shared,
+                pass,
+                service,
+                checkout_datetime,
+            )
+            .await?
+        } else if sharers > 0 {
+            // Others log in with this credential too: keep its password and
+            // let it last until the latest of their checkouts.
+            let others: Vec<GuestProfile> = guests
+                .iter()
+                .filter(|g| !std::ptr::eq(*g, guest))
+                .cloned()
+                .collect();
+            Placement {
+                username: guest.username.clone(),
+                password: guest.password.clone(),
+                service,
+                expires: latest_checkout(&others, &guest.username, checkout_datetime),
+            }
+        } else {
+            Placement {
+                username: guest.username.clone(),
+                password: pass,
+                service,
+                expires: checkout_datetime,
+            }
+        };
+        let (check_attributes, reply_attributes) =
+            self.credential_attributes(&placement.service, placement.expires);
+
         let booking = Booking {
             room_number: new_room.clone(),
-            password: pass,
+            username: placement.username,
+            password: placement.password,
             name: Some(formatted_name.clone()),
             checkin_date: check_in_datetime,
             checkout_date: checkout_datetime,
-            folio_number: query.rsvno.clone(),
+            folio_number: rsvno.map(str::to_string).or(guest.folio_number.clone()),
             gtype: query.gtype.clone(),
+            service: Some(placement.service),
+            check_attributes,
+            reply_attributes,
+            recorded_at: self.settings.dates.now(),
         };
 
-        self.repo.update_repo(&old_room, &booking).await?;
+        let occupants = if is_change_room {
+            &target_guests
+        } else {
+            &guests
+        };
+        self.repo.update_repo(guest, &booking, occupants).await?;
 
-        let msg = if is_change_room {
-            format!("room {} successfully updated to {}", old_room, new_room)
+        // The old credential no longer exists after a move, unless shared.
+        let (msg, disconnects) = if is_change_room {
+            (
+                format!("room {} successfully updated to {}", old_room, new_room),
+                if sharers == 0 {
+                    self.disconnect_sessions(&guest.username).await
+                } else {
+                    None
+                },
+            )
         } else {
-            format!("room {} successfully updated", new_room)
+            (format!("room {} successfully updated", new_room), None)
         };
 
-        Ok(PmsResponse {
-            status: "success".into(),
-            message: msg,
+        Ok(PmsResponse::success(msg).with_disconnects(disconnects))
+    }
+}
+
+/// Username, password and plan a guest's stay is written under.
+struct Placement {
+    username: String,
+    password: String,
+    service: HotelService,
+    /// When the credential expires; the latest checkout of everyone sharing it.
+    expires: NaiveDateTime,
+}
+
+/// A snapshot guest with its dates parsed.
+struct Stay {
+    room: String,
+    /// Formatted as on checkin; `None` when the snapshot gives neither name
+    /// nor pass.
+    name: Option<String>,
+    pass: Option<String>,
+    folio: Option<String>,
+    checkin: NaiveDateTime,
+    checkout: NaiveDateTime,
+    gtype: Option<String>,
+}
+
+impl Stay {
+    fn parse(guest: &SnapshotGuest, dates: &DateSettings) -> Result<Self> {
+        let room = guest.room.trim();
+        if room.is_empty() {
+            anyhow::bail!("room is required");
+        }
+
+        let named = non_empty(&guest.name).is_some() || non_empty(&guest.pass).is_some();
+        Ok(Self {
+            room: room.to_string(),
+            name: named.then(|| get_formatted_name(&guest.name, &guest.pass)),
+            pass: non_empty(&guest.pass).map(str::to_string),
+            folio: non_empty(&guest.rsvno).map(str::to_string),
+            checkin: dates.parse_checkin(&guest.cidate)?,
+            checkout: dates.parse_checkout(&guest.codate, guest.cotime.as_deref())?,
+            gtype: non_empty(&guest.gtype).map(str::to_string),
         })
     }
+
+    fn key(&self) -> String {
+        match &self.folio {
+            Some(folio) => format!("guest {}", folio),
+            None => format!("room {}", self.room),
+        }
+    }
+}
+
+/// How `guest` and its credential differ from the snapshot's `stay`.
+fn stay_differences(
+    stay: &Stay,
+    guest: &GuestProfile,
+    service: Option<&HotelService>,
+    services: &[HotelService],
+    credentials: &HashMap<String, StoredCredential>,
+    storage: PasswordStorage,
+) -> Vec<String> {
+    let mut details = Vec::new();
+    if stay.room != guest.room_number {
+        details.push(format!("room {} -> {}", guest.room_number, stay.room));
+    }
+    if let Some(name) = stay
+        .name
+        .as_ref()
+        .filter(|n| guest.name.as_ref() != Some(*n))
+    {
+        details.push(format!(
+            "name {} -> {}",
+            guest.name.as_deref().unwrap_or("-"),
+            name
+        ));
+    }
+    if stay.checkin.date() != guest.checkin_date.date() {
+        details.push(format!(
+            "checkin {} -> {}",
+            guest.checkin_date.date(),
+            stay.checkin.date()
+        ));
+    }
+    if stay.checkout != guest.checkout_date {
+        details.push(format!(
+            "checkout {} -> {}",
+            guest.checkout_date, stay.checkout
+        ));
+    }
+
+    let current = services.iter().find(|s| s.id == guest.service_id);
+    if let Some(service) = service.filter(|s| s.id != guest.service_id) {
+        details.push(format!(
+            "service {} -> {}",
+            current.map_or("-", |s| s.name.as_str()),
+            service.name
+        ));
+    }
+
+    match credentials.get(&guest.username) {
+        None => details.push(format!("credential {} missing", guest.username)),
+        Some(credential) => {
+            match credential.password_attribute.as_deref() {
+                Some(attribute) if attribute != storage.attribute() => details.push(format!(
+                    "password of {} stored as {}",
+                    guest.username, attribute
+                )),
+                _ if credential.password.as_deref() != Some(guest.password.as_str()) => {
+                    details.push(format!("password of {} out of sync", guest.username))
+                }
+                _ => {}
+            }
+            if let Some(current) = current.filter(|s| s.name != credential.groupname) {
+                details.push(format!(
+                    "group {} -> {}",
+                    credential.groupname, current.name
+                ));
+            }
+        }
+    }
+    details
+}
+
+/// The guest row `booking` will leave in `hotel_rooms`.
+fn booked_profile(booking: &Booking) -> GuestProfile {
+    GuestProfile {
+        room_number: booking.room_number.clone(),
+        username: booking.username.clone(),
+        password: booking.password.clone(),
+        name: booking.name.clone(),
+        folio_number: booking.folio_number.clone(),
+        service_id: booking.service.as_ref().map_or(0, |s| s.id),
+        checkin_date: booking.checkin_date,
+        checkout_date: booking.checkout_date,
+    }
+}
+
+fn is_stale(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<StorageError>() == Some(&StorageError::Stale)
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn find_guest<'a>(
+    guests: &'a [GuestProfile],
+    folio: &str,
+    room: &str,
+) -> Result<&'a GuestProfile, ErrorResponse> {
+    guests
+        .iter()
+        .find(|g| g.folio_number.as_deref() == Some(folio))
+        .ok_or_else(|| {
+            ErrorResponse::NotFound(format!("guest {} not found in room {}", folio, room))
+        })
+}
+
+fn latest_checkout(
+    guests: &[GuestProfile],
+    username: &str,
+    checkout: NaiveDateTime,
+) -> NaiveDateTime {
+    guests
+        .iter()
+        .filter(|g| g.username == username)
+        .map(|g| g.checkout_date)
+        .fold(checkout, NaiveDateTime::max)
+}
+
+/// The guest a staff action targets: the only one, or the one with `rsvno`.
+fn admin_guest<'a>(
+    guests: &'a [GuestProfile],
+    room: &str,
+    rsvno: Option<&str>,
+) -> Result<&'a GuestProfile, ErrorResponse> {
+    match (guests.len(), rsvno) {
+        (0, _) => Err(ErrorResponse::NotFound(format!("room {} not found", room))),
+        (1, None) => Ok(&guests[0]),
+        (_, Some(folio)) => find_guest(guests, folio, room),
+        (_, None) => Err(ErrorResponse::invalid_field(
+            "rsvno",
+            format!("rsvno is required for room {} with several guests", room),
+        )),
+    }
+}
+
+/// An `update` request restating `guest`'s current stay unchanged; without
+/// a pass it keeps the current password.
+fn stay_update(guest: &GuestProfile) -> PmsQueryParams {
+    PmsQueryParams {
+        mode: "update".into(),
+        room: Some(guest.room_number.clone()),
+        name: guest.name.clone(),
+        rsvno: guest.folio_number.clone(),
+        cidate: Some(guest.checkin_date.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        codate: Some(guest.checkout_date.format("%Y-%m-%d").to_string()),
+        cotime: Some(guest.checkout_date.format("%H:%M:%S").to_string()),
+        ..Default::default()
+    }
+}
+
+/// Whether the words of `last_name`, cleaned like a pass, end the guest's
+/// name, so `Cruz` and `de la Cruz` both match `Maria de la Cruz`.
+fn surname_matches(name: Option<&str>, last_name: &str) -> bool {
+    let name = clean_password(name.unwrap_or_default());
+    let name: Vec<&str> = name.split_whitespace().collect();
+    let last: Vec<&str> = last_name.split_whitespace().collect();
+    !last.is_empty() && name.ends_with(&last)
+}
+
+/// Groups guests, already ordered by room, into one view per room.
+fn room_views(guests: Vec<GuestProfile>, services: &[HotelService]) -> Vec<RoomView> {
+    let mut rooms: Vec<RoomView> = Vec::new();
+    for guest in guests {
+        let view = GuestView {
+            username: guest.username,
+            name: guest.name,
+            folio_number: guest.folio_number,
+            service: services
+                .iter()
+                .find(|s| s.id == guest.service_id)
+                .map(|s| s.name.clone()),
+            checkin_date: guest.checkin_date,
+            checkout_date: guest.checkout_date,
+        };
+        match rooms.last_mut() {
+            Some(room) if room.room == guest.room_number => room.guests.push(view),
+            _ => rooms.push(RoomView {
+                room: guest.room_number,
+                guests: vec![view],
+            }),
+        }
+    }
+    rooms
+}
+
+/// HMAC-SHA256 keyed with `secret` over the full request (password
+/// included, idempotency key excluded), hex encoded.
+fn request_fingerprint(secret: &[u8], query: &PmsQueryParams) -> String {
+    let payload = PmsQueryParams {
+        idempotency_key: None,
+        ..query.clone()
+    };
+    let json = serde_json::to_string(&payload).unwrap_or_default();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(json.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{
+        PasswordStorage, Property, RadiusAttribute, RadiusSchema, RadiusSession,
+    };
+    use crate::domain::nas::DisconnectOutcome;
+    use crate::infrastructure::repositories::memory::{
+        InMemoryBookingRepository, RadAcctRow, ServiceRow,
+    };
+    use chrono::{Local, NaiveDate, NaiveTime};
+
+    fn setup() -> (
+        Arc<InMemoryBookingRepository>,
+        BookingService<InMemoryBookingRepository>,
+    ) {
+        setup_with(ServiceSettings::default())
+    }
+
+    fn setup_with(
+        settings: ServiceSettings,
+    ) -> (
+        Arc<InMemoryBookingRepository>,
+        BookingService<InMemoryBookingRepository>,
+    ) {
+        let repo = Arc::new(InMemoryBookingRepository::with_hotel_service(
+            7,
+            "Hotel Basic",
+        ));
+        (repo.clone(), BookingService::new(repo, Arc::new(settings)))
+    }
+
+    fn reply_settings() -> ServiceSettings {
+        let mut settings = ServiceSettings::default();
+        settings.radius.reply_attributes = vec![
+            RadiusAttribute::new("Session-Timeout", ":=", "{stay_seconds}"),
+            RadiusAttribute::new("Mikrotik-Rate-Limit", ":=", "2M/4M"),
+        ];
+        settings
+    }
+
+    fn query(mode: &str) -> PmsQueryParams {
+        PmsQueryParams {
+            mode: mode.into(),
+            room: Some("101".into()),
+            oldroom: None,
+            name: Some("john SMITH".into()),
+            pass: Some("Smith!".into()),
+            rsvno: Some("R-1".into()),
+            cidate: Some("20/11/2025".into()),
+            codate: Some("22/11/2025".into()),
+            cotime: None,
+            gtype: Some("VIP".into()),
+            credential: None,
+            idempotency_key: None,
+            property: None,
+        }
+    }
+
+    async fn checkin(service: &BookingService<InMemoryBookingRepository>, room: &str) {
+        let mut q = query("checkin");
+        q.room = Some(room.into());
+        service.process(q).await.expect("checkin failed");
+    }
+
+    fn assert_validation<T: std::fmt::Debug>(result: Result<T, ErrorResponse>, expected: &str) {
+        match result {
+            Err(ErrorResponse::Validation(msg)) => assert_eq!(msg, expected),
+            other => panic!("expected validation error {:?}, got {:?}", expected, other),
+        }
+    }
+
+    fn assert_invalid_field<T: std::fmt::Debug>(
+        field: &str,
+        result: Result<T, ErrorResponse>,
+        expected: &str,
+    ) {
+        match result {
+            Err(ErrorResponse::InvalidField { field: f, message }) => {
+                assert_eq!((f, message.as_str()), (field, expected))
+            }
+            other => panic!("expected invalid {} {:?}, got {:?}", field, expected, other),
+        }
+    }
+
+    fn assert_conflict<T: std::fmt::Debug>(result: Result<T, ErrorResponse>, expected: &str) {
+        match result {
+            Err(ErrorResponse::Conflict(msg)) => assert_eq!(msg, expected),
+            other => panic!("expected conflict {:?}, got {:?}", expected, other),
+        }
+    }
+
+    fn assert_not_found<T: std::fmt::Debug>(result: Result<T, ErrorResponse>, expected: &str) {
+        match result {
+            Err(ErrorResponse::NotFound(msg)) => assert_eq!(msg, expected),
+            other => panic!("expected not found {:?}, got {:?}", expected, other),
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_mode_is_rejected() {
+        let (_, service) = setup();
+        assert_invalid_field(
+            "mode",
+            service.process(query("noshow")).await,
+            "invalid mode noshow",
+        );
+    }
+
+    #[tokio::test]
+    async fn checkin_writes_all_tables() {
+        let (repo, service) = setup();
+
+        let resp = service.process(query("checkin")).await.unwrap();
+        assert_eq!(resp.status, "success");
+        assert_eq!(resp.message, "room 101 successfully checkin");
+
+        let tables = repo.tables();
+        let room = &tables.hotel_rooms[0];
+        assert_eq!(room.room_number, "101");
+        assert_eq!(room.password, "smith");
+        assert_eq!(room.name, "John Smith");
+        assert_eq!(room.service_id, 7);
+        assert_eq!(room.folio_number, "R-1");
+        assert_eq!(
+            room.checkin_date.date(),
+            NaiveDate::from_ymd_opt(2025, 11, 20).unwrap()
+        );
+        assert_eq!(
+            room.checkout_date,
+            NaiveDate::from_ymd_opt(2025, 11, 22)
+                .unwrap()
+                .and_hms_opt(13, 0, 0)
+                .unwrap()
+        );
+
+        assert_eq!(tables.radcheck.len(), 2);
+        assert_eq!(tables.radcheck[0].username, "101");
+        assert_eq!(tables.radcheck[0].attribute, "Cleartext-Password");
+        assert_eq!(tables.radcheck[0].value, "smith");
+        assert_eq!(tables.radcheck[1].attribute, "Expiration");
+        assert_eq!(tables.radcheck[1].value, "22 Nov 2025 13:00:00");
+        assert!(tables.radreply.is_empty());
+
+        assert_eq!(tables.radusergroup.len(), 1);
+        assert_eq!(tables.radusergroup[0].groupname, "Hotel Basic");
+        assert_eq!(tables.radusergroup[0].user_type, "hotel-room");
+    }
+
+    #[tokio::test]
+    async fn checkin_uses_pass_as_name_fallback_and_cotime() {
+        let (repo, service) = setup();
+        let mut q = query("checkin");
+        q.name = None;
+        q.pass = Some("  doe  ".into());
+        q.cotime = Some("10:30:00".into());
+        q.cidate = Some("20/11/2025 14:00:00".into());
+
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        let room = &tables.hotel_rooms[0];
+        assert_eq!(room.name, "Doe");
+        assert_eq!(room.password, "doe");
+        assert_eq!(
+            room.checkin_date.time(),
+            NaiveTime::from_hms_opt(14, 0, 0).unwrap()
+        );
+        assert_eq!(
+            room.checkout_date.time(),
+            NaiveTime::from_hms_opt(10, 30, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn checkin_requires_fields() {
+        let (repo, service) = setup();
+
+        let mut q = query("checkin");
+        q.room = Some("".into());
+        assert_invalid_field("room", service.process(q).await, "room is required");
+
+        let mut q = query("checkin");
+        q.pass = None;
+        assert_invalid_field("pass", service.process(q).await, "pass is required");
+
+        let mut q = query("checkin");
+        q.cidate = Some("   ".into());
+        assert_invalid_field("cidate", service.process(q).await, "cidate is required");
+
+        let mut q = query("checkin");
+        q.codate = None;
+        assert_invalid_field("codate", service.process(q).await, "codate is required");
+
+        assert!(repo.tables().hotel_rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn checkin_rejects_room_in_use() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("checkin");
+        q.rsvno = Some("R-2".into());
+        assert_conflict(service.process(q).await, "room 101 is in use");
+        assert_eq!(repo.tables().radcheck.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn checkin_rejects_bad_dates() {
+        let (repo, service) = setup();
+
+        let mut q = query("checkin");
+        q.cidate = Some("20.11.2025".into());
+        assert_invalid_field(
+            "cidate",
+            service.process(q).await,
+            "invalid checkin date 20.11.2025",
+        );
+
+        let mut q = query("checkin");
+        q.codate = Some("31/02/2025".into());
+        assert_invalid_field(
+            "codate",
+            service.process(q).await,
+            "invalid checkout date 31/02/2025",
+        );
+
+        let mut q = query("checkin");
+        q.cotime = Some("1pm".into());
+        assert_invalid_field(
+            "cotime",
+            service.process(q).await,
+            "invalid checkout time 1pm",
+        );
+
+        assert!(repo.tables().hotel_rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn checkin_uses_configured_date_settings() {
+        let mut settings = ServiceSettings::default();
+        settings.dates.formats = vec!["%m/%d/%Y".into()];
+        settings.dates.default_checkout = NaiveTime::from_hms_opt(11, 0, 0).unwrap();
+        let (repo, service) = setup_with(settings);
+
+        let mut q = query("checkin");
+        q.cidate = Some("2025-11-20T14:30:00".into());
+        q.codate = Some("11/22/2025".into());
+        service.process(q).await.unwrap();
+
+        let room = repo.tables().hotel_rooms[0].clone();
+        assert_eq!(
+            room.checkin_date,
+            NaiveDate::from_ymd_opt(2025, 11, 20)
+                .unwrap()
+                .and_hms_opt(14, 30, 0)
+                .unwrap()
+        );
+        assert_eq!(
+            room.checkout_date,
+            NaiveDate::from_ymd_opt(2025, 11, 22)
+                .unwrap()
+                .and_hms_opt(11, 0, 0)
+                .unwrap()
+        );
+
+        let mut q = query("checkin");
+        q.room = Some("102".into());
+        q.rsvno = Some("R-2".into());
+        assert_invalid_field(
+            "cidate",
+            service.process(q).await,
+            "invalid checkin date 20/11/2025",
+        );
+    }
+
+    #[tokio::test]
+    async fn checkin_without_hotel_service_fails() {
+        let repo = Arc::new(InMemoryBookingRepository::new());
+        let service = BookingService::new(repo.clone(), Arc::new(ServiceSettings::default()));
+
+        assert!(matches!(
+            service.process(query("checkin")).await,
+            Err(ErrorResponse::NotConfigured(msg)) if msg == "No active hotel service found"
+        ));
+        assert!(repo.tables().hotel_rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn checkin_follows_configured_radius_schema() {
+        let repo = Arc::new(InMemoryBookingRepository::new().with_schema(RadiusSchema {
+            user_type: "guest".into(),
+            service_type: "wifi".into(),
+            ..RadiusSchema::default()
+        }));
+        repo.tables().services.push(ServiceRow {
+            id: 3,
+            service_name: "Guest WiFi".into(),
+            cron: true,
+            cron_type: "wifi".into(),
+            simultaneous_use: None,
+        });
+        repo.add_hotel_service(7, "Hotel Basic");
+        let service = BookingService::new(repo.clone(), Arc::new(ServiceSettings::default()));
+
+        checkin(&service, "101").await;
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms[0].service_id, 3);
+        assert_eq!(tables.radusergroup[0].groupname, "Guest WiFi");
+        assert_eq!(tables.radusergroup[0].user_type, "guest");
+    }
+
+    #[tokio::test]
+    async fn checkout_removes_all_rows() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+        checkin(&service, "102").await;
+
+        let resp = service.process(query("checkout")).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkout");
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.hotel_rooms[0].room_number, "102");
+        assert!(tables.radcheck.iter().all(|r| r.username == "102"));
+        assert!(tables.radusergroup.iter().all(|r| r.username == "102"));
+    }
+
+    #[tokio::test]
+    async fn checkout_requires_room() {
+        let (_, service) = setup();
+        let mut q = query("checkout");
+        q.room = None;
+        assert_invalid_field("room", service.process(q).await, "room is required");
+    }
+
+    #[tokio::test]
+    async fn checkout_unknown_room_is_not_found() {
+        let (_, service) = setup();
+        assert_not_found(
+            service.process(query("checkout")).await,
+            "room 101 not found for checkout",
+        );
+    }
+
+    #[tokio::test]
+    async fn checkout_of_another_folio_keeps_the_guest() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("checkout");
+        q.rsvno = Some("R-2".into());
+        assert_not_found(service.process(q).await, "guest R-2 not found in room 101");
+        assert_eq!(repo.tables().hotel_rooms.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_of_another_folio_keeps_the_guest() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        q.rsvno = Some("R-2".into());
+        assert_not_found(service.process(q).await, "guest R-2 not found in room 101");
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.hotel_rooms[0].room_number, "101");
+    }
+
+    #[tokio::test]
+    async fn update_same_room_refreshes_credentials_and_dates() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.pass = Some("Jones".into());
+        q.name = Some("mary jones".into());
+        q.codate = Some("25/11/2025".into());
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully updated");
+
+        let tables = repo.tables();
+        let room = &tables.hotel_rooms[0];
+        assert_eq!(room.password, "jones");
+        assert_eq!(room.name, "Mary Jones");
+        assert_eq!(
+            room.checkout_date.date(),
+            NaiveDate::from_ymd_opt(2025, 11, 25).unwrap()
+        );
+        assert!(room.updated_at.is_some());
+        assert_eq!(tables.radcheck[0].value, "jones");
+    }
+
+    #[tokio::test]
+    async fn update_moves_guest_to_new_room() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully updated to 205");
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.hotel_rooms[0].room_number, "205");
+        assert_eq!(tables.radcheck[0].username, "205");
+        assert_eq!(tables.radusergroup[0].username, "205");
+    }
+
+    #[tokio::test]
+    async fn update_requires_fields() {
+        let (_, service) = setup();
+
+        let mut q = query("update");
+        q.room = None;
+        assert_invalid_field("room", service.process(q).await, "room is required");
+
+        let mut q = query("update");
+        q.pass = Some("".into());
+        assert_invalid_field("pass", service.process(q).await, "pass is required");
+
+        let mut q = query("update");
+        q.cidate = None;
+        assert_invalid_field("cidate", service.process(q).await, "cidate is required");
+
+        let mut q = query("update");
+        q.codate = Some(" ".into());
+        assert_invalid_field("codate", service.process(q).await, "codate is required");
+    }
+
+    #[tokio::test]
+    async fn update_unknown_room_is_not_found() {
+        let (_, service) = setup();
+
+        assert_not_found(
+            service.process(query("update")).await,
+            "room 101 not found for update",
+        );
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("999".into());
+        assert_not_found(service.process(q).await, "room 999 not found for update");
+    }
+
+    #[tokio::test]
+    async fn update_rejects_move_into_occupied_room() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+        checkin(&service, "205").await;
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        assert_conflict(
+            service.process(q).await,
+            "target room 205 is already in use",
+        );
+
+        let tables = repo.tables();
+        assert!(tables.hotel_rooms.iter().any(|r| r.room_number == "101"));
+        assert_eq!(tables.radcheck.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn update_rejects_bad_dates() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.codate = Some("2025/11/25".into());
+        assert_invalid_field(
+            "codate",
+            service.process(q).await,
+            "invalid checkout date 2025/11/25",
+        );
+
+        assert_eq!(
+            repo.tables().hotel_rooms[0].checkout_date.date(),
+            NaiveDate::from_ymd_opt(2025, 11, 22).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn checkin_writes_reply_attributes() {
+        let (repo, service) = setup_with(reply_settings());
+        let mut q = query("checkin");
+        q.codate = Some(
+            (Local::now() + chrono::Duration::days(2))
+                .format("%d/%m/%Y")
+                .to_string(),
+        );
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.radreply.len(), 2);
+        assert_eq!(tables.radreply[0].attribute, "Session-Timeout");
+        let timeout: i64 = tables.radreply[0].value.parse().unwrap();
+        assert!(timeout > 86_400 && timeout <= 3 * 86_400);
+        assert_eq!(tables.radreply[1].attribute, "Mikrotik-Rate-Limit");
+        assert_eq!(tables.radreply[1].value, "2M/4M");
+        assert!(tables.radreply.iter().all(|r| r.username == "101"));
+    }
+
+    #[tokio::test]
+    async fn checkin_without_expiration() {
+        let mut settings = ServiceSettings::default();
+        settings.radius.expiration = false;
+        let (repo, service) = setup_with(settings);
+        service.process(query("checkin")).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.radcheck.len(), 1);
+        assert_eq!(tables.radcheck[0].attribute, "Cleartext-Password");
+    }
+
+    #[tokio::test]
+    async fn update_keeps_radius_attributes_in_sync() {
+        let (repo, service) = setup_with(reply_settings());
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        q.pass = Some("jones".into());
+        q.codate = Some("25/11/2025".into());
+        q.cotime = Some("11:00:00".into());
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.radcheck.len(), 2);
+        assert!(tables.radcheck.iter().all(|r| r.username == "205"));
+        assert_eq!(tables.radcheck[0].value, "jones");
+        assert_eq!(tables.radcheck[1].value, "25 Nov 2025 11:00:00");
+        assert_eq!(tables.radreply.len(), 2);
+        assert!(tables.radreply.iter().all(|r| r.username == "205"));
+    }
+
+    #[tokio::test]
+    async fn checkout_removes_reply_attributes() {
+        let (repo, service) = setup_with(reply_settings());
+        checkin(&service, "101").await;
+
+        service.process(query("checkout")).await.unwrap();
+
+        let tables = repo.tables();
+        assert!(tables.radcheck.is_empty());
+        assert!(tables.radreply.is_empty());
+    }
+
+    fn tiered_setup() -> (
+        Arc<InMemoryBookingRepository>,
+        BookingService<InMemoryBookingRepository>,
+    ) {
+        let mut settings = ServiceSettings::default();
+        settings.guest_types.services = [
+            ("VIP".to_string(), "Hotel Premium".to_string()),
+            ("STAFF".to_string(), "Staff".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        settings.guest_types.default_service = Some("Hotel Standard".into());
+
+        let (repo, service) = setup_with(settings);
+        repo.add_hotel_service(8, "Hotel Standard");
+        repo.add_hotel_service(9, "Hotel Premium");
+        (repo, service)
+    }
+
+    #[tokio::test]
+    async fn checkin_maps_gtype_to_service() {
+        let (repo, service) = tiered_setup();
+
+        let mut q = query("checkin");
+        q.gtype = Some("vip".into());
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms[0].service_id, 9);
+        assert_eq!(tables.radusergroup[0].groupname, "Hotel Premium");
+    }
+
+    #[tokio::test]
+    async fn checkin_falls_back_to_default_service() {
+        let (repo, service) = tiered_setup();
+
+        // Unmapped gtype, missing gtype and a mapping to an inactive service.
+        for (room, gtype) in [
+            ("101", Some("GROUP")),
+            ("102", None),
+            ("103", Some("STAFF")),
+        ] {
+            let mut q = query("checkin");
+            q.room = Some(room.into());
+            q.gtype = gtype.map(String::from);
+            service.process(q).await.unwrap();
+        }
+
+        let tables = repo.tables();
+        assert!(tables.hotel_rooms.iter().all(|r| r.service_id == 8));
+        assert!(
+            tables
+                .radusergroup
+                .iter()
+                .all(|r| r.groupname == "Hotel Standard")
+        );
+    }
+
+    #[tokio::test]
+    async fn update_switches_service_when_gtype_changes() {
+        let (repo, service) = tiered_setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.gtype = Some("".into());
+        service.process(q).await.unwrap();
+        assert_eq!(repo.tables().radusergroup[0].groupname, "Hotel Premium");
+
+        let mut q = query("update");
+        q.gtype = Some("CORPORATE".into());
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms[0].service_id, 8);
+        assert_eq!(tables.radusergroup[0].username, "205");
+        assert_eq!(tables.radusergroup[0].groupname, "Hotel Standard");
+    }
+
+    /// Records every session it is asked to disconnect and ACKs it.
+    #[derive(Default)]
+    struct RecordingNas {
+        sessions: std::sync::Mutex<Vec<RadiusSession>>,
+    }
+
+    #[async_trait::async_trait]
+    impl NasClient for RecordingNas {
+        async fn disconnect(&self, session: &RadiusSession) -> Result<DisconnectOutcome> {
+            self.sessions.lock().unwrap().push(session.clone());
+            Ok(DisconnectOutcome::Ack)
+        }
+    }
+
+    fn setup_with_nas() -> (
+        Arc<InMemoryBookingRepository>,
+        Arc<RecordingNas>,
+        BookingService<InMemoryBookingRepository>,
+    ) {
+        let (repo, service) = setup();
+        let nas = Arc::new(RecordingNas::default());
+        let service = service.with_nas_client(Some(nas.clone()));
+
+        let mut tables = repo.tables();
+        for (username, session_id, stopped) in [
+            ("101", "s-1", false),
+            ("101", "s-0", true),
+            ("102", "s-2", false),
+        ] {
+            tables.radacct.push(RadAcctRow {
+                username: username.into(),
+                nasipaddress: "10.0.0.1".into(),
+                acctsessionid: session_id.into(),
+                framedipaddress: None,
+                callingstationid: None,
+                acctstoptime: stopped.then(|| Local::now().naive_local()),
+            });
+        }
+        drop(tables);
+
+        (repo, nas, service)
+    }
+
+    #[tokio::test]
+    async fn checkout_disconnects_open_sessions() {
+        let (_, nas, service) = setup_with_nas();
+        checkin(&service, "101").await;
+
+        let resp = service.process(query("checkout")).await.unwrap();
+
+        let disconnects = resp.disconnects.unwrap();
+        assert_eq!(disconnects.len(), 1);
+        assert_eq!(disconnects[0].nas, "10.0.0.1");
+        assert_eq!(disconnects[0].session_id, "s-1");
+        assert_eq!(disconnects[0].result, "ack");
+        assert_eq!(nas.sessions.lock().unwrap()[0].username, "101");
+    }
+
+    #[tokio::test]
+    async fn room_move_disconnects_old_room_sessions() {
+        let (_, nas, service) = setup_with_nas();
+        checkin(&service, "101").await;
+
+        let resp = service.process(query("update")).await.unwrap();
+        assert!(resp.disconnects.is_none());
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        let resp = service.process(q).await.unwrap();
+
+        assert_eq!(resp.disconnects.unwrap().len(), 1);
+        let sessions = nas.sessions.lock().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].acct_session_id, "s-1");
+    }
+
+    #[tokio::test]
+    async fn checkout_without_nas_skips_disconnect() {
+        let (_, service) = setup();
+        checkin(&service, "101").await;
+
+        let resp = service.process(query("checkout")).await.unwrap();
+        assert!(resp.disconnects.is_none());
+    }
+
+    fn history_params() -> HistoryQueryParams {
+        HistoryQueryParams {
+            room: None,
+            folio: None,
+            from: None,
+            to: None,
+            limit: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn every_request_is_audited_with_redacted_password() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+        let mut q = query("checkin");
+        q.rsvno = Some("R-2".into());
+        let _ = service.process(q).await;
+        let _ = service.process(query("noshow")).await;
+
+        let tables = repo.tables();
+        let log: Vec<_> = tables.pms_audit_log.iter().map(|r| &r.entry).collect();
+        assert_eq!(log.len(), 3);
+
+        assert_eq!(log[0].mode, "checkin");
+        assert_eq!(log[0].outcome, "success");
+        assert_eq!(log[0].folio_number.as_deref(), Some("R-1"));
+        assert!(!log[0].params.contains("Smith!"));
+        let params: serde_json::Value = serde_json::from_str(&log[0].params).unwrap();
+        assert_eq!(params["pass"], "***");
+        assert_eq!(params["cidate"], "20/11/2025");
+
+        assert_eq!(log[1].outcome, "error");
+        assert_eq!(log[1].code.as_deref(), Some("conflict"));
+        assert_eq!(log[1].message, "room 101 is in use");
+
+        assert_eq!(log[2].mode, "noshow");
+        assert_eq!(log[2].outcome, "error");
+    }
+
+    #[tokio::test]
+    async fn history_filters_by_room_and_folio() {
+        let (_, service) = setup();
+        checkin(&service, "101").await;
+        let mut q = query("checkin");
+        q.room = Some("305".into());
+        q.rsvno = Some("R-2".into());
+        service.process(q).await.unwrap();
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        service.process(q).await.unwrap();
+
+        let mut params = history_params();
+        params.room = Some("101".into());
+        let entries = service.history(params).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].mode, "update");
+        assert_eq!(entries[1].mode, "checkin");
+        assert_eq!(entries[1].params["room"], "101");
+
+        let mut params = history_params();
+        params.folio = Some("R-2".into());
+        let entries = service.history(params).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].room_number.as_deref(), Some("305"));
+
+        let mut params = history_params();
+        params.limit = Some(1);
+        assert_eq!(service.history(params).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn history_filters_by_date_range() {
+        let (_, service) = setup();
+        checkin(&service, "101").await;
+        let today = Local::now().date_naive();
+
+        let mut params = history_params();
+        params.from = Some(today.format("%Y-%m-%d").to_string());
+        params.to = Some(today.format("%Y-%m-%d").to_string());
+        assert_eq!(service.history(params).await.unwrap().len(), 1);
+
+        let mut params = history_params();
+        params.to = Some(today.pred_opt().unwrap().format("%Y-%m-%d").to_string());
+        assert!(service.history(params).await.unwrap().is_empty());
+
+        let mut params = history_params();
+        params.from = Some("12/11/2025".into());
+        assert_invalid_field(
+            "from",
+            service.history(params).await,
+            "invalid date 12/11/2025",
+        );
+    }
+
+    #[tokio::test]
+    async fn retried_checkin_returns_original_response() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let resp = service.process(query("checkin")).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkin");
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.pms_audit_log[1].entry.outcome, "replay");
+    }
+
+    #[tokio::test]
+    async fn retried_checkout_and_room_move_are_replayed() {
+        let (_, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("update");
+        q.room = Some("205".into());
+        q.oldroom = Some("101".into());
+        service.process(q.clone()).await.unwrap();
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully updated to 205");
+
+        let mut q = query("checkout");
+        q.room = Some("205".into());
+        service.process(q.clone()).await.unwrap();
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(resp.message, "room 205 successfully checkout");
+    }
+
+    #[tokio::test]
+    async fn repeat_after_a_later_change_is_processed_again() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+        service.process(query("checkout")).await.unwrap();
+
+        service.process(query("checkin")).await.unwrap();
+        assert_eq!(repo.tables().hotel_rooms.len(), 1);
+
+        let mut there = query("update");
+        there.room = Some("205".into());
+        there.oldroom = Some("101".into());
+        let mut back = query("update");
+        back.room = Some("101".into());
+        back.oldroom = Some("205".into());
+        service.process(there.clone()).await.unwrap();
+        service.process(back).await.unwrap();
+        service.process(there).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms[0].room_number, "205");
+        assert!(
+            tables
+                .pms_audit_log
+                .iter()
+                .all(|r| r.entry.outcome == "success")
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_returns_the_full_original_response() {
+        let (_, nas, service) = setup_with_nas();
+        checkin(&service, "101").await;
+        service.process(query("checkout")).await.unwrap();
+
+        let resp = service.process(query("checkout")).await.unwrap();
+        let disconnects = resp
+            .disconnects
+            .expect("disconnects of the original checkout");
+        assert_eq!(disconnects[0].session_id, "s-1");
+        assert_eq!(nas.sessions.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn checkout_without_rsvno_or_key_is_not_deduplicated() {
+        let (_, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("checkout");
+        q.rsvno = None;
+        service.process(q.clone()).await.unwrap();
+        assert_not_found(service.process(q).await, "room 101 not found for checkout");
+    }
+
+    #[tokio::test]
+    async fn idempotency_key_replays_and_rejects_reuse() {
+        let (repo, service) = setup();
+
+        let mut q = query("checkout");
+        q.rsvno = None;
+        q.idempotency_key = Some("k-1".into());
+        checkin(&service, "101").await;
+        service.process(q.clone()).await.unwrap();
+
+        let resp = service.process(q.clone()).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkout");
+
+        q.room = Some("102".into());
+        assert_conflict(
+            service.process(q).await,
+            "idempotency key k-1 was already used for a different request",
+        );
+        assert_eq!(
+            repo.tables().pms_audit_log[1]
+                .entry
+                .idempotency_key
+                .as_deref(),
+            Some("k-1")
+        );
+    }
+
+    #[test]
+    fn fingerprints_are_keyed() {
+        let q = query("checkin");
+        let fingerprint = request_fingerprint(b"key-a", &q);
+
+        assert_eq!(fingerprint, request_fingerprint(b"key-a", &q));
+        assert_ne!(fingerprint, request_fingerprint(b"key-b", &q));
+        let mut other_pass = q.clone();
+        other_pass.pass = Some("other".into());
+        assert_ne!(fingerprint, request_fingerprint(b"key-a", &other_pass));
+    }
+
+    #[tokio::test]
+    async fn replay_detection_can_be_disabled() {
+        let mut settings = ServiceSettings::default();
+        settings.idempotency.window_secs = 0;
+        let (_, service) = setup_with(settings);
+        checkin(&service, "101").await;
+
+        assert_conflict(
+            service.process(query("checkin")).await,
+            "room 101 is in use",
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_overdue_checks_out_past_stays_only() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let mut q = query("checkin");
+        q.room = Some("102".into());
+        q.rsvno = Some("R-2".into());
+        q.codate = Some("22/11/2099".into());
+        service.process(q).await.unwrap();
+
+        let expired = service.expire_overdue(3600).await.unwrap();
+        assert_eq!(expired, vec!["101".to_string()]);
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.hotel_rooms[0].room_number, "102");
+        assert!(tables.radcheck.iter().all(|r| r.username == "102"));
+
+        let audit = &tables.pms_audit_log.last().unwrap().entry;
+        assert_eq!(audit.mode, "expire");
+        assert_eq!(audit.room_number.as_deref(), Some("101"));
+        assert_eq!(audit.folio_number.as_deref(), Some("R-1"));
+        assert_eq!(audit.outcome, "success");
+    }
+
+    #[tokio::test]
+    async fn expire_overdue_respects_grace_and_cron_flag() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let grace = (Local::now().naive_local()
+            - NaiveDate::from_ymd_opt(2025, 11, 22)
+                .unwrap()
+                .and_hms_opt(13, 0, 0)
+                .unwrap())
+        .num_seconds()
+            + 3600;
+        assert!(service.expire_overdue(grace).await.unwrap().is_empty());
+
+        repo.tables().services[0].cron = false;
+        assert!(service.expire_overdue(0).await.unwrap().is_empty());
+        assert_eq!(repo.tables().hotel_rooms.len(), 1);
+    }
+
+    fn suite_setup() -> (
+        Arc<InMemoryBookingRepository>,
+        BookingService<InMemoryBookingRepository>,
+    ) {
+        let mut settings = ServiceSettings::default();
+        settings.rooms.max_guests = 3;
+        setup_with(settings)
+    }
+
+    fn guest(folio: &str, name: &str) -> PmsQueryParams {
+        let mut q = query("checkin");
+        q.rsvno = Some(folio.into());
+        q.name = Some(name.into());
+        q.pass = name.split_whitespace().last().map(str::to_string);
+        q
+    }
+
+    fn passwords(repo: &InMemoryBookingRepository) -> Vec<(String, String)> {
+        repo.tables()
+            .radcheck
+            .iter()
+            .filter(|r| r.attribute == "Cleartext-Password")
+            .map(|r| (r.username.clone(), r.value.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn additional_guests_get_their_own_credential() {
+        let (repo, service) = suite_setup();
+        checkin(&service, "101").await;
+
+        let resp = service.process(guest("R-2", "Jane Doe")).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkin as 101-2");
+        let resp = service.process(guest("R-3", "Tim Doe")).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkin as 101-3");
+
+        assert_eq!(
+            passwords(&repo),
+            vec![
+                ("101".to_string(), "smith".to_string()),
+                ("101-2".to_string(), "doe".to_string()),
+                ("101-3".to_string(), "doe".to_string()),
+            ]
+        );
+        assert_eq!(repo.tables().radusergroup.len(), 3);
+
+        assert_conflict(
+            service.process(guest("R-4", "Ann Doe")).await,
+            "room 101 is in use",
+        );
+    }
+
+    #[tokio::test]
+    async fn joining_guest_needs_a_new_folio() {
+        let (_, service) = suite_setup();
+        checkin(&service, "101").await;
+
+        let mut q = guest("R-2", "Jane Doe");
+        q.rsvno = None;
+        assert_invalid_field(
+            "rsvno",
+            service.process(q).await,
+            "rsvno is required to add a guest to room 101",
+        );
+        assert_conflict(
+            service.process(guest("R-1", "Jane Doe")).await,
+            "guest R-1 is already in room 101",
+        );
+
+        let mut q = guest("R-2", "Jane Doe");
+        q.credential = Some("family".into());
+        assert_invalid_field(
+            "credential",
+            service.process(q).await,
+            "invalid credential family",
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_credential_lasts_until_latest_checkout() {
+        let (repo, service) = suite_setup();
+        checkin(&service, "101").await;
+
+        let mut q = guest("R-2", "Jane Doe");
+        q.credential = Some("shared".into());
+        q.codate = Some("25/11/2025".into());
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkin");
+
+        assert_eq!(
+            passwords(&repo),
+            vec![("101".to_string(), "smith".to_string())]
+        );
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 2);
+        assert!(tables.hotel_rooms.iter().all(|r| r.username == "101"));
+        let expiration = tables
+            .radcheck
+            .iter()
+            .find(|r| r.attribute == "Expiration")
+            .unwrap();
+        assert_eq!(expiration.value, "25 Nov 2025 13:00:00");
+    }
+
+    #[tokio::test]
+    async fn checkout_with_rsvno_removes_only_that_guest() {
+        let (repo, service) = suite_setup();
+        checkin(&service, "101").await;
+        service.process(guest("R-2", "Jane Doe")).await.unwrap();
+
+        let mut q = query("checkout");
+        q.rsvno = Some("R-2".into());
+        let resp = service.process(q.clone()).await.unwrap();
+        assert_eq!(
+            resp.message,
+            "guest R-2 successfully checkout from room 101"
+        );
+        assert_eq!(
+            passwords(&repo),
+            vec![("101".to_string(), "smith".to_string())]
+        );
+
+        service.process(guest("R-3", "Tim Doe")).await.unwrap();
+        q.rsvno = Some("R-9".into());
+        assert_not_found(service.process(q).await, "guest R-9 not found in room 101");
+
+        let mut q = query("checkout");
+        q.rsvno = None;
+        service.process(q).await.unwrap();
+        let tables = repo.tables();
+        assert!(tables.hotel_rooms.is_empty());
+        assert!(tables.radcheck.is_empty());
+        assert!(tables.radusergroup.is_empty());
+    }
+
+    #[tokio::test]
+    async fn shared_credential_survives_one_guest_leaving() {
+        let (repo, service) = suite_setup();
+        checkin(&service, "101").await;
+        let mut q = guest("R-2", "Jane Doe");
+        q.credential = Some("shared".into());
+        service.process(q).await.unwrap();
+
+        let mut q = query("checkout");
+        q.rsvno = Some("R-1".into());
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.hotel_rooms[0].folio_number, "R-2");
+        assert_eq!(tables.radusergroup[0].username, "101");
+        drop(tables);
+        assert_eq!(
+            passwords(&repo),
+            vec![("101".to_string(), "smith".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_moves_one_guest_of_a_shared_room() {
+        let (repo, service) = suite_setup();
+        checkin(&service, "101").await;
+        service.process(guest("R-2", "Jane Doe")).await.unwrap();
+
+        let mut q = guest("R-2", "Jane Doe");
+        q.mode = "update".into();
+        q.oldroom = Some("101".into());
+        q.room = Some("205".into());
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully updated to 205");
+
+        assert_eq!(
+            passwords(&repo),
+            vec![
+                ("101".to_string(), "smith".to_string()),
+                ("205".to_string(), "doe".to_string()),
+            ]
+        );
+
+        let mut q = query("update");
+        q.rsvno = None;
+        service.process(guest("R-3", "Tim Doe")).await.unwrap();
+        assert_invalid_field(
+            "rsvno",
+            service.process(q).await,
+            "rsvno is required to update a guest in room 101",
+        );
+    }
+
+    #[tokio::test]
+    async fn simultaneous_use_comes_from_the_service_plan() {
+        let (repo, service) = setup();
+        repo.tables().services[0].simultaneous_use = Some(3);
+        checkin(&service, "101").await;
+
+        let tables = repo.tables();
+        let limit = tables
+            .radcheck
+            .iter()
+            .find(|r| r.attribute == "Simultaneous-Use")
+            .unwrap();
+        assert_eq!((limit.op.as_str(), limit.value.as_str()), (":=", "3"));
+    }
+
+    fn password_request(pass: &str, rsvno: Option<&str>) -> AdminPasswordRequest {
+        AdminPasswordRequest {
+            pass: pass.into(),
+            rsvno: rsvno.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn list_rooms_groups_guests_and_filters() {
+        let (_repo, service) = suite_setup();
+        checkin(&service, "101").await;
+        service.process(guest("R-2", "Jane Doe")).await.unwrap();
+        let mut q = guest("R-3", "Tim Doe");
+        q.room = Some("102".into());
+        q.codate = Some("23/11/2025".into());
+        service.process(q).await.unwrap();
+
+        let rooms = service.list_rooms(RoomListParams::default()).await.unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[0].room, "101");
+        assert_eq!(
+            rooms[0]
+                .guests
+                .iter()
+                .map(|g| g.username.as_str())
+                .collect::<Vec<_>>(),
+            vec!["101", "101-2"]
+        );
+        assert_eq!(rooms[0].guests[1].service.as_deref(), Some("Hotel Basic"));
+
+        let leaving = service
+            .list_rooms(RoomListParams {
+                checkout: Some("2025-11-23".into()),
+                service: Some("hotel basic".into()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(leaving.len(), 1);
+        assert_eq!(leaving[0].room, "102");
+        assert_eq!(leaving[0].guests[0].folio_number.as_deref(), Some("R-3"));
+
+        assert_invalid_field(
+            "service",
+            service
+                .list_rooms(RoomListParams {
+                    service: Some("gold".into()),
+                    ..Default::default()
+                })
+                .await,
+            "unknown service gold",
+        );
+        assert_invalid_field(
+            "checkout",
+            service
+                .list_rooms(RoomListParams {
+                    checkout: Some("23/11/2025".into()),
+                    ..Default::default()
+                })
+                .await,
+            "invalid date 23/11/2025",
+        );
+    }
+
+    #[tokio::test]
+    async fn room_view_needs_an_occupied_room() {
+        let (_repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let room = service.room("101").await.unwrap();
+        assert_eq!(room.guests.len(), 1);
+        assert_eq!(room.guests[0].name.as_deref(), Some("John Smith"));
+        assert_not_found(service.room("102").await, "room 102 not found");
+    }
+
+    #[tokio::test]
+    async fn force_checkout_is_audited_as_admin_action() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let resp = service.force_checkout("101", None).await.unwrap();
+        assert_eq!(resp.message, "room 101 successfully checkout");
+        assert!(repo.tables().hotel_rooms.is_empty());
+        assert_eq!(repo.tables().pms_audit_log[1].entry.mode, "admin_checkout");
+
+        // A PMS checkout of the same folio is still processed, not replayed.
+        let mut q = query("checkout");
+        q.rsvno = Some("R-1".into());
+        assert_not_found(service.process(q).await, "room 101 not found for checkout");
+    }
+
+    #[tokio::test]
+    async fn reset_password_keeps_the_stay() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let resp = service
+            .reset_password("101", password_request("Sunny-Day7", None))
+            .await
+            .unwrap();
+        assert_eq!(resp.message, "room 101 successfully updated");
+        assert_eq!(
+            passwords(&repo),
+            vec![("101".to_string(), "sunnyday7".to_string())]
+        );
+
+        let tables = repo.tables();
+        let room = &tables.hotel_rooms[0];
+        assert_eq!(room.name, "John Smith");
+        assert_eq!(room.folio_number, "R-1");
+        assert_eq!(
+            room.checkout_date,
+            NaiveDate::from_ymd_opt(2025, 11, 22)
+                .unwrap()
+                .and_hms_opt(13, 0, 0)
+                .unwrap()
+        );
+        let audit = &tables.pms_audit_log.last().unwrap().entry;
+        assert_eq!(audit.mode, "admin_update");
+        assert!(!audit.params.contains("Sunny"));
+    }
+
+    #[tokio::test]
+    async fn reset_password_picks_the_guest_by_rsvno() {
+        let mut settings = ServiceSettings::default();
+        settings.rooms.max_guests = 3;
+        settings.rooms.shared_credential = true;
+        let (repo, service) = setup_with(settings);
+        checkin(&service, "101").await;
+        service.process(guest("R-2", "Jane Doe")).await.unwrap();
+
+        assert_invalid_field(
+            "rsvno",
+            service
+                .reset_password("101", password_request("secret", None))
+                .await,
+            "rsvno is required for room 101 with several guests",
+        );
+        assert_not_found(
+            service
+                .reset_password("101", password_request("secret", Some("R-9")))
+                .await,
+            "guest R-9 not found in room 101",
+        );
+        assert_validation(
+            service
+                .reset_password("101", password_request("secret", Some("R-2")))
+                .await,
+            "credential 101 is shared with other guests of room 101",
+        );
+        assert_eq!(
+            passwords(&repo),
+            vec![("101".to_string(), "smith".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn extend_stay_moves_checkout_and_expiration() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+
+        assert_validation(
+            service
+                .extend_stay(
+                    "101",
+                    AdminExtendRequest {
+                        codate: "21/11/2025".into(),
+                        cotime: None,
+                        rsvno: None,
+                    },
+                )
+                .await,
+            "new checkout 2025-11-21 13:00:00 is not after current checkout 2025-11-22 13:00:00",
+        );
+
+        service
+            .extend_stay(
+                "101",
+                AdminExtendRequest {
+                    codate: "25/11/2025".into(),
+                    cotime: Some("11:00:00".into()),
+                    rsvno: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(
+            tables.hotel_rooms[0].checkout_date,
+            NaiveDate::from_ymd_opt(2025, 11, 25)
+                .unwrap()
+                .and_hms_opt(11, 0, 0)
+                .unwrap()
+        );
+        assert_eq!(tables.hotel_rooms[0].password, "smith");
+        assert_eq!(tables.radcheck[1].value, "25 Nov 2025 11:00:00");
+    }
+
+    fn snap(room: &str, folio: &str, codate: &str) -> SnapshotGuest {
+        SnapshotGuest {
+            room: room.into(),
+            name: Some("john SMITH".into()),
+            pass: Some("Smith".into()),
+            rsvno: Some(folio.into()),
+            cidate: "20/11/2025".into(),
+            codate: codate.into(),
+            ..Default::default()
+        }
+    }
+
+    /// 101 checks out later than we know, 102 has left, 103 is missing and
+    /// the credential `999` belongs to no guest.
+    async fn drifted_setup() -> (
+        Arc<InMemoryBookingRepository>,
+        BookingService<InMemoryBookingRepository>,
+        Vec<SnapshotGuest>,
+    ) {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+        let mut q = query("checkin");
+        q.room = Some("102".into());
+        q.rsvno = Some("R-2".into());
+        service.process(q).await.unwrap();
+        repo.tables().radusergroup.push(
+            crate::infrastructure::repositories::memory::RadUserGroupRow {
+                username: "999".into(),
+                groupname: "Hotel Basic".into(),
+                priority: 1,
+                user_type: "hotel-room".into(),
+            },
+        );
+
+        let snapshot = vec![
+            snap("101", "R-1", "23/11/2025"),
+            snap("103", "R-3", "24/11/2025"),
+        ];
+        (repo, service, snapshot)
+    }
+
+    #[tokio::test]
+    async fn reconcile_dry_run_only_reports() {
+        let (repo, service, snapshot) = drifted_setup().await;
+
+        let report = service.reconcile(snapshot, false).await.unwrap();
+        assert!(!report.applied);
+        assert_eq!(report.in_sync, 0);
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].folio_number.as_deref(), Some("R-3"));
+        assert_eq!(report.stale.len(), 1);
+        assert_eq!(report.stale[0].room, "102");
+        assert_eq!(
+            report.mismatched[0].details,
+            vec!["checkout 2025-11-22 13:00:00 -> 2025-11-23 13:00:00"]
+        );
+        assert_eq!(report.orphaned, vec!["999"]);
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 2);
+        assert_eq!(tables.radusergroup.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reconcile_apply_brings_tables_in_line() {
+        let (repo, service, snapshot) = drifted_setup().await;
+
+        let report = service.reconcile(snapshot.clone(), true).await.unwrap();
+        assert!(report.applied);
+        {
+            let tables = repo.tables();
+            let mut rooms: Vec<_> = tables
+                .hotel_rooms
+                .iter()
+                .map(|r| (r.room_number.as_str(), r.checkout_date.date().to_string()))
+                .collect();
+            rooms.sort();
+            assert_eq!(
+                rooms,
+                vec![("101", "2025-11-23".into()), ("103", "2025-11-24".into())]
+            );
+            assert!(tables.radusergroup.iter().all(|g| g.username != "999"));
+            assert!(tables.radcheck.iter().all(|c| c.username != "102"));
+            assert_eq!(tables.pms_audit_log.last().unwrap().entry.mode, "reconcile");
+        }
+
+        let again = service.reconcile(snapshot, false).await.unwrap();
+        assert_eq!(again.in_sync, 2);
+        assert!(again.missing.is_empty() && again.stale.is_empty());
+        assert!(again.mismatched.is_empty() && again.orphaned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reconcile_repairs_credentials_and_room_moves() {
+        let (repo, service) = setup();
+        checkin(&service, "101").await;
+        repo.tables().radcheck.clear();
+
+        let report = service
+            .reconcile(vec![snap("101", "R-1", "22/11/2025")], true)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.mismatched[0].details,
+            vec!["password of 101 out of sync"]
+        );
+        assert_eq!(
+            passwords(&repo),
+            vec![("101".to_string(), "smith".to_string())]
+        );
+
+        let report = service
+            .reconcile(vec![snap("205", "R-1", "22/11/2025")], true)
+            .await
+            .unwrap();
+        assert_eq!(report.mismatched[0].details, vec!["room 101 -> 205"]);
+        assert_eq!(
+            passwords(&repo),
+            vec![("205".to_string(), "smith".to_string())]
+        );
+    }
+
+    fn hashed_setup(
+        storage: PasswordStorage,
+    ) -> (
+        Arc<InMemoryBookingRepository>,
+        BookingService<InMemoryBookingRepository>,
+    ) {
+        let mut settings = ServiceSettings::default();
+        settings.radius.schema.password_storage = storage;
+        settings.rooms.max_guests = 2;
+        let repo = Arc::new(
+            InMemoryBookingRepository::with_hotel_service(7, "Hotel Basic")
+                .with_schema(settings.radius.schema.clone()),
+        );
+        (repo.clone(), BookingService::new(repo, Arc::new(settings)))
+    }
+
+    /// radcheck password value of `username`, checked against its attribute.
+    fn stored_password(repo: &InMemoryBookingRepository, username: &str) -> String {
+        let tables = repo.tables();
+        let row = tables
+            .radcheck
+            .iter()
+            .find(|r| r.username == username && r.attribute == "SSHA2-512-Password")
+            .expect("no SSHA2-512-Password row");
+        let room = tables
+            .hotel_rooms
+            .iter()
+            .find(|r| r.username == username)
+            .unwrap();
+        assert_eq!(room.password, row.value, "hotel_rooms copy differs");
+        row.value.clone()
+    }
+
+    #[tokio::test]
+    async fn hashed_passwords_are_kept_until_changed() {
+        let (repo, service) = hashed_setup(PasswordStorage::Ssha512);
+        checkin(&service, "101").await;
+        let first = stored_password(&repo, "101");
+        assert_ne!(first, "smith");
+        assert!(verify_password(PasswordStorage::Ssha512, "smith", &first));
+
+        // The PMS resending the same password leaves the stored hash alone.
+        service.process(query("update")).await.unwrap();
+        assert_eq!(stored_password(&repo, "101"), first);
+
+        service
+            .extend_stay(
+                "101",
+                AdminExtendRequest {
+                    codate: "25/11/2025".into(),
+                    cotime: None,
+                    rsvno: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(stored_password(&repo, "101"), first);
+
+        service
+            .reset_password("101", password_request("Jones", None))
+            .await
+            .unwrap();
+        let reset = stored_password(&repo, "101");
+        assert!(verify_password(PasswordStorage::Ssha512, "jones", &reset));
+        assert!(!verify_password(PasswordStorage::Ssha512, "smith", &reset));
+    }
+
+    #[tokio::test]
+    async fn hashed_credential_is_shared_as_stored() {
+        let (repo, service) = hashed_setup(PasswordStorage::Ssha512);
+        checkin(&service, "101").await;
+        let mut q = guest("R-2", "Ann Jones");
+        q.credential = Some("shared".into());
+        service.process(q).await.unwrap();
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 2);
+        assert_eq!(
+            tables.hotel_rooms[0].password,
+            tables.hotel_rooms[1].password
+        );
+        assert_eq!(tables.radcheck[0].value, tables.hotel_rooms[0].password);
+    }
+
+    #[tokio::test]
+    async fn reconcile_converts_cleartext_credentials() {
+        let (repo, service) = hashed_setup(PasswordStorage::Ssha512);
+        checkin(&service, "101").await;
+        // As written before the storage mode changed.
+        {
+            let mut tables = repo.tables();
+            tables.hotel_rooms[0].password = "smith".into();
+            tables.radcheck[0].attribute = "Cleartext-Password".into();
+            tables.radcheck[0].value = "smith".into();
+        }
+
+        let snapshot = vec![snap("101", "R-1", "22/11/2025")];
+        let report = service.reconcile(snapshot.clone(), true).await.unwrap();
+        assert_eq!(
+            report.mismatched[0].details,
+            vec!["password of 101 stored as Cleartext-Password"]
+        );
+        let converted = stored_password(&repo, "101");
+        assert!(verify_password(
+            PasswordStorage::Ssha512,
+            "smith",
+            &converted
+        ));
+        assert!(
+            repo.tables()
+                .radcheck
+                .iter()
+                .all(|r| r.attribute != "Cleartext-Password")
+        );
+
+        let again = service.reconcile(snapshot, false).await.unwrap();
+        assert_eq!(again.in_sync, 1);
+    }
+
+    #[tokio::test]
+    async fn reconcile_reports_hashes_it_cannot_convert() {
+        let (repo, service) = hashed_setup(PasswordStorage::Ssha512);
+        checkin(&service, "101").await;
+        let nt = hash_password(PasswordStorage::NtHash, "smith");
+        {
+            let mut tables = repo.tables();
+            tables.hotel_rooms[0].password = nt.clone();
+            tables.radcheck[0].attribute = "NT-Password".into();
+            tables.radcheck[0].value = nt.clone();
+        }
+
+        let report = service
+            .reconcile(vec![snap("101", "R-1", "22/11/2025")], true)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.mismatched[0].details,
+            vec![
+                "password of 101 stored as NT-Password",
+                "check in again to convert the password"
+            ]
+        );
+        let tables = repo.tables();
+        assert_eq!(tables.radcheck[0].attribute, "NT-Password");
+        assert_eq!(tables.radcheck[0].value, nt);
+    }
+
+    #[tokio::test]
+    async fn reconcile_rejects_unusable_snapshots() {
+        let (_repo, service) = setup();
+
+        assert_validation(
+            service
+                .reconcile(
+                    vec![
+                        snap("101", "R-1", "22/11/2025"),
+                        snap("102", "R-1", "22/11/2025"),
+                    ],
+                    false,
+                )
+                .await,
+            "guest R-1 is listed twice",
+        );
+        assert_validation(
+            service
+                .reconcile(vec![snap("101", "R-1", "22.11.2025")], false)
+                .await,
+            "guest 1: invalid checkout date 22.11.2025",
+        );
+
+        assert_validation(
+            service.reconcile(Vec::new(), true).await,
+            "refusing to apply an empty snapshot",
+        );
+
+        let mut no_pass = snap("101", "R-1", "22/11/2025");
+        no_pass.pass = None;
+        let report = service
+            .reconcile(vec![no_pass.clone()], false)
+            .await
+            .unwrap();
+        assert_eq!(report.missing[0].details, vec!["no pass to check in with"]);
+        assert_validation(
+            service.reconcile(vec![no_pass], true).await,
+            "pass is required to check in guest R-1",
+        );
+    }
+
+    #[test]
+    fn snapshot_accepts_csv_and_json() {
+        let csv = "Room,Name,RSVNO,cidate,codate\r\n101,\"Smith, John\",R-1,20/11/2025,22/11/2025\r\n\n102,,,20/11/2025,21/11/2025\n";
+        let guests = SnapshotGuest::parse_snapshot(csv).unwrap();
+        assert_eq!(guests.len(), 2);
+        assert_eq!(guests[0].name.as_deref(), Some("Smith, John"));
+        assert_eq!(guests[0].rsvno.as_deref(), Some("R-1"));
+        assert_eq!(guests[1].name, None);
+        assert!(SnapshotGuest::parse_snapshot("room,codate\n101,22/11/2025").is_err());
+
+        let json =
+            r#"{"guests": [{"room": "101", "cidate": "20/11/2025", "codate": "22/11/2025"}]}"#;
+        assert_eq!(SnapshotGuest::parse_snapshot(json).unwrap()[0].room, "101");
+    }
+
+    fn property_service(
+        repo: &InMemoryBookingRepository,
+        id: &str,
+    ) -> BookingService<InMemoryBookingRepository> {
+        let property = Property {
+            id: id.into(),
+            realm: format!("@{}", id),
+        };
+        let settings = ServiceSettings {
+            property: Some(property.clone()),
+            ..ServiceSettings::default()
+        };
+        BookingService::new(Arc::new(repo.for_property(property)), Arc::new(settings))
+    }
+
+    #[tokio::test]
+    async fn properties_keep_their_rooms_apart() {
+        let repo = InMemoryBookingRepository::with_hotel_service(7, "Hotel Basic");
+        let madrid = property_service(&repo, "madrid");
+        let lisbon = property_service(&repo, "lisbon");
+
+        // Same request in both hotels: neither is a replay of the other.
+        checkin(&madrid, "101").await;
+        checkin(&lisbon, "101").await;
+        {
+            let tables = repo.tables();
+            let mut usernames: Vec<&str> = tables
+                .radusergroup
+                .iter()
+                .map(|r| r.username.as_str())
+                .collect();
+            usernames.sort();
+            assert_eq!(usernames, ["101@lisbon", "101@madrid"]);
+            assert_eq!(tables.hotel_rooms.len(), 2);
+            assert!(
+                tables
+                    .pms_audit_log
+                    .iter()
+                    .all(|r| r.entry.outcome == "success")
+            );
+        }
+
+        madrid.process(query("checkout")).await.unwrap();
+        assert_not_found(madrid.room("101").await, "room 101 not found");
+        let view = lisbon.room("101").await.unwrap();
+        assert_eq!(view.guests[0].username, "101@lisbon");
+
+        let tables = repo.tables();
+        assert_eq!(tables.hotel_rooms.len(), 1);
+        assert_eq!(tables.hotel_rooms[0].property_id.as_deref(), Some("lisbon"));
+        assert!(tables.radcheck.iter().all(|r| r.username == "101@lisbon"));
+    }
+
+    #[tokio::test]
+    async fn property_guests_share_the_realm() {
+        let repo = InMemoryBookingRepository::with_hotel_service(7, "Hotel Basic");
+        let mut settings = ServiceSettings {
+            property: Some(Property {
+                id: "madrid".into(),
+                realm: "@madrid".into(),
+            }),
+            ..ServiceSettings::default()
+        };
+        settings.rooms.max_guests = 2;
+        let property = settings.property.clone().unwrap();
+        let service =
+            BookingService::new(Arc::new(repo.for_property(property)), Arc::new(settings));
+
+        checkin(&service, "101").await;
+        let mut q = query("checkin");
+        q.rsvno = Some("R-2".into());
+        let resp = service.process(q).await.unwrap();
+        assert_eq!(
+            resp.message,
+            "room 101 successfully checkin as 101-2@madrid"
+        );
+        checkin(&property_service(&repo, "lisbon"), "205").await;
+
+        // Credentials of other properties are not orphans of this one.
+        let report = service
+            .reconcile(
+                vec![
+                    snap("101", "R-1", "22/11/2025"),
+                    snap("101", "R-2", "22/11/2025"),
+                ],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.in_sync, 2);
+        assert!(report.orphaned.is_empty(), "{:?}", report.orphaned);
+    }
+
+    fn portal_request(
+        room: &str,
+        last_name: Option<&str>,
+        rsvno: Option<&str>,
+    ) -> PortalLoginRequest {
+        PortalLoginRequest {
+            room: room.into(),
+            last_name: last_name.map(str::to_string),
+            rsvno: rsvno.map(str::to_string),
+            property: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn portal_login_matches_last_name_or_rsvno() {
+        let (_repo, service) = setup();
+        checkin(&service, "101").await;
+
+        let session = service
+            .portal_login(&portal_request("101", Some(" SMITH "), None))
+            .await
+            .unwrap()
+            .expect("last name should match");
+        assert_eq!(session.username, "101");
+        assert_eq!(session.password.as_deref(), Some("smith"));
+        assert_eq!(session.service.as_deref(), Some("Hotel Basic"));
+        assert_eq!(session.expires_at.to_string(), "2025-11-22 13:00:00");
+
+        let by_rsvno = service
+            .portal_login(&portal_request("101", None, Some("R-1")))
+            .await
+            .unwrap();
+        assert!(by_rsvno.is_some());
+
+        for request in [
+            portal_request("101", Some("Jones"), None),
+            portal_request("101", Some("Smith"), Some("R-2")),
+            portal_request("102", Some("Smith"), None),
+        ] {
+            assert!(
+                service.portal_login(&request).await.unwrap().is_none(),
+                "{:?}",
+                request
+            );
+        }
+        assert_validation(
+            service
+                .portal_login(&portal_request("101", Some("!!"), None))
+                .await,
+            "room and last_name or rsvno are required",
+        );
+    }
+
+    #[tokio::test]
+    async fn portal_login_finds_the_guest_among_several() {
+        let mut settings = ServiceSettings::default();
+        settings.rooms.max_guests = 3;
+        let (_repo, service) = setup_with(settings);
+        checkin(&service, "101").await;
+        let mut q = guest("R-2", "Maria de la Cruz");
+        q.pass = Some("Sunny7".into());
+        service.process(q).await.unwrap();
+
+        // The surname matches on its own, whatever the PMS sent as pass.
+        let session = service
+            .portal_login(&portal_request("101", Some("De La Cruz"), None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.username, "101-2");
+        assert_eq!(session.password.as_deref(), Some("sunny7"));
+
+        let session = service
+            .portal_login(&portal_request("101", Some("cruz"), Some("R-2")))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.username, "101-2");
+        assert!(
+            service
+                .portal_login(&portal_request("101", Some("Maria"), None))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn portal_login_returns_hashed_password_only_when_given() {
+        let (_repo, service) = hashed_setup(PasswordStorage::Ssha512);
+        checkin(&service, "101").await;
+
+        let session = service
+            .portal_login(&portal_request("101", Some("Smith"), None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.password.as_deref(), Some("smith"));
+
+        let session = service
+            .portal_login(&portal_request("101", None, Some("R-1")))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.username, "101");
+        assert_eq!(session.password, None);
+    }
+
+    #[tokio::test]
+    async fn portal_login_stays_within_the_property() {
+        let repo = InMemoryBookingRepository::with_hotel_service(7, "Hotel Basic");
+        let madrid = property_service(&repo, "madrid");
+        checkin(&madrid, "101").await;
+
+        let session = madrid
+            .portal_login(&portal_request("101", Some("Smith"), None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.username, "101@madrid");
+        assert!(
+            property_service(&repo, "lisbon")
+                .portal_login(&portal_request("101", Some("Smith"), None))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,686 @@
+use crate::application::errors::ErrorResponse;
+use crate::application::utils::datetime_utils::{
+    parse_pms_date, parse_pms_time, validate_date_format,
+};
+use crate::config::{env_name, var};
+use crate::domain::entities::{PasswordStorage, Property, RadiusAttribute, RadiusSchema};
+use anyhow::{Result, anyhow};
+use chrono::{Local, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::OnceCell;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Placeholder replaced by the number of seconds left until checkout.
+pub const STAY_SECONDS: &str = "{stay_seconds}";
+
+/// FreeRADIUS `Expiration` attribute format, e.g. `22 Nov 2025 13:00:00`.
+const EXPIRATION_FORMAT: &str = "%d %b %Y %H:%M:%S";
+
+pub static SERVICE_SETTINGS: OnceCell<Arc<ServiceSettings>> = OnceCell::new();
+pub static PROPERTY_SETTINGS: OnceCell<Vec<Arc<ServiceSettings>>> = OnceCell::new();
+
+#[derive(Debug, Clone, Default)]
+pub struct ServiceSettings {
+    /// The hotel these settings serve; `None` for a single-property install.
+    pub property: Option<Property>,
+    pub radius: RadiusSettings,
+    pub guest_types: GuestTypeSettings,
+    pub idempotency: IdempotencySettings,
+    pub rooms: RoomSettings,
+    pub dates: DateSettings,
+}
+
+impl ServiceSettings {
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(&var)
+    }
+
+    fn from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Self> {
+        Ok(Self {
+            property: None,
+            radius: RadiusSettings::from_vars(var)?,
+            guest_types: GuestTypeSettings::from_vars(var)?,
+            idempotency: IdempotencySettings::from_vars(var)?,
+            rooms: RoomSettings::from_vars(var)?,
+            dates: DateSettings::from_vars(var)?,
+        })
+    }
+
+    /// Settings of each property in `PROPERTIES=madrid,lisbon`; empty when
+    /// unset. Any setting can be overridden per property as
+    /// `PROPERTY_<ID>_<NAME>`, e.g. `PROPERTY_MADRID_PMS_TIMEZONE`, and
+    /// `PROPERTY_<ID>_REALM` (default `@<id>`) is appended to the
+    /// property's RADIUS usernames.
+    pub fn properties_from_env() -> Result<Vec<Self>> {
+        Self::properties_from_vars(&var)
+    }
+
+    fn properties_from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Vec<Self>> {
+        let ids = var("PROPERTIES").unwrap_or_default();
+        let mut properties: Vec<Self> = Vec::new();
+        for id in ids.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(anyhow!("invalid property id {:?}", id));
+            }
+            let prefix = format!("PROPERTY_{}_", env_name(id));
+            if properties
+                .iter()
+                .filter_map(|p| p.property.as_ref())
+                .any(|p| env_name(&p.id) == env_name(id))
+            {
+                return Err(anyhow!("property {:?} is listed twice", id));
+            }
+
+            let scoped = |name: &str| var(&format!("{}{}", prefix, name)).or_else(|| var(name));
+            let mut settings =
+                Self::from_vars(&scoped).map_err(|e| anyhow!("property {}: {}", id, e))?;
+
+            let realm = var(&format!("{}REALM", prefix))
+                .map(|r| r.trim().to_string())
+                .unwrap_or_else(|| format!("@{}", id.to_ascii_lowercase()));
+            if realm.is_empty()
+                || !realm
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '-'))
+            {
+                return Err(anyhow!("invalid {}REALM {:?}", prefix, realm));
+            }
+            settings.property = Some(Property {
+                id: id.to_string(),
+                realm,
+            });
+            properties.push(settings);
+        }
+
+        // A realm ending another would let one property see the other's
+        // credentials.
+        let realms: Vec<&str> = properties
+            .iter()
+            .filter_map(|p| p.property.as_ref())
+            .map(|p| p.realm.as_str())
+            .collect();
+        for (i, a) in realms.iter().enumerate() {
+            if let Some(b) = realms[i + 1..]
+                .iter()
+                .find(|b| a.ends_with(**b) || b.ends_with(*a))
+            {
+                return Err(anyhow!("realms {} and {} overlap", a, b));
+            }
+        }
+        Ok(properties)
+    }
+
+    /// Append the property realm to a room-based `username`.
+    pub fn username(&self, base: &str) -> String {
+        match &self.property {
+            Some(property) => format!("{}{}", base, property.realm),
+            None => base.to_string(),
+        }
+    }
+}
+
+pub fn init_service_settings(settings: ServiceSettings) {
+    SERVICE_SETTINGS
+        .set(Arc::new(settings))
+        .expect("❌ SERVICE_SETTINGS is already initialized");
+}
+
+pub fn service_settings() -> Arc<ServiceSettings> {
+    SERVICE_SETTINGS
+        .get()
+        .expect("❌ SERVICE_SETTINGS is not initialized")
+        .clone()
+}
+
+pub fn init_property_settings(properties: Vec<ServiceSettings>) {
+    PROPERTY_SETTINGS
+        .set(properties.into_iter().map(Arc::new).collect())
+        .expect("❌ PROPERTY_SETTINGS is already initialized");
+}
+
+/// Settings of every property served: each configured property, or the
+/// shared settings of a single-property install.
+pub fn tenant_settings() -> Vec<Arc<ServiceSettings>> {
+    match PROPERTY_SETTINGS.get() {
+        Some(properties) if !properties.is_empty() => properties.clone(),
+        _ => vec![service_settings()],
+    }
+}
+
+/// Settings for a request naming `property`, or none; a multi-property
+/// install needs one named.
+pub fn settings_for(property: Option<&str>) -> Result<Arc<ServiceSettings>, ErrorResponse> {
+    let properties = PROPERTY_SETTINGS.get().map_or(&[][..], Vec::as_slice);
+    match (property, properties.is_empty()) {
+        (None, true) => Ok(service_settings()),
+        (None, false) => Err(ErrorResponse::Validation("property is required".into())),
+        (Some(id), _) => properties
+            .iter()
+            .find(|p| p.property.as_ref().is_some_and(|p| p.id == id))
+            .cloned()
+            .ok_or_else(|| ErrorResponse::NotFound(format!("property {} not found", id))),
+    }
+}
+
+/// RADIUS attributes written for every stay besides the password.
+#[derive(Debug, Clone)]
+pub struct RadiusSettings {
+    /// Hotel `user_type`, password attribute and `services.cron_type`.
+    pub schema: RadiusSchema,
+    /// Write an `Expiration` check attribute at the checkout datetime.
+    pub expiration: bool,
+    /// radreply templates; values may contain [`STAY_SECONDS`].
+    pub reply_attributes: Vec<RadiusAttribute>,
+}
+
+impl Default for RadiusSettings {
+    fn default() -> Self {
+        Self {
+            schema: RadiusSchema::default(),
+            expiration: true,
+            reply_attributes: Vec::new(),
+        }
+    }
+}
+
+impl RadiusSettings {
+    /// Reads `RADIUS_USER_TYPE`, `RADIUS_PASSWORD_STORAGE` (`cleartext`,
+    /// `nt`, `ssha512` or `crypt`), `HOTEL_SERVICE_CRON_TYPE`, `RADIUS_EXPIRATION` and
+    /// `RADIUS_REPLY_ATTRIBUTES`, the latter as `;`-separated entries like
+    /// `Session-Timeout:={stay_seconds}`.
+    pub fn from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut settings = Self::default();
+
+        if let Some(v) = var("RADIUS_USER_TYPE") {
+            settings.schema.user_type = v.trim().to_string();
+        }
+        if let Some(v) = var("RADIUS_PASSWORD_STORAGE") {
+            settings.schema.password_storage = PasswordStorage::parse(&v)
+                .ok_or_else(|| anyhow!("invalid RADIUS_PASSWORD_STORAGE {:?}", v))?;
+        }
+        if let Some(v) = var("HOTEL_SERVICE_CRON_TYPE") {
+            settings.schema.service_type = v.trim().to_string();
+        }
+
+        if let Some(v) = var("RADIUS_EXPIRATION") {
+            settings.expiration = !matches!(v.trim(), "0" | "false" | "no");
+        }
+
+        if let Some(v) = var("RADIUS_REPLY_ATTRIBUTES") {
+            settings.reply_attributes = parse_attributes(&v)?;
+        }
+
+        Ok(settings)
+    }
+
+    pub fn check_attributes(&self, checkout: NaiveDateTime) -> Vec<RadiusAttribute> {
+        if !self.expiration {
+            return Vec::new();
+        }
+        vec![RadiusAttribute::new(
+            "Expiration",
+            ":=",
+            checkout.format(EXPIRATION_FORMAT).to_string(),
+        )]
+    }
+
+    pub fn reply_attributes(
+        &self,
+        now: NaiveDateTime,
+        checkout: NaiveDateTime,
+    ) -> Vec<RadiusAttribute> {
+        let stay_seconds = (checkout - now).num_seconds().max(1).to_string();
+        self.reply_attributes
+            .iter()
+            .map(|a| RadiusAttribute {
+                value: a.value.replace(STAY_SECONDS, &stay_seconds),
+                ..a.clone()
+            })
+            .collect()
+    }
+}
+
+/// Maps PMS guest types (`gtype`) to hotel service names.
+#[derive(Debug, Clone, Default)]
+pub struct GuestTypeSettings {
+    /// Upper-cased gtype -> `services.service_name`.
+    pub services: HashMap<String, String>,
+    /// Service used when the gtype is missing or unmapped; the first active
+    /// hotel service is used when this is unset too.
+    pub default_service: Option<String>,
+}
+
+impl GuestTypeSettings {
+    /// Reads `GUEST_TYPE_SERVICES` as `VIP=Hotel Premium;CORP=Hotel Business`
+    /// and `DEFAULT_HOTEL_SERVICE`.
+    pub fn from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let services = match var("GUEST_TYPE_SERVICES") {
+            Some(v) => parse_guest_types(&v)?,
+            None => HashMap::new(),
+        };
+
+        let default_service = var("DEFAULT_HOTEL_SERVICE")
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+
+        Ok(Self {
+            services,
+            default_service,
+        })
+    }
+
+    pub fn service_for(&self, gtype: &str) -> Option<&str> {
+        self.services
+            .get(&gtype.trim().to_uppercase())
+            .map(String::as_str)
+    }
+}
+
+fn parse_guest_types(raw: &str) -> Result<HashMap<String, String>> {
+    raw.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (gtype, service) = entry
+                .split_once('=')
+                .map(|(g, s)| (g.trim(), s.trim()))
+                .filter(|(g, s)| !g.is_empty() && !s.is_empty())
+                .ok_or_else(|| anyhow!("invalid guest type mapping {:?}", entry))?;
+            Ok((gtype.to_uppercase(), service.to_string()))
+        })
+        .collect()
+}
+
+/// How long a successful PMS request is remembered for replay detection.
+#[derive(Clone)]
+pub struct IdempotencySettings {
+    /// `0` disables replay detection.
+    pub window_secs: i64,
+    /// HMAC key for request fingerprints. Without `IDEMPOTENCY_SECRET` a
+    /// random key is drawn at startup, so requests made before a restart
+    /// are no longer recognised by payload.
+    pub secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for IdempotencySettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdempotencySettings")
+            .field("window_secs", &self.window_secs)
+            .field("secret", &"***")
+            .finish()
+    }
+}
+
+impl Default for IdempotencySettings {
+    fn default() -> Self {
+        let mut secret = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self {
+            window_secs: 600,
+            secret,
+        }
+    }
+}
+
+impl IdempotencySettings {
+    /// Reads `IDEMPOTENCY_WINDOW_SECS` and `IDEMPOTENCY_SECRET`.
+    pub fn from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut settings = Self::default();
+        if let Some(v) = var("IDEMPOTENCY_WINDOW_SECS") {
+            settings.window_secs = v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid IDEMPOTENCY_WINDOW_SECS {:?}", v))?;
+        }
+        if let Some(v) = var("IDEMPOTENCY_SECRET").filter(|v| !v.is_empty()) {
+            settings.secret = v.into_bytes();
+        }
+        Ok(settings)
+    }
+}
+
+/// How many guests a room takes and how additional guests log in.
+#[derive(Debug, Clone)]
+pub struct RoomSettings {
+    /// `1` rejects a second checkin into an occupied room.
+    pub max_guests: usize,
+    /// Additional guests share the room's credential instead of getting
+    /// their own `{room}-{n}` username, unless the PMS asks otherwise.
+    pub shared_credential: bool,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        Self {
+            max_guests: 1,
+            shared_credential: false,
+        }
+    }
+}
+
+impl RoomSettings {
+    /// Reads `ROOM_MAX_GUESTS` and `ROOM_SHARED_CREDENTIAL`.
+    pub fn from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut settings = Self::default();
+        if let Some(v) = var("ROOM_MAX_GUESTS") {
+            settings.max_guests = v
+                .trim()
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("invalid ROOM_MAX_GUESTS {:?}", v))?;
+        }
+        if let Some(v) = var("ROOM_SHARED_CREDENTIAL") {
+            settings.shared_credential = matches!(v.trim(), "1" | "true" | "yes");
+        }
+        Ok(settings)
+    }
+}
+
+/// How PMS dates are read and which clock the property runs on.
+#[derive(Debug, Clone)]
+pub struct DateSettings {
+    /// chrono formats tried in order for `cidate` / `codate`; ISO 8601 is
+    /// always accepted on top of these.
+    pub formats: Vec<String>,
+    /// Property timezone; `None` uses the server's local time.
+    pub timezone: Option<Tz>,
+    /// Checkout time used when the PMS sends neither `cotime` nor a time
+    /// within `codate`.
+    pub default_checkout: NaiveTime,
+}
+
+impl Default for DateSettings {
+    fn default() -> Self {
+        Self {
+            formats: vec!["%d/%m/%Y".to_string()],
+            timezone: None,
+            default_checkout: NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+        }
+    }
+}
+
+impl DateSettings {
+    /// Reads `PMS_DATE_FORMATS` as `,`-separated chrono formats like
+    /// `%d/%m/%Y,%m/%d/%Y`, `PMS_TIMEZONE` as an IANA name like
+    /// `Asia/Jakarta` and `PMS_DEFAULT_CHECKOUT_TIME` as `HH:MM[:SS]`.
+    pub fn from_vars(var: &impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut settings = Self::default();
+        if let Some(v) = var("PMS_DATE_FORMATS") {
+            let formats: Vec<String> = v
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(str::to_string)
+                .collect();
+            for format in &formats {
+                validate_date_format(format)
+                    .map_err(|e| anyhow!("invalid PMS_DATE_FORMATS: {}", e))?;
+            }
+            settings.formats = formats;
+        }
+        if let Some(v) = var("PMS_TIMEZONE") {
+            let v = v.trim();
+            if !v.is_empty() {
+                settings.timezone = Some(
+                    v.parse()
+                        .map_err(|_| anyhow!("invalid PMS_TIMEZONE {:?}", v))?,
+                );
+            }
+        }
+        if let Some(v) = var("PMS_DEFAULT_CHECKOUT_TIME") {
+            settings.default_checkout = parse_pms_time(&v)
+                .ok_or_else(|| anyhow!("invalid PMS_DEFAULT_CHECKOUT_TIME {:?}", v))?;
+        }
+        Ok(settings)
+    }
+
+    /// Current wall-clock time at the property.
+    pub fn now(&self) -> NaiveDateTime {
+        match self.timezone {
+            Some(tz) => Utc::now().with_timezone(&tz).naive_local(),
+            None => Local::now().naive_local(),
+        }
+    }
+
+    /// Parse `cidate`; a bare date checks in at the current time of day.
+    pub fn parse_checkin(&self, cidate: &str) -> Result<NaiveDateTime, ErrorResponse> {
+        let (date, time) =
+            parse_pms_date(cidate, &self.formats, self.timezone).ok_or_else(|| {
+                ErrorResponse::invalid_field(
+                    "cidate",
+                    format!("invalid checkin date {}", cidate.trim()),
+                )
+            })?;
+        Ok(date.and_time(time.unwrap_or_else(|| self.now().time())))
+    }
+
+    /// Parse `codate` and `cotime`; `cotime` wins over a time within `codate`,
+    /// and [`Self::default_checkout`] applies when neither is given.
+    pub fn parse_checkout(
+        &self,
+        codate: &str,
+        cotime: Option<&str>,
+    ) -> Result<NaiveDateTime, ErrorResponse> {
+        let (date, time) =
+            parse_pms_date(codate, &self.formats, self.timezone).ok_or_else(|| {
+                ErrorResponse::invalid_field(
+                    "codate",
+                    format!("invalid checkout date {}", codate.trim()),
+                )
+            })?;
+        let time = match cotime.map(str::trim).filter(|t| !t.is_empty()) {
+            Some(t) => parse_pms_time(t).ok_or_else(|| {
+                ErrorResponse::invalid_field("cotime", format!("invalid checkout time {}", t))
+            })?,
+            None => time.unwrap_or(self.default_checkout),
+        };
+        Ok(date.and_time(time))
+    }
+}
+
+/// Parse `Attr:=value;Attr=value` into attributes.
+pub fn parse_attributes(raw: &str) -> Result<Vec<RadiusAttribute>> {
+    raw.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(parse_attribute)
+        .collect()
+}
+
+fn parse_attribute(entry: &str) -> Result<RadiusAttribute> {
+    let eq = entry
+        .find('=')
+        .ok_or_else(|| anyhow!("invalid radius attribute {:?}", entry))?;
+
+    let (name_end, value_start) = match (entry[..eq].chars().last(), &entry[eq + 1..]) {
+        (Some(':' | '+'), _) => (eq - 1, eq + 1),
+        (_, rest) if rest.starts_with('=') => (eq, eq + 2),
+        _ => (eq, eq + 1),
+    };
+
+    let attribute = entry[..name_end].trim();
+    if attribute.is_empty() {
+        return Err(anyhow!("invalid radius attribute {:?}", entry));
+    }
+
+    Ok(RadiusAttribute::new(
+        attribute,
+        &entry[name_end..value_start],
+        entry[value_start..].trim(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn parses_attribute_list() {
+        let attrs = parse_attributes(
+            "Session-Timeout:={stay_seconds}; Mikrotik-Rate-Limit = 2M/4M;WISPr-Bandwidth-Max-Down==4000000",
+        )
+        .unwrap();
+
+        assert_eq!(
+            attrs,
+            vec![
+                RadiusAttribute::new("Session-Timeout", ":=", "{stay_seconds}"),
+                RadiusAttribute::new("Mikrotik-Rate-Limit", "=", "2M/4M"),
+                RadiusAttribute::new("WISPr-Bandwidth-Max-Down", "==", "4000000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_guest_type_mapping() {
+        let settings = GuestTypeSettings {
+            services: parse_guest_types("vip=Hotel Premium; CORP = Hotel Business;").unwrap(),
+            default_service: None,
+        };
+
+        assert_eq!(settings.service_for("VIP"), Some("Hotel Premium"));
+        assert_eq!(settings.service_for(" corp "), Some("Hotel Business"));
+        assert_eq!(settings.service_for("group"), None);
+        assert!(parse_guest_types("VIP").is_err());
+    }
+
+    #[test]
+    fn parses_configured_date_formats() {
+        let settings = DateSettings {
+            formats: vec!["%m/%d/%Y".into(), "%d.%m.%Y".into()],
+            ..DateSettings::default()
+        };
+        let at = |y, m, d, h, min| {
+            NaiveDate::from_ymd_opt(y, m, d)
+                .unwrap()
+                .and_hms_opt(h, min, 0)
+                .unwrap()
+        };
+
+        assert_eq!(
+            settings.parse_checkout("11/22/2025", None).unwrap(),
+            at(2025, 11, 22, 13, 0)
+        );
+        assert_eq!(
+            settings
+                .parse_checkout("22.11.2025", Some("11:30"))
+                .unwrap(),
+            at(2025, 11, 22, 11, 30)
+        );
+        assert_eq!(
+            settings
+                .parse_checkout("2025-11-22T10:15:00", None)
+                .unwrap(),
+            at(2025, 11, 22, 10, 15)
+        );
+        assert_eq!(
+            settings.parse_checkin("11/20/2025 14:30").unwrap(),
+            at(2025, 11, 20, 14, 30)
+        );
+        assert_eq!(
+            settings
+                .parse_checkout("22/11/2025", None)
+                .unwrap_err()
+                .to_string(),
+            "invalid checkout date 22/11/2025"
+        );
+        assert_eq!(
+            settings
+                .parse_checkout("2025-11-22", Some("noon"))
+                .unwrap_err()
+                .to_string(),
+            "invalid checkout time noon"
+        );
+    }
+
+    #[test]
+    fn converts_offsets_to_property_timezone() {
+        let settings = DateSettings {
+            timezone: Some(chrono_tz::Asia::Jakarta),
+            default_checkout: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            ..DateSettings::default()
+        };
+
+        assert_eq!(
+            settings.parse_checkin("2025-11-20T07:30:00Z").unwrap(),
+            NaiveDate::from_ymd_opt(2025, 11, 20)
+                .unwrap()
+                .and_hms_opt(14, 30, 0)
+                .unwrap()
+        );
+        assert_eq!(
+            settings.parse_checkout("2025-11-22", None).unwrap().time(),
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_date_formats() {
+        assert!(validate_date_format("%d/%m/%Y").is_ok());
+        assert!(validate_date_format("%Y-%m").is_err());
+        assert!(validate_date_format("%Q").is_err());
+    }
+
+    #[test]
+    fn rejects_attribute_without_operator() {
+        assert!(parse_attributes("Session-Timeout").is_err());
+        assert!(parse_attributes(":=3600").is_err());
+    }
+
+    #[test]
+    fn reads_property_overrides_and_realms() {
+        let env = HashMap::from([
+            ("PROPERTIES", "madrid, lisbon"),
+            ("DEFAULT_HOTEL_SERVICE", "Hotel Standard"),
+            ("PROPERTY_MADRID_DEFAULT_HOTEL_SERVICE", "Madrid Standard"),
+            ("PROPERTY_LISBON_REALM", "@lis.example.com"),
+        ]);
+        let var = |name: &str| env.get(name).map(|v| v.to_string());
+        let properties = ServiceSettings::properties_from_vars(&var).unwrap();
+
+        assert_eq!(properties.len(), 2);
+        let (madrid, lisbon) = (&properties[0], &properties[1]);
+        assert_eq!(madrid.property.as_ref().unwrap().id, "madrid");
+        assert_eq!(
+            madrid.guest_types.default_service.as_deref(),
+            Some("Madrid Standard")
+        );
+        assert_eq!(
+            lisbon.guest_types.default_service.as_deref(),
+            Some("Hotel Standard")
+        );
+        assert_eq!(madrid.username("101"), "101@madrid");
+        assert_eq!(lisbon.username("101-2"), "101-2@lis.example.com");
+        assert_eq!(ServiceSettings::default().username("101"), "101");
+
+        let none = |_: &str| None;
+        assert!(
+            ServiceSettings::properties_from_vars(&none)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rejects_ambiguous_properties() {
+        let parse = |pairs: &[(&str, &str)]| {
+            let env: HashMap<&str, &str> = pairs.iter().copied().collect();
+            ServiceSettings::properties_from_vars(&|name: &str| {
+                env.get(name).map(|v| v.to_string())
+            })
+        };
+
+        assert!(parse(&[("PROPERTIES", "madrid,MADRID")]).is_err());
+        assert!(parse(&[("PROPERTIES", "ma drid")]).is_err());
+        assert!(parse(&[("PROPERTIES", "a,b"), ("PROPERTY_B_REALM", "@x@a")]).is_err());
+        assert!(parse(&[("PROPERTIES", "a"), ("PROPERTY_A_REALM", "@a%")]).is_err());
+        assert!(parse(&[("PROPERTIES", "a"), ("PROPERTY_A_ROOM_MAX_GUESTS", "x")]).is_err());
+    }
+}
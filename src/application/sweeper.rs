@@ -0,0 +1,89 @@
+use crate::application::metrics::METRICS;
+use crate::application::services::BookingService;
+use crate::config::var;
+use crate::domain::repositories::BookingRepository;
+use anyhow::{Result, anyhow};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct ExpirySettings {
+    pub interval: Duration,
+    /// How long past checkout a stay is kept before it is removed.
+    pub grace_secs: i64,
+}
+
+impl ExpirySettings {
+    /// Reads `EXPIRY_SWEEP_INTERVAL_SECS` (default 300) and `EXPIRY_GRACE_SECS`
+    /// (default 3600). Returns `None` when the interval is `0`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let interval = match var("EXPIRY_SWEEP_INTERVAL_SECS") {
+            Some(v) => v
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid EXPIRY_SWEEP_INTERVAL_SECS {:?}", v))?,
+            _ => 300,
+        };
+        if interval == 0 {
+            return Ok(None);
+        }
+
+        let grace_secs = match var("EXPIRY_GRACE_SECS") {
+            Some(v) => v
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|g| *g >= 0)
+                .ok_or_else(|| anyhow!("invalid EXPIRY_GRACE_SECS {:?}", v))?,
+            _ => 3600,
+        };
+
+        Ok(Some(Self {
+            interval: Duration::from_secs(interval),
+            grace_secs,
+        }))
+    }
+}
+
+/// Periodically checks out stays the PMS never checked out.
+pub async fn run<R: BookingRepository + ?Sized>(
+    settings: ExpirySettings,
+    service: Arc<BookingService<R>>,
+) {
+    tracing::info!(
+        "expiry sweeper running every {:?} with {}s grace",
+        settings.interval,
+        settings.grace_secs
+    );
+
+    let mut ticker = tokio::time::interval(settings.interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        sweep(&settings, &service).await;
+    }
+}
+
+async fn sweep<R: BookingRepository + ?Sized>(
+    settings: &ExpirySettings,
+    service: &BookingService<R>,
+) {
+    match service.expire_overdue(settings.grace_secs).await {
+        Ok(guests) if guests.is_empty() => {}
+        Ok(guests) => {
+            METRICS.expired_checkouts.inc_by(guests.len() as u64);
+            let total = METRICS.expired_checkouts.get();
+            tracing::info!(
+                "expiry sweep checked out {} guest(s) {:?}, {} since startup",
+                guests.len(),
+                guests,
+                total
+            );
+        }
+        Err(err) => {
+            METRICS.failed_sweeps.inc();
+            tracing::error!("expiry sweep failed: {}", err);
+        }
+    }
+}
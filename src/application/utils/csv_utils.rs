@@ -0,0 +1,45 @@
+use anyhow::{Result, anyhow};
+
+/// Split CSV text into records of fields. Fields may be quoted with `"`,
+/// with `""` standing for a literal quote; blank lines are skipped.
+pub fn parse_records(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                push_record(&mut records, std::mem::take(&mut record));
+            }
+            '\r' if !quoted => {}
+            c => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(anyhow!("unterminated quoted field"));
+    }
+    record.push(field);
+    push_record(&mut records, record);
+    Ok(records)
+}
+
+fn push_record(records: &mut Vec<Vec<String>>, record: Vec<String>) {
+    if record.iter().any(|f| !f.trim().is_empty()) {
+        records.push(record.into_iter().map(|f| f.trim().to_string()).collect());
+    }
+}
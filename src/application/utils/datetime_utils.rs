@@ -1,33 +1,98 @@
 use anyhow::{Result, anyhow};
-use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
-
-/// Parse string date check-in into `NaiveDateTime`.
-/// Accepts format like `20/11/2025` or `20/11/2025 14:30:00`.
-pub fn parse_checkin_datetime(cidate_str: &str) -> Result<NaiveDateTime> {
-    NaiveDateTime::parse_from_str(cidate_str, "%d/%m/%Y %H:%M:%S")
-        .or_else(|_| {
-            NaiveDate::parse_from_str(cidate_str, "%d/%m/%Y").map(|d| {
-                let now_time = Local::now().naive_local().time();
-                d.and_time(now_time)
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+
+/// Date-time layouts always accepted besides the configured PMS formats.
+const ISO_DATETIME_FORMATS: [&str; 3] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Split a PMS date into its calendar date and, when present, time of day.
+/// ISO 8601 (`2025-11-20`, `2025-11-20T14:30:00`, `2025-11-20T14:30:00+07:00`)
+/// is always accepted; a UTC offset is converted to `tz`, or to server-local
+/// time when no timezone is configured. Otherwise each of `formats` is tried,
+/// alone or followed by ` %H:%M:%S` / ` %H:%M`.
+pub fn parse_pms_date(
+    value: &str,
+    formats: &[String],
+    tz: Option<Tz>,
+) -> Option<(NaiveDate, Option<NaiveTime>)> {
+    let value = value.trim();
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        let local = match tz {
+            Some(tz) => dt.with_timezone(&tz).naive_local(),
+            None => dt.with_timezone(&Local).naive_local(),
+        };
+        return Some((local.date(), Some(local.time())));
+    }
+
+    let with_time = |dt: NaiveDateTime| (dt.date(), Some(dt.time()));
+    if let Some(dt) = ISO_DATETIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(value, f).ok())
+    {
+        return Some(with_time(dt));
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some((d, None));
+    }
+
+    formats.iter().find_map(|f| {
+        NaiveDate::parse_from_str(value, f)
+            .map(|d| (d, None))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(value, &format!("{f} %H:%M:%S")).map(with_time)
             })
-        })
-        .map_err(|_| anyhow!("invalid checkin date format"))
+            .or_else(|_| NaiveDateTime::parse_from_str(value, &format!("{f} %H:%M")).map(with_time))
+            .ok()
+    })
 }
 
-/// Parse string date checkout into `NaiveDateTime` (combined date + time).
-/// Default checkout time: `13:00:00` if `cotime` is not provided.
-pub fn parse_checkout_datetime(
-    codate_str: &str,
-    cotime_str: Option<&str>,
-) -> Result<NaiveDateTime> {
-    let check_out_date = NaiveDate::parse_from_str(codate_str, "%d/%m/%Y")
-        .map_err(|_| anyhow!("invalid checkout date format"))?;
-
-    let check_out_time = match cotime_str {
-        Some(t) if !t.trim().is_empty() => NaiveTime::parse_from_str(t.trim(), "%H:%M:%S")
-            .map_err(|_| anyhow!("invalid checkout time format"))?,
-        _ => NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
-    };
+/// Parse a PMS time of day, `HH:MM:SS` or `HH:MM`.
+pub fn parse_pms_time(value: &str) -> Option<NaiveTime> {
+    let value = value.trim();
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+        .ok()
+}
+
+/// Check that `format` is a strftime pattern able to produce a full date.
+pub fn validate_date_format(format: &str) -> Result<()> {
+    let sample = NaiveDate::from_ymd_opt(2025, 11, 20).unwrap();
+    let items = chrono::format::StrftimeItems::new(format)
+        .parse()
+        .map_err(|_| anyhow!("invalid date format {:?}", format))?;
+    let rendered = sample.format_with_items(items.iter()).to_string();
+    match NaiveDate::parse_from_str(&rendered, format) {
+        Ok(d) if d == sample => Ok(()),
+        _ => Err(anyhow!(
+            "date format {:?} does not describe a full date",
+            format
+        )),
+    }
+}
 
-    Ok(check_out_date.and_time(check_out_time))
+/// Parse a query range bound: `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS`.
+/// A bare date used as an upper bound (`end = true`) covers the whole day,
+/// i.e. it resolves to midnight of the following day.
+pub fn parse_range_bound(value: &str, end: bool) -> Result<NaiveDateTime> {
+    let value = value.trim();
+    if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+    {
+        return Ok(dt);
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| anyhow!("invalid date {}", value))?;
+    let date = if end {
+        date.succ_opt()
+            .ok_or_else(|| anyhow!("invalid date {}", value))?
+    } else {
+        date
+    };
+    Ok(date.and_time(NaiveTime::MIN))
 }
@@ -1,2 +1,4 @@
+pub mod csv_utils;
 pub mod datetime_utils;
+pub mod password_utils;
 pub mod string_utils;
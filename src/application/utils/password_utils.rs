@@ -0,0 +1,101 @@
+use crate::domain::entities::PasswordStorage;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use md4::Md4;
+use rand::RngCore;
+use sha2::{Digest, Sha512};
+
+const SALT_LEN: usize = 8;
+
+/// `password` in the form radcheck stores it for `storage`. Salted forms
+/// give a new value on every call.
+pub fn hash_password(storage: PasswordStorage, password: &str) -> String {
+    match storage {
+        PasswordStorage::Cleartext => password.to_string(),
+        PasswordStorage::NtHash => nt_hash(password),
+        PasswordStorage::Ssha512 => {
+            let mut salt = [0u8; SALT_LEN];
+            rand::thread_rng().fill_bytes(&mut salt);
+            ssha512(password, &salt)
+        }
+        PasswordStorage::Crypt => {
+            pwhash::sha512_crypt::hash(password).expect("sha512_crypt with default parameters")
+        }
+    }
+}
+
+/// Whether `password` is the one `stored` was made from.
+pub fn verify_password(storage: PasswordStorage, password: &str, stored: &str) -> bool {
+    match storage {
+        PasswordStorage::Cleartext => password == stored,
+        PasswordStorage::NtHash => nt_hash(password).eq_ignore_ascii_case(stored.trim()),
+        PasswordStorage::Ssha512 => match STANDARD.decode(stored.trim()) {
+            Ok(bytes) if bytes.len() > Sha512::output_size() => {
+                let salt = &bytes[Sha512::output_size()..];
+                ssha512(password, salt) == stored.trim()
+            }
+            _ => false,
+        },
+        PasswordStorage::Crypt => pwhash::sha512_crypt::verify(password, stored.trim()),
+    }
+}
+
+fn nt_hash(password: &str) -> String {
+    let utf16: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    format!("{:x}", Md4::digest(&utf16))
+}
+
+fn ssha512(password: &str, salt: &[u8]) -> String {
+    let mut bytes = Sha512::new()
+        .chain_update(password.as_bytes())
+        .chain_update(salt)
+        .finalize()
+        .to_vec();
+    bytes.extend_from_slice(salt);
+    STANDARD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nt_hash_matches_known_value() {
+        assert_eq!(
+            hash_password(PasswordStorage::NtHash, "password"),
+            "8846f7eaee8fb117ad06bdd830b7586c"
+        );
+        assert!(verify_password(
+            PasswordStorage::NtHash,
+            "password",
+            "8846F7EAEE8FB117AD06BDD830B7586C"
+        ));
+    }
+
+    #[test]
+    fn salted_forms_verify_and_differ_per_call() {
+        for storage in [PasswordStorage::Ssha512, PasswordStorage::Crypt] {
+            let first = hash_password(storage, "smith");
+            let second = hash_password(storage, "smith");
+            assert_ne!(first, second, "{:?}", storage);
+            assert!(verify_password(storage, "smith", &first), "{:?}", storage);
+            assert!(verify_password(storage, "smith", &second), "{:?}", storage);
+            assert!(!verify_password(storage, "jones", &first), "{:?}", storage);
+        }
+        assert!(hash_password(PasswordStorage::Crypt, "smith").starts_with("$6$"));
+    }
+
+    #[test]
+    fn cleartext_is_stored_as_is() {
+        assert_eq!(hash_password(PasswordStorage::Cleartext, "smith"), "smith");
+        assert!(verify_password(
+            PasswordStorage::Cleartext,
+            "smith",
+            "smith"
+        ));
+        assert!(!verify_password(
+            PasswordStorage::Ssha512,
+            "smith",
+            "not base64!"
+        ));
+    }
+}
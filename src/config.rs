@@ -0,0 +1,620 @@
+//! Layered configuration: built-in defaults, then an optional TOML file,
+//! then environment variables. Each file setting maps to the environment
+//! variable that overrides it, so the settings structs read both through
+//! [`var`] and validate the result the same way.
+
+use anyhow::{Context, Result, anyhow};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// File read when `VHP_CONFIG` is not set; it is optional.
+const DEFAULT_CONFIG_FILE: &str = "vhp.toml";
+
+pub static CONFIG_LAYER: OnceCell<HashMap<String, String>> = OnceCell::new();
+
+/// Load the file named by `VHP_CONFIG`, or `vhp.toml` when present. Returns
+/// the path that was read, if any.
+pub fn init_config() -> Result<Option<PathBuf>> {
+    let (path, required) = match std::env::var("VHP_CONFIG") {
+        Ok(p) if !p.trim().is_empty() => (PathBuf::from(p.trim()), true),
+        _ => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+    };
+    if !required && !path.exists() {
+        CONFIG_LAYER
+            .set(HashMap::new())
+            .expect("❌ CONFIG_LAYER is already initialized");
+        return Ok(None);
+    }
+
+    let layer = AppConfig::from_file(&path)?.into_layer();
+    CONFIG_LAYER
+        .set(layer)
+        .expect("❌ CONFIG_LAYER is already initialized");
+    Ok(Some(path))
+}
+
+/// Value of setting `name`: the environment variable when set and not
+/// blank, otherwise the config file entry.
+pub fn var(name: &str) -> Option<String> {
+    let env = std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+    env.or_else(|| {
+        CONFIG_LAYER
+            .get()
+            .and_then(|layer| layer.get(name).cloned())
+    })
+}
+
+/// Contents of the TOML config file; every setting is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub log: LogConfig,
+    pub database: DatabaseConfig,
+    pub radius: RadiusConfig,
+    pub hotel_services: HotelServicesConfig,
+    pub idempotency: IdempotencyConfig,
+    pub rooms: RoomsConfig,
+    pub dates: DatesConfig,
+    pub expiry: ExpiryConfig,
+    pub fias: FiasConfig,
+    pub pms: ClientsConfig,
+    pub admin: ClientsConfig,
+    pub portal: PortalConfig,
+    /// `PROPERTIES` and `PROPERTY_<ID>_*`.
+    pub properties: BTreeMap<String, PropertyConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// `APP_HOST`
+    pub host: Option<String>,
+    /// `APP_PORT`
+    pub port: Option<u16>,
+    /// `SHUTDOWN_GRACE_SECS`
+    pub shutdown_grace_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `LOG_LEVEL`
+    pub level: Option<String>,
+    /// `LOG_FORMAT`
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// `DATABASE_URL`
+    pub url: Option<String>,
+    /// `DB_MAX_CONNECTIONS`
+    pub max_connections: Option<u32>,
+    /// `DB_MIN_CONNECTIONS`
+    pub min_connections: Option<u32>,
+    /// `DB_ACQUIRE_TIMEOUT_SECS`
+    pub acquire_timeout_secs: Option<u64>,
+    /// `DB_IDLE_TIMEOUT_SECS`
+    pub idle_timeout_secs: Option<u64>,
+    /// `DB_CONNECT_ATTEMPTS`
+    pub connect_attempts: Option<u32>,
+    /// `DB_CONNECT_BACKOFF_MS`
+    pub connect_backoff_ms: Option<u64>,
+    /// `DB_CONNECT_MAX_BACKOFF_MS`
+    pub connect_max_backoff_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadiusConfig {
+    /// `RADIUS_USER_TYPE`
+    pub user_type: Option<String>,
+    /// `RADIUS_PASSWORD_STORAGE`
+    pub password_storage: Option<String>,
+    /// `RADIUS_EXPIRATION`
+    pub expiration: Option<bool>,
+    /// `RADIUS_REPLY_ATTRIBUTES`, one `Attr:=value` per entry.
+    pub reply_attributes: Option<Vec<String>>,
+    /// `RADIUS_NAS_CLIENTS`, NAS `ip[:port]` -> secret.
+    pub nas_clients: Option<BTreeMap<String, String>>,
+    /// `RADIUS_DISCONNECT_TIMEOUT_MS`
+    pub disconnect_timeout_ms: Option<u64>,
+    /// `RADIUS_DISCONNECT_RETRIES`
+    pub disconnect_retries: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotelServicesConfig {
+    /// `HOTEL_SERVICE_CRON_TYPE`, the `services.cron_type` of hotel plans.
+    pub cron_type: Option<String>,
+    /// `DEFAULT_HOTEL_SERVICE`
+    pub default: Option<String>,
+    /// `GUEST_TYPE_SERVICES`, gtype -> `services.service_name`.
+    pub guest_types: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    /// `IDEMPOTENCY_WINDOW_SECS`
+    pub window_secs: Option<i64>,
+    /// `IDEMPOTENCY_SECRET`
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomsConfig {
+    /// `ROOM_MAX_GUESTS`
+    pub max_guests: Option<usize>,
+    /// `ROOM_SHARED_CREDENTIAL`
+    pub shared_credential: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatesConfig {
+    /// `PMS_DATE_FORMATS`
+    pub formats: Option<Vec<String>>,
+    /// `PMS_TIMEZONE`
+    pub timezone: Option<String>,
+    /// `PMS_DEFAULT_CHECKOUT_TIME`
+    pub default_checkout_time: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpiryConfig {
+    /// `EXPIRY_SWEEP_INTERVAL_SECS`
+    pub sweep_interval_secs: Option<u64>,
+    /// `EXPIRY_GRACE_SECS`
+    pub grace_secs: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiasConfig {
+    /// `FIAS_HOST`
+    pub host: Option<String>,
+    /// `FIAS_PORT`
+    pub port: Option<u16>,
+    /// `FIAS_RESYNC_ON_LINK`
+    pub resync_on_link: Option<bool>,
+    /// `FIAS_HEARTBEAT_SECS`
+    pub heartbeat_secs: Option<u64>,
+    /// `FIAS_PROPERTY`
+    pub property: Option<String>,
+    /// `FIAS_ALLOWED_IPS`
+    pub allowed_ips: Vec<String>,
+}
+
+/// `[portal]`: guest self-service login on `/portal/login`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortalConfig {
+    /// `PORTAL_ENABLED`
+    pub enabled: Option<bool>,
+    /// `PORTAL_MAX_ATTEMPTS`
+    pub max_attempts: Option<u32>,
+    /// `PORTAL_WINDOW_SECS`
+    pub window_secs: Option<u32>,
+    /// `PORTAL_MAX_FAILURES`
+    pub max_failures: Option<u32>,
+    /// `PORTAL_LOCKOUT_SECS`
+    pub lockout_secs: Option<u32>,
+    /// `PORTAL_TRUSTED_PROXIES`
+    pub trusted_proxies: Vec<String>,
+}
+
+/// `[pms]` or `[admin]`: callers allowed on `/vhp` or `/admin`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientsConfig {
+    /// `<SCOPE>_AUTH_MAX_SKEW_SECS`
+    pub max_skew_secs: Option<i64>,
+    /// `<SCOPE>_CLIENTS` and `<SCOPE>_CLIENT_<NAME>_*`.
+    pub clients: BTreeMap<String, ClientConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub api_key: Option<String>,
+    pub hmac_secret: Option<String>,
+    pub allowed_ips: Vec<String>,
+    pub property: Option<String>,
+}
+
+/// `[properties.<id>]`: one hotel of a multi-property install. Its sections
+/// override the shared ones; the NAS and disconnect settings stay shared.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PropertyConfig {
+    /// `PROPERTY_<ID>_REALM`
+    pub realm: Option<String>,
+    pub radius: RadiusConfig,
+    pub hotel_services: HotelServicesConfig,
+    pub idempotency: IdempotencyConfig,
+    pub rooms: RoomsConfig,
+    pub dates: DatesConfig,
+}
+
+impl AppConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|err| anyhow!("{}", err.message()))
+    }
+
+    /// Flatten into the environment variables each setting stands for.
+    pub fn into_layer(self) -> HashMap<String, String> {
+        let mut layer = HashMap::new();
+        let mut put = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                layer.insert(name.to_string(), value);
+            }
+        };
+        let text = |v: Option<u64>| v.map(|v| v.to_string());
+
+        put("APP_HOST", self.server.host);
+        put("APP_PORT", self.server.port.map(|v| v.to_string()));
+        put("SHUTDOWN_GRACE_SECS", text(self.server.shutdown_grace_secs));
+        put("LOG_LEVEL", self.log.level);
+        put("LOG_FORMAT", self.log.format);
+
+        let db = self.database;
+        put("DATABASE_URL", db.url);
+        put(
+            "DB_MAX_CONNECTIONS",
+            db.max_connections.map(|v| v.to_string()),
+        );
+        put(
+            "DB_MIN_CONNECTIONS",
+            db.min_connections.map(|v| v.to_string()),
+        );
+        put("DB_ACQUIRE_TIMEOUT_SECS", text(db.acquire_timeout_secs));
+        put("DB_IDLE_TIMEOUT_SECS", text(db.idle_timeout_secs));
+        put(
+            "DB_CONNECT_ATTEMPTS",
+            db.connect_attempts.map(|v| v.to_string()),
+        );
+        put("DB_CONNECT_BACKOFF_MS", text(db.connect_backoff_ms));
+        put("DB_CONNECT_MAX_BACKOFF_MS", text(db.connect_max_backoff_ms));
+
+        let radius = &self.radius;
+        put("RADIUS_NAS_CLIENTS", radius.nas_clients.as_ref().map(pairs));
+        put(
+            "RADIUS_DISCONNECT_TIMEOUT_MS",
+            text(radius.disconnect_timeout_ms),
+        );
+        put(
+            "RADIUS_DISCONNECT_RETRIES",
+            radius.disconnect_retries.map(|v| v.to_string()),
+        );
+        for (name, value) in service_layer(
+            "",
+            &self.radius,
+            &self.hotel_services,
+            &self.idempotency,
+            &self.rooms,
+            &self.dates,
+        ) {
+            put(&name, value);
+        }
+
+        if !self.properties.is_empty() {
+            let ids: Vec<&str> = self.properties.keys().map(String::as_str).collect();
+            put("PROPERTIES", Some(ids.join(",")));
+        }
+        for (id, property) in &self.properties {
+            let prefix = format!("PROPERTY_{}_", env_name(id));
+            put(&format!("{}REALM", prefix), property.realm.clone());
+            for (name, value) in service_layer(
+                &prefix,
+                &property.radius,
+                &property.hotel_services,
+                &property.idempotency,
+                &property.rooms,
+                &property.dates,
+            ) {
+                put(&name, value);
+            }
+        }
+
+        put(
+            "EXPIRY_SWEEP_INTERVAL_SECS",
+            text(self.expiry.sweep_interval_secs),
+        );
+        put(
+            "EXPIRY_GRACE_SECS",
+            self.expiry.grace_secs.map(|v| v.to_string()),
+        );
+
+        put("FIAS_HOST", self.fias.host);
+        put("FIAS_PORT", self.fias.port.map(|v| v.to_string()));
+        put(
+            "FIAS_RESYNC_ON_LINK",
+            self.fias.resync_on_link.map(|v| v.to_string()),
+        );
+        put("FIAS_HEARTBEAT_SECS", text(self.fias.heartbeat_secs));
+        put("FIAS_PROPERTY", self.fias.property);
+        if !self.fias.allowed_ips.is_empty() {
+            put("FIAS_ALLOWED_IPS", Some(self.fias.allowed_ips.join(",")));
+        }
+
+        let portal = self.portal;
+        put("PORTAL_ENABLED", portal.enabled.map(|v| v.to_string()));
+        put(
+            "PORTAL_MAX_ATTEMPTS",
+            portal.max_attempts.map(|v| v.to_string()),
+        );
+        put(
+            "PORTAL_WINDOW_SECS",
+            portal.window_secs.map(|v| v.to_string()),
+        );
+        put(
+            "PORTAL_MAX_FAILURES",
+            portal.max_failures.map(|v| v.to_string()),
+        );
+        put(
+            "PORTAL_LOCKOUT_SECS",
+            portal.lockout_secs.map(|v| v.to_string()),
+        );
+        if !portal.trusted_proxies.is_empty() {
+            put(
+                "PORTAL_TRUSTED_PROXIES",
+                Some(portal.trusted_proxies.join(",")),
+            );
+        }
+
+        for (scope, clients) in [("PMS", self.pms), ("ADMIN", self.admin)] {
+            put(
+                &format!("{}_AUTH_MAX_SKEW_SECS", scope),
+                clients.max_skew_secs.map(|v| v.to_string()),
+            );
+            if clients.clients.is_empty() {
+                continue;
+            }
+            let names: Vec<&str> = clients.clients.keys().map(String::as_str).collect();
+            put(&format!("{}_CLIENTS", scope), Some(names.join(",")));
+            for (name, client) in &clients.clients {
+                let prefix = format!("{}_CLIENT_{}", scope, env_name(name));
+                put(&format!("{}_API_KEY", prefix), client.api_key.clone());
+                put(
+                    &format!("{}_HMAC_SECRET", prefix),
+                    client.hmac_secret.clone(),
+                );
+                if !client.allowed_ips.is_empty() {
+                    put(
+                        &format!("{}_ALLOWED_IPS", prefix),
+                        Some(client.allowed_ips.join(",")),
+                    );
+                }
+                put(&format!("{}_PROPERTY", prefix), client.property.clone());
+            }
+        }
+
+        layer
+    }
+}
+
+/// Settings [`ServiceSettings`](crate::application::settings::ServiceSettings)
+/// reads, named with `prefix` (`PROPERTY_<ID>_` for a property's overrides).
+fn service_layer(
+    prefix: &str,
+    radius: &RadiusConfig,
+    services: &HotelServicesConfig,
+    idempotency: &IdempotencyConfig,
+    rooms: &RoomsConfig,
+    dates: &DatesConfig,
+) -> Vec<(String, Option<String>)> {
+    let settings = [
+        ("RADIUS_USER_TYPE", radius.user_type.clone()),
+        ("RADIUS_PASSWORD_STORAGE", radius.password_storage.clone()),
+        (
+            "RADIUS_EXPIRATION",
+            radius.expiration.map(|v| v.to_string()),
+        ),
+        (
+            "RADIUS_REPLY_ATTRIBUTES",
+            radius.reply_attributes.as_ref().map(|v| v.join(";")),
+        ),
+        ("HOTEL_SERVICE_CRON_TYPE", services.cron_type.clone()),
+        ("DEFAULT_HOTEL_SERVICE", services.default.clone()),
+        (
+            "GUEST_TYPE_SERVICES",
+            services.guest_types.as_ref().map(pairs),
+        ),
+        (
+            "IDEMPOTENCY_WINDOW_SECS",
+            idempotency.window_secs.map(|v| v.to_string()),
+        ),
+        ("IDEMPOTENCY_SECRET", idempotency.secret.clone()),
+        ("ROOM_MAX_GUESTS", rooms.max_guests.map(|v| v.to_string())),
+        (
+            "ROOM_SHARED_CREDENTIAL",
+            rooms.shared_credential.map(|v| v.to_string()),
+        ),
+        (
+            "PMS_DATE_FORMATS",
+            dates.formats.as_ref().map(|v| v.join(",")),
+        ),
+        ("PMS_TIMEZONE", dates.timezone.clone()),
+        (
+            "PMS_DEFAULT_CHECKOUT_TIME",
+            dates.default_checkout_time.clone(),
+        ),
+    ];
+    settings
+        .into_iter()
+        .map(|(name, value)| (format!("{}{}", prefix, name), value))
+        .collect()
+}
+
+/// `key=value;key=value`, the list format of the environment variables.
+fn pairs(map: &BTreeMap<String, String>) -> String {
+    map.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Client name as it appears in `<SCOPE>_CLIENT_<NAME>_*` variables.
+pub fn env_name(client: &str) -> String {
+    client
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattens_file_into_settings() {
+        let layer = AppConfig::parse(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 5801
+
+            [log]
+            format = "json"
+
+            [radius]
+            user_type = "guest"
+            reply_attributes = ["Session-Timeout:={stay_seconds}", "Mikrotik-Rate-Limit:=2M/4M"]
+            nas_clients = { "10.0.0.1" = "s1", "10.0.0.2:1700" = "s2" }
+
+            [hotel_services]
+            guest_types = { VIP = "Hotel Premium" }
+
+            [dates]
+            formats = ["%d/%m/%Y", "%m/%d/%Y"]
+
+            [fias]
+            port = 5010
+            allowed_ips = ["10.0.0.7"]
+
+            [pms.clients.opera]
+            api_key = "k1"
+            allowed_ips = ["10.0.0.5", "192.168.1.0/24"]
+
+            [portal]
+            enabled = true
+            trusted_proxies = ["10.0.0.2", "172.16.0.0/12"]
+            "#,
+        )
+        .unwrap()
+        .into_layer();
+
+        let get = |name: &str| layer.get(name).map(String::as_str);
+        assert_eq!(get("APP_HOST"), Some("127.0.0.1"));
+        assert_eq!(get("APP_PORT"), Some("5801"));
+        assert_eq!(get("LOG_FORMAT"), Some("json"));
+        assert_eq!(get("LOG_LEVEL"), None);
+        assert_eq!(get("RADIUS_USER_TYPE"), Some("guest"));
+        assert_eq!(
+            get("RADIUS_REPLY_ATTRIBUTES"),
+            Some("Session-Timeout:={stay_seconds};Mikrotik-Rate-Limit:=2M/4M")
+        );
+        assert_eq!(
+            get("RADIUS_NAS_CLIENTS"),
+            Some("10.0.0.1=s1;10.0.0.2:1700=s2")
+        );
+        assert_eq!(get("GUEST_TYPE_SERVICES"), Some("VIP=Hotel Premium"));
+        assert_eq!(get("PMS_DATE_FORMATS"), Some("%d/%m/%Y,%m/%d/%Y"));
+        assert_eq!(get("FIAS_PORT"), Some("5010"));
+        assert_eq!(get("FIAS_ALLOWED_IPS"), Some("10.0.0.7"));
+        assert_eq!(get("PMS_CLIENTS"), Some("opera"));
+        assert_eq!(get("PMS_CLIENT_OPERA_API_KEY"), Some("k1"));
+        assert_eq!(
+            get("PMS_CLIENT_OPERA_ALLOWED_IPS"),
+            Some("10.0.0.5,192.168.1.0/24")
+        );
+        assert_eq!(get("ADMIN_CLIENTS"), None);
+        assert_eq!(get("PORTAL_ENABLED"), Some("true"));
+        assert_eq!(
+            get("PORTAL_TRUSTED_PROXIES"),
+            Some("10.0.0.2,172.16.0.0/12")
+        );
+        assert_eq!(get("PORTAL_LOCKOUT_SECS"), None);
+        assert_eq!(get("DATABASE_URL"), None);
+    }
+
+    #[test]
+    fn rejects_unknown_and_mistyped_settings() {
+        let err = AppConfig::parse("[database]\nmax_conections = 5\n").unwrap_err();
+        assert!(
+            err.to_string().contains("unknown field `max_conections`"),
+            "{}",
+            err
+        );
+
+        let err = AppConfig::parse("[server]\nport = \"http\"\n").unwrap_err();
+        assert!(err.to_string().contains("invalid type"), "{}", err);
+    }
+
+    #[test]
+    fn flattens_property_overrides() {
+        let layer = AppConfig::parse(
+            r#"
+            [hotel_services]
+            default = "Hotel Standard"
+
+            [properties.madrid]
+            realm = "@mad.example.com"
+            hotel_services = { default = "Madrid Standard" }
+            dates = { timezone = "Europe/Madrid" }
+
+            [properties.lisbon]
+
+            [pms.clients.opera-madrid]
+            api_key = "k1"
+            property = "madrid"
+            "#,
+        )
+        .unwrap()
+        .into_layer();
+
+        let get = |name: &str| layer.get(name).map(String::as_str);
+        assert_eq!(get("PROPERTIES"), Some("lisbon,madrid"));
+        assert_eq!(get("DEFAULT_HOTEL_SERVICE"), Some("Hotel Standard"));
+        assert_eq!(get("PROPERTY_MADRID_REALM"), Some("@mad.example.com"));
+        assert_eq!(
+            get("PROPERTY_MADRID_DEFAULT_HOTEL_SERVICE"),
+            Some("Madrid Standard")
+        );
+        assert_eq!(get("PROPERTY_MADRID_PMS_TIMEZONE"), Some("Europe/Madrid"));
+        assert_eq!(get("PROPERTY_LISBON_DEFAULT_HOTEL_SERVICE"), None);
+        assert_eq!(get("PMS_CLIENT_OPERA_MADRID_PROPERTY"), Some("madrid"));
+    }
+
+    #[test]
+    fn example_file_is_valid() {
+        let layer = AppConfig::parse(include_str!("../vhp.example.toml"))
+            .unwrap()
+            .into_layer();
+        assert_eq!(
+            layer.get("RADIUS_PASSWORD_STORAGE").map(String::as_str),
+            Some("cleartext")
+        );
+        assert_eq!(layer.get("PMS_CLIENTS"), None);
+    }
+}
@@ -1,13 +1,219 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize)]
 pub struct Booking {
     pub room_number: String,
+    /// RADIUS username of the guest: the room number, or `{room}-{n}` for a
+    /// guest with their own credential in a room shared with others.
+    pub username: String,
+    /// Password as written to radcheck and `hotel_rooms`, already in the
+    /// configured `PasswordStorage` form.
     pub password: String,
     pub name: Option<String>,
     pub folio_number: Option<String>,
     pub checkin_date: NaiveDateTime,
     pub checkout_date: NaiveDateTime,
     pub gtype: Option<String>,
+    /// Hotel service (radusergroup groupname) for the stay.
+    pub service: Option<HotelService>,
+    /// Extra radcheck rows written next to the password, e.g. `Expiration`.
+    pub check_attributes: Vec<RadiusAttribute>,
+    /// radreply rows for the stay, e.g. `Session-Timeout`.
+    pub reply_attributes: Vec<RadiusAttribute>,
+    /// Property-local time the booking was made, stored as `updated_at`.
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotelService {
+    pub id: i32,
+    pub name: String,
+    /// Devices allowed per credential (`services.simultaneous_use`).
+    pub simultaneous_use: Option<i32>,
+}
+
+/// One guest staying in a room; guests sharing a credential share `username`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GuestProfile {
+    pub room_number: String,
+    pub username: String,
+    /// Password as stored, see `PasswordStorage`.
+    pub password: String,
+    pub name: Option<String>,
+    pub folio_number: Option<String>,
+    pub service_id: i32,
+    pub checkin_date: NaiveDateTime,
+    pub checkout_date: NaiveDateTime,
+}
+
+/// Narrows the guests listed by the admin API; every field is optional.
+#[derive(Debug, Clone, Default)]
+pub struct GuestFilter {
+    pub service_id: Option<i32>,
+    pub checkout_from: Option<NaiveDateTime>,
+    /// Exclusive upper bound.
+    pub checkout_to: Option<NaiveDateTime>,
+}
+
+/// One hotel of a multi-property install.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    /// Stored in `hotel_rooms.property_id` and `pms_audit_log.property_id`.
+    pub id: String,
+    /// Suffix of every RADIUS username of the property, e.g. `@madrid`.
+    pub realm: String,
+}
+
+/// Where hotel credentials and plans live in the RADIUS and `services`
+/// tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RadiusSchema {
+    /// `radusergroup.user_type` of hotel credentials.
+    pub user_type: String,
+    /// How guest passwords are written to radcheck and `hotel_rooms`.
+    pub password_storage: PasswordStorage,
+    /// `services.cron_type` of hotel plans.
+    pub service_type: String,
+}
+
+impl Default for RadiusSchema {
+    fn default() -> Self {
+        Self {
+            user_type: "hotel-room".into(),
+            password_storage: PasswordStorage::default(),
+            service_type: "hotel".into(),
+        }
+    }
+}
+
+/// Form of the guest password in radcheck; `hotel_rooms` keeps the same
+/// value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PasswordStorage {
+    /// `Cleartext-Password`, for PAP, CHAP and MSCHAPv2.
+    #[default]
+    Cleartext,
+    /// `NT-Password`, the hex MD4 of the UTF-16LE password, for PAP and
+    /// MSCHAPv2.
+    NtHash,
+    /// `SSHA2-512-Password`, base64 of SHA-512(password + salt) + salt, for
+    /// PAP only.
+    Ssha512,
+    /// `Crypt-Password` as SHA-512 crypt (`$6$`), for PAP only.
+    Crypt,
+}
+
+impl PasswordStorage {
+    pub const ALL: [Self; 4] = [Self::Cleartext, Self::NtHash, Self::Ssha512, Self::Crypt];
+
+    /// radcheck attribute holding the password in this form.
+    pub fn attribute(self) -> &'static str {
+        match self {
+            Self::Cleartext => "Cleartext-Password",
+            Self::NtHash => "NT-Password",
+            Self::Ssha512 => "SSHA2-512-Password",
+            Self::Crypt => "Crypt-Password",
+        }
+    }
+
+    /// Parse `cleartext`, `nt`, `ssha512` or `crypt`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "cleartext" => Some(Self::Cleartext),
+            "nt" => Some(Self::NtHash),
+            "ssha512" => Some(Self::Ssha512),
+            "crypt" => Some(Self::Crypt),
+            _ => None,
+        }
+    }
+}
+
+/// A hotel credential as stored in radusergroup (with the hotel
+/// `user_type`) and radcheck.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredCredential {
+    pub username: String,
+    /// radcheck attribute of the password, any `PasswordStorage` form, if
+    /// the row exists.
+    pub password_attribute: Option<String>,
+    pub password: Option<String>,
+    pub groupname: String,
+}
+
+/// Writes that bring the RADIUS tables back in line with a PMS snapshot,
+/// applied in this order within one transaction.
+#[derive(Default)]
+pub struct ReconcilePlan {
+    /// Guests the PMS no longer lists.
+    pub checkouts: Vec<GuestProfile>,
+    /// Hotel credentials no guest uses.
+    pub orphans: Vec<String>,
+    pub updates: Vec<(GuestProfile, Booking)>,
+    pub checkins: Vec<Booking>,
+    /// Guests of each room the updates and checkins land in, as the plan
+    /// was made against them.
+    pub occupants: BTreeMap<String, Vec<GuestProfile>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RadiusAttribute {
+    pub attribute: String,
+    pub op: String,
+    pub value: String,
+}
+
+impl RadiusAttribute {
+    pub fn new(attribute: &str, op: &str, value: impl Into<String>) -> Self {
+        Self {
+            attribute: attribute.to_string(),
+            op: op.to_string(),
+            value: value.into(),
+        }
+    }
+}
+
+/// An open radacct session (`acctstoptime IS NULL`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RadiusSession {
+    pub username: String,
+    pub nas_ip_address: String,
+    pub acct_session_id: String,
+    pub framed_ip_address: Option<String>,
+    pub calling_station_id: Option<String>,
+}
+
+/// One row of the append-only `pms_audit_log` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: Option<i64>,
+    pub mode: String,
+    pub room_number: Option<String>,
+    pub old_room: Option<String>,
+    pub folio_number: Option<String>,
+    /// Raw PMS parameters as JSON, password redacted.
+    pub params: String,
+    /// `success`, `error` or `replay`.
+    pub outcome: String,
+    pub code: Option<String>,
+    pub message: String,
+    pub idempotency_key: Option<String>,
+    /// Keyed HMAC of the full request, used to recognise PMS retries.
+    pub fingerprint: Option<String>,
+    /// `PmsResponse` sent back as JSON, when the request succeeded; a
+    /// replay is answered with it.
+    pub response: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    /// Matches either the room or the room a guest moved from.
+    pub room: Option<String>,
+    pub folio_number: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    /// Exclusive upper bound.
+    pub to: Option<NaiveDateTime>,
+    pub limit: i64,
 }
@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+/// Repository failures the service answers specifically, attached as
+/// context to the driver error; any other failure is internal.
+#[derive(Debug, Error, Clone, Copy, PartialEq)]
+pub enum StorageError {
+    /// A concurrent write got there first, e.g. on a unique key.
+    #[error("conflicting write")]
+    Conflict,
+
+    /// The rows a write was planned against changed before it took its
+    /// lock; planning again from a fresh read may succeed.
+    #[error("rows changed since they were read")]
+    Stale,
+
+    /// The service a write was planned with is no longer an active hotel
+    /// service; retrying does not help.
+    #[error("inactive hotel service")]
+    InactiveService,
+
+    /// The database could not be reached or no connection was free in time.
+    #[error("database unavailable")]
+    Unavailable,
+}
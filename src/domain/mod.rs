@@ -1,2 +1,4 @@
 pub mod entities;
+pub mod errors;
+pub mod nas;
 pub mod repositories;
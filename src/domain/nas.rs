@@ -0,0 +1,32 @@
+use crate::domain::entities::RadiusSession;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisconnectOutcome {
+    /// Disconnect-ACK received.
+    Ack,
+    /// Disconnect-NAK received, with the Error-Cause if the NAS sent one.
+    Nak(Option<u32>),
+    /// No valid answer after all retries.
+    Timeout,
+    /// The session's NAS is not in the configured NAS list.
+    UnknownNas,
+}
+
+impl DisconnectOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisconnectOutcome::Ack => "ack",
+            DisconnectOutcome::Nak(_) => "nak",
+            DisconnectOutcome::Timeout => "timeout",
+            DisconnectOutcome::UnknownNas => "unknown_nas",
+        }
+    }
+}
+
+/// Sends RFC 5176 dynamic authorization requests to the NAS.
+#[async_trait]
+pub trait NasClient: Send + Sync {
+    async fn disconnect(&self, session: &RadiusSession) -> Result<DisconnectOutcome>;
+}
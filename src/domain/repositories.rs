@@ -1,11 +1,59 @@
-use crate::domain::entities::Booking;
+use crate::domain::entities::{
+    AuditEntry, AuditFilter, Booking, GuestFilter, GuestProfile, HotelService, RadiusSession,
+    ReconcilePlan, StoredCredential,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
+
+/// Hotel rooms, credentials and audit trail of one property: rooms and audit
+/// entries carry its id, credentials its realm.
 #[async_trait]
 pub trait BookingRepository: Send + Sync {
-    async fn checkin_repo(&self, booking: &Booking) -> Result<()>;
-    async fn checkout_repo(&self, booking: &Booking) -> Result<()>;
-    async fn update_repo(&self, old_room: &str, booking: &Booking) -> Result<()>;
-    async fn get_cron_hotel_service(&self) -> Result<Vec<(i32, String)>>;
+    /// Adds the guest and (re)writes the credential of `booking.username`,
+    /// in one transaction holding the room. Fails with
+    /// [`StorageError::Stale`](crate::domain::errors::StorageError) when the
+    /// room's guests are no longer `occupants`, the guests the booking was
+    /// planned against, and with `Conflict` when its service is no longer an
+    /// active hotel service or the folio is already in the room.
+    async fn checkin_repo(&self, booking: &Booking, occupants: &[GuestProfile]) -> Result<()>;
+    /// Removes every guest of the room, or only the one whose folio equals
+    /// `booking.folio_number` when set. Returns the usernames whose
+    /// credentials were dropped because no guest uses them anymore.
+    async fn checkout_repo(&self, booking: &Booking) -> Result<Vec<String>>;
+    /// Moves `guest` to the state in `booking`, possibly under a new username,
+    /// holding `booking.room_number`. Fails with `Stale` when that room's
+    /// guests are no longer `occupants`, the guests the move was planned
+    /// against.
+    async fn update_repo(
+        &self,
+        guest: &GuestProfile,
+        booking: &Booking,
+        occupants: &[GuestProfile],
+    ) -> Result<()>;
+    async fn get_cron_hotel_service(&self) -> Result<Vec<HotelService>>;
     async fn is_room_active(&self, room_number: &str) -> Result<bool>;
+    async fn room_guests(&self, room_number: &str) -> Result<Vec<GuestProfile>>;
+    /// Every guest matching `filter`, ordered by room then checkin.
+    async fn list_guests(&self, filter: &GuestFilter) -> Result<Vec<GuestProfile>>;
+    /// Guests on `cron = 1` hotel services whose checkout is before `cutoff`.
+    async fn overdue_rooms(&self, cutoff: NaiveDateTime) -> Result<Vec<GuestProfile>>;
+    /// Every credential in radusergroup with the hotel `user_type`.
+    async fn hotel_credentials(&self) -> Result<Vec<StoredCredential>>;
+    /// Applies `plan` in one transaction. Returns the usernames whose
+    /// credentials were dropped.
+    async fn apply_reconciliation(&self, plan: &ReconcilePlan) -> Result<Vec<String>>;
+    async fn open_sessions(&self, username: &str) -> Result<Vec<RadiusSession>>;
+    async fn record_audit(&self, entry: &AuditEntry) -> Result<()>;
+    async fn audit_history(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>>;
+    /// Latest successful audit entry since `since`, matched by idempotency
+    /// key when one is given. Otherwise matched by request fingerprint, and
+    /// only while no later successful request touched the same guest or
+    /// rooms: the same payload after that is a new request, not a retry.
+    async fn find_replay(
+        &self,
+        idempotency_key: Option<&str>,
+        fingerprint: &str,
+        since: NaiveDateTime,
+    ) -> Result<Option<AuditEntry>>;
 }
@@ -0,0 +1,196 @@
+use anyhow::{Result, anyhow};
+use chrono::{NaiveDate, NaiveDateTime};
+
+pub const STX: u8 = 0x02;
+pub const ETX: u8 = 0x03;
+
+/// A single FIAS record, e.g. `GI|RN101|G#12345|GNSmith|`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FiasRecord {
+    pub record_type: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl FiasRecord {
+    pub fn new(record_type: &str) -> Self {
+        Self {
+            record_type: record_type.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn field(mut self, id: &str, value: impl Into<String>) -> Self {
+        self.fields.push((id.to_string(), value.into()));
+        self
+    }
+
+    /// Adds the `DA`/`TI` timestamp fields every link record carries, at
+    /// `now` on the property's clock.
+    pub fn stamped(self, now: NaiveDateTime) -> Self {
+        self.field("DA", now.format("%y%m%d").to_string())
+            .field("TI", now.format("%H%M%S").to_string())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == id)
+            .map(|(_, v)| v.as_str())
+            .filter(|v| !v.trim().is_empty())
+    }
+
+    /// Parse the content between STX and ETX.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let mut parts = raw.split('|');
+        let record_type = parts
+            .next()
+            .filter(|t| t.len() == 2)
+            .ok_or_else(|| anyhow!("invalid fias record: {:?}", raw))?;
+
+        let fields = parts
+            .filter(|p| p.len() >= 2)
+            .map(|p| {
+                let split = p.char_indices().nth(2).map(|(i, _)| i).unwrap_or(p.len());
+                let (id, value) = p.split_at(split);
+                (id.to_string(), value.to_string())
+            })
+            .collect();
+
+        Ok(Self {
+            record_type: record_type.to_string(),
+            fields,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let mut out = format!("{}|", self.record_type);
+        for (id, value) in &self.fields {
+            out.push_str(id);
+            out.push_str(value);
+            out.push('|');
+        }
+        out
+    }
+
+    /// Encoded record wrapped in STX/ETX, ready to write to the socket.
+    pub fn to_frame(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.encode().len() + 2);
+        frame.push(STX);
+        frame.extend_from_slice(self.encode().as_bytes());
+        frame.push(ETX);
+        frame
+    }
+}
+
+/// Longest frame kept while waiting for its ETX; a longer one is dropped.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Accumulates bytes from the socket and yields complete STX..ETX frames.
+/// Bytes outside a frame are discarded.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn next_frame(&mut self) -> Option<String> {
+        loop {
+            let Some(start) = self.buf.iter().position(|b| *b == STX) else {
+                self.buf.clear();
+                return None;
+            };
+            self.buf.drain(..start);
+
+            let Some(end) = self.buf.iter().position(|b| *b == ETX) else {
+                if self.buf.len() > MAX_FRAME_LEN {
+                    tracing::warn!(
+                        "dropping fias frame of over {} bytes without ETX",
+                        MAX_FRAME_LEN
+                    );
+                    self.buf.clear();
+                }
+                return None;
+            };
+
+            let frame = self.buf[1..end].to_vec();
+            self.buf.drain(..=end);
+
+            // A stray STX inside a frame means the previous frame was cut off.
+            match frame.iter().rposition(|b| *b == STX) {
+                Some(i) => return Some(String::from_utf8_lossy(&frame[i + 1..]).into_owned()),
+                None if frame.is_empty() => continue,
+                None => return Some(String::from_utf8_lossy(&frame).into_owned()),
+            }
+        }
+    }
+}
+
+/// Convert a FIAS `YYMMDD` date into an ISO `YYYY-MM-DD` date, which the PMS
+/// flow accepts whatever `PMS_DATE_FORMATS` is set to.
+pub fn fias_date_to_pms(value: &str) -> Result<String> {
+    NaiveDate::parse_from_str(value.trim(), "%y%m%d")
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .map_err(|_| anyhow!("invalid fias date {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(decoder: &mut FrameDecoder) -> Vec<String> {
+        std::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    #[test]
+    fn parses_and_encodes_records() {
+        let record = FiasRecord::parse("GI|RN101|G#12345|GNSmith|GA251120|").unwrap();
+        assert_eq!(record.record_type, "GI");
+        assert_eq!(record.get("RN"), Some("101"));
+        assert_eq!(record.get("G#"), Some("12345"));
+        assert_eq!(record.get("GF"), None);
+        assert_eq!(record.encode(), "GI|RN101|G#12345|GNSmith|GA251120|");
+        assert!(FiasRecord::parse("G|RN101|").is_err());
+        assert_eq!(fias_date_to_pms("251120").unwrap(), "2025-11-20");
+        assert!(fias_date_to_pms("2511").is_err());
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() {
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(b"\x02GI|RN10");
+        assert_eq!(decoder.next_frame(), None);
+        decoder.extend(b"1|\x03\x02GO|RN102|\x03");
+        assert_eq!(frames(&mut decoder), ["GI|RN101|", "GO|RN102|"]);
+    }
+
+    #[test]
+    fn skips_junk_and_cut_off_frames() {
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(b"noise\x02\x03\r\n\x02GI|RN1\x02GO|RN102|\x03junk\x02LA|\x03");
+        assert_eq!(frames(&mut decoder), ["GO|RN102|", "LA|"]);
+
+        decoder.extend(b"junk without any frame");
+        assert_eq!(decoder.next_frame(), None);
+        assert!(decoder.buf.is_empty());
+    }
+
+    #[test]
+    fn drops_oversized_partial_frames() {
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(&[STX]);
+        decoder.extend(&vec![b'x'; MAX_FRAME_LEN - 1]);
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.buf.len(), MAX_FRAME_LEN);
+
+        decoder.extend(b"x");
+        assert_eq!(decoder.next_frame(), None);
+        assert!(decoder.buf.is_empty());
+
+        decoder.extend(b"more of the same\x03\x02LA|\x03");
+        assert_eq!(frames(&mut decoder), ["LA|"]);
+    }
+}
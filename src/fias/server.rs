@@ -0,0 +1,592 @@
+use crate::application::{dtos::PmsQueryParams, services::BookingService};
+use crate::config::var;
+use crate::domain::repositories::BookingRepository;
+use crate::fias::protocol::{FiasRecord, FrameDecoder, fias_date_to_pms};
+use crate::logging::request_id;
+use crate::presentation::auth::parse_allowed_ips;
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDateTime;
+use ipnet::IpNet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::Instrument;
+
+#[derive(Debug, Clone)]
+pub struct FiasSettings {
+    pub bind_addr: String,
+    /// Send a DR (database resync request) as soon as the link is up.
+    pub resync_on_link: bool,
+    /// Idle time after which an LA heartbeat is sent to the PMS.
+    pub heartbeat: Duration,
+    /// Property the link serves, from `FIAS_PROPERTY`; required when
+    /// several are configured.
+    pub property: Option<String>,
+    /// Peers allowed to connect; any when empty. FIAS carries no
+    /// credentials, so this is its only access control besides the bind
+    /// address.
+    pub allowed_ips: Vec<IpNet>,
+}
+
+impl FiasSettings {
+    /// Returns `None` when `FIAS_PORT` is not set, i.e. the FIAS interface is
+    /// disabled. `FIAS_HOST` defaults to `127.0.0.1`; `FIAS_ALLOWED_IPS`
+    /// lists the ips or CIDRs of the PMS interface hosts.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(port) = var("FIAS_PORT") else {
+            return Ok(None);
+        };
+        let port: u16 = port
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid FIAS_PORT {:?}", port))?;
+        let host = var("FIAS_HOST").unwrap_or_else(|| "127.0.0.1".into());
+
+        let resync_on_link =
+            var("FIAS_RESYNC_ON_LINK").is_some_and(|v| matches!(v.trim(), "1" | "true" | "yes"));
+
+        let heartbeat = match var("FIAS_HEARTBEAT_SECS") {
+            Some(v) => v
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid FIAS_HEARTBEAT_SECS {:?}", v))?,
+            None => 60,
+        };
+
+        Ok(Some(Self {
+            bind_addr: format!("{}:{}", host.trim(), port),
+            resync_on_link,
+            heartbeat: Duration::from_secs(heartbeat),
+            property: var("FIAS_PROPERTY").map(|p| p.trim().to_string()),
+            allowed_ips: match var("FIAS_ALLOWED_IPS") {
+                Some(v) => parse_allowed_ips(&v).context("invalid FIAS_ALLOWED_IPS")?,
+                None => Vec::new(),
+            },
+        }))
+    }
+}
+
+/// Pause after a failed accept, so that running out of file descriptors
+/// does not spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(500);
+
+pub async fn run<R: BookingRepository + ?Sized + 'static>(
+    settings: FiasSettings,
+    service: Arc<BookingService<R>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(&settings.bind_addr).await?;
+    tracing::info!("fias interface listening on {}", settings.bind_addr);
+    serve(listener, settings, service).await
+}
+
+pub async fn serve<R: BookingRepository + ?Sized + 'static>(
+    listener: TcpListener,
+    settings: FiasSettings,
+    service: Arc<BookingService<R>>,
+) -> Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                // Out of file descriptors or a connection reset before it
+                // was accepted: the listener itself is fine.
+                tracing::warn!("fias accept failed: {}", err);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        if !settings.allowed_ips.is_empty()
+            && !settings
+                .allowed_ips
+                .iter()
+                .any(|net| net.contains(&peer.ip()))
+        {
+            tracing::warn!("refusing fias connection from {}", peer);
+            continue;
+        }
+        tracing::info!("fias connection from {}", peer);
+
+        let settings = settings.clone();
+        let service = service.clone();
+        let span = tracing::info_span!("fias", peer = %peer);
+        tokio::spawn(
+            async move {
+                if let Err(err) = handle_connection(stream, settings, service).await {
+                    tracing::error!("fias connection {} failed: {}", peer, err);
+                }
+                tracing::info!("fias connection from {} closed", peer);
+            }
+            .instrument(span),
+        );
+    }
+}
+
+async fn handle_connection<R: BookingRepository + ?Sized>(
+    mut stream: TcpStream,
+    settings: FiasSettings,
+    service: Arc<BookingService<R>>,
+) -> Result<()> {
+    let mut session = FiasSession::new(service, settings.resync_on_link);
+    let mut decoder = FrameDecoder::default();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let n = match tokio::time::timeout(settings.heartbeat, stream.read(&mut buf)).await {
+            Ok(read) => read?,
+            Err(_) => {
+                // Idle: keep the link alive.
+                stream
+                    .write_all(
+                        &FiasRecord::new("LA")
+                            .stamped(session.service.now())
+                            .to_frame(),
+                    )
+                    .await?;
+                continue;
+            }
+        };
+
+        if n == 0 {
+            return Ok(());
+        }
+        decoder.extend(&buf[..n]);
+
+        while let Some(raw) = decoder.next_frame() {
+            let record = match FiasRecord::parse(&raw) {
+                Ok(r) => r,
+                Err(err) => {
+                    tracing::warn!("{}", err);
+                    continue;
+                }
+            };
+
+            if record.record_type == "LE" {
+                tracing::info!("fias link end received");
+                return Ok(());
+            }
+
+            // Each record is one PMS call, traced like an HTTP request.
+            let span = tracing::info_span!(
+                "request",
+                request_id = %request_id(None),
+                record = %record.record_type
+            );
+            for reply in session.handle(&record).instrument(span).await {
+                stream.write_all(&reply.to_frame()).await?;
+            }
+        }
+    }
+}
+
+/// State of one FIAS link: handshake progress and an in-flight database resync.
+pub struct FiasSession<R: BookingRepository + ?Sized> {
+    service: Arc<BookingService<R>>,
+    resync_on_link: bool,
+    link_up: bool,
+    resync: Option<usize>,
+}
+
+impl<R: BookingRepository + ?Sized> FiasSession<R> {
+    pub fn new(service: Arc<BookingService<R>>, resync_on_link: bool) -> Self {
+        Self {
+            service,
+            resync_on_link,
+            link_up: false,
+            resync: None,
+        }
+    }
+
+    /// Handle one incoming record and return the records to send back.
+    pub async fn handle(&mut self, record: &FiasRecord) -> Vec<FiasRecord> {
+        match record.record_type.as_str() {
+            "LS" => {
+                self.link_up = false;
+                link_description(self.service.now())
+            }
+            "LA" => {
+                if self.link_up {
+                    return Vec::new();
+                }
+                self.link_up = true;
+                tracing::info!("fias link established");
+                if self.resync_on_link {
+                    vec![FiasRecord::new("DR").stamped(self.service.now())]
+                } else {
+                    Vec::new()
+                }
+            }
+            "DS" => {
+                tracing::info!("fias database resync started");
+                self.resync = Some(0);
+                Vec::new()
+            }
+            "DE" => {
+                let count = self.resync.take().unwrap_or_default();
+                tracing::info!("fias database resync finished, {} guest records", count);
+                Vec::new()
+            }
+            "GI" => {
+                self.handle_guest_in(record).await;
+                Vec::new()
+            }
+            "GO" => {
+                self.handle_guest_out(record).await;
+                Vec::new()
+            }
+            "GC" => {
+                self.handle_guest_change(record).await;
+                Vec::new()
+            }
+            other => {
+                tracing::debug!("ignoring fias record {}", other);
+                Vec::new()
+            }
+        }
+    }
+
+    async fn handle_guest_in(&mut self, record: &FiasRecord) {
+        if is_sharer(record) {
+            tracing::info!("ignoring sharer GI for room {:?}", record.get("RN"));
+            return;
+        }
+
+        // During a resync the PMS replays every in-house guest, so rooms we
+        // already know about are refreshed instead of rejected as in use.
+        let mode = match (self.resync.as_mut(), record.get("RN")) {
+            (Some(count), Some(room)) => {
+                *count += 1;
+                match self.service.is_room_active(room).await {
+                    Ok(true) => "update",
+                    Ok(false) => "checkin",
+                    Err(err) => {
+                        tracing::error!("fias resync lookup for room {} failed: {}", room, err);
+                        return;
+                    }
+                }
+            }
+            _ => "checkin",
+        };
+
+        self.dispatch(record, mode).await;
+    }
+
+    async fn handle_guest_out(&mut self, record: &FiasRecord) {
+        if is_sharer(record) {
+            // A sharer leaving must not remove the main guest's access.
+            tracing::info!("ignoring sharer GO for room {:?}", record.get("RN"));
+            return;
+        }
+        self.dispatch(record, "checkout").await;
+    }
+
+    async fn handle_guest_change(&mut self, record: &FiasRecord) {
+        self.dispatch(record, "update").await;
+    }
+
+    async fn dispatch(&self, record: &FiasRecord, mode: &str) {
+        let query = match to_query(record, mode) {
+            Ok(q) => q,
+            Err(err) => {
+                tracing::error!("fias {} rejected: {}", record.record_type, err);
+                return;
+            }
+        };
+
+        match self.service.process(query).await {
+            Ok(resp) => tracing::info!("fias {}: {}", record.record_type, resp.message),
+            Err(err) => tracing::error!("fias {} failed: {}", record.record_type, err),
+        }
+    }
+}
+
+fn is_sharer(record: &FiasRecord) -> bool {
+    record.get("GS") == Some("Y")
+}
+
+/// Map a GI/GO/GC record onto the query parameters of the HTTP interface.
+fn to_query(record: &FiasRecord, mode: &str) -> Result<PmsQueryParams> {
+    let field = |id: &str| record.get(id).map(|v| v.trim().to_string());
+    let date = |id: &str| field(id).map(|v| fias_date_to_pms(&v)).transpose();
+
+    let name = match (field("GF"), field("GN")) {
+        (Some(first), Some(last)) => Some(format!("{} {}", first, last)),
+        (_, last) => last,
+    };
+
+    Ok(PmsQueryParams {
+        mode: mode.to_string(),
+        room: field("RN"),
+        oldroom: field("RO"),
+        name,
+        // FIAS carries no password; the guest's last name is used, as most PMS do.
+        pass: field("GN"),
+        rsvno: field("G#"),
+        cidate: date("GA")?,
+        codate: date("GD")?,
+        cotime: None,
+        gtype: field("GV"),
+        credential: None,
+        idempotency_key: None,
+        property: None,
+    })
+}
+
+/// LD + LR records announcing the records and fields we want, closed by LA.
+fn link_description(now: NaiveDateTime) -> Vec<FiasRecord> {
+    vec![
+        FiasRecord::new("LD")
+            .stamped(now)
+            .field("V#", env!("CARGO_PKG_VERSION"))
+            .field("IF", "WW"),
+        FiasRecord::new("LR")
+            .field("RI", "GI")
+            .field("FL", "RNG#GNGFGAGDGVGS"),
+        FiasRecord::new("LR")
+            .field("RI", "GO")
+            .field("FL", "RNG#GS"),
+        FiasRecord::new("LR")
+            .field("RI", "GC")
+            .field("FL", "RNROG#GNGFGAGDGV"),
+        FiasRecord::new("LR").field("RI", "DS").field("FL", "DATI"),
+        FiasRecord::new("LR").field("RI", "DE").field("FL", "DATI"),
+        FiasRecord::new("LA").stamped(now),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::settings::ServiceSettings;
+    use crate::infrastructure::repositories::memory::InMemoryBookingRepository;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// Scripted PMS end of the link.
+    struct Pms {
+        stream: TcpStream,
+        decoder: FrameDecoder,
+    }
+
+    impl Pms {
+        async fn send(&mut self, raw: &str) {
+            let record = FiasRecord::parse(raw).unwrap();
+            self.stream.write_all(&record.to_frame()).await.unwrap();
+        }
+
+        /// Next `count` records sent by the interface.
+        async fn receive(&mut self, count: usize) -> Vec<FiasRecord> {
+            let mut records = Vec::new();
+            let mut buf = [0u8; 4096];
+            while records.len() < count {
+                if let Some(raw) = self.decoder.next_frame() {
+                    records.push(FiasRecord::parse(&raw).unwrap());
+                    continue;
+                }
+                let n = tokio::time::timeout(WAIT, self.stream.read(&mut buf))
+                    .await
+                    .expect("no record from the interface")
+                    .unwrap();
+                assert!(n > 0, "link closed after {:?}", records);
+                self.decoder.extend(&buf[..n]);
+            }
+            records
+        }
+
+        async fn handshake(&mut self) {
+            self.send("LS|DA251120|TI120000|").await;
+            let types: Vec<_> = self
+                .receive(7)
+                .await
+                .into_iter()
+                .map(|r| r.record_type)
+                .collect();
+            assert_eq!(types, ["LD", "LR", "LR", "LR", "LR", "LR", "LA"]);
+            self.send("LA|DA251120|TI120000|").await;
+        }
+    }
+
+    fn settings() -> FiasSettings {
+        FiasSettings {
+            bind_addr: "127.0.0.1:0".into(),
+            resync_on_link: false,
+            heartbeat: Duration::from_secs(60),
+            property: None,
+            allowed_ips: Vec::new(),
+        }
+    }
+
+    async fn start(settings: FiasSettings) -> (Arc<InMemoryBookingRepository>, Pms) {
+        let repo = Arc::new(InMemoryBookingRepository::with_hotel_service(1, "Basic"));
+        let service = Arc::new(BookingService::new(
+            repo.clone(),
+            Arc::new(ServiceSettings::default()),
+        ));
+        let listener = TcpListener::bind(&settings.bind_addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, settings, service));
+
+        let pms = Pms {
+            stream: TcpStream::connect(addr).await.unwrap(),
+            decoder: FrameDecoder::default(),
+        };
+        (repo, pms)
+    }
+
+    /// Rooms in use and their guests' folios, once `expected`.
+    async fn wait_for_rooms(repo: &InMemoryBookingRepository, expected: &[(&str, &str)]) {
+        let rooms = || {
+            let mut rooms: Vec<(String, String)> = repo
+                .tables()
+                .hotel_rooms
+                .iter()
+                .map(|r| (r.room_number.clone(), r.folio_number.clone()))
+                .collect();
+            rooms.sort();
+            rooms
+        };
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(room, folio)| (room.to_string(), folio.to_string()))
+            .collect();
+        let deadline = tokio::time::Instant::now() + WAIT;
+        while rooms() != expected {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "rooms {:?}, expected {:?}",
+                rooms(),
+                expected
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_then_guest_in_change_and_out() {
+        let (repo, mut pms) = start(settings()).await;
+        pms.handshake().await;
+
+        pms.send("GI|RN101|G#R-1|GNSmith|GFJohn|GA251120|GD251122|GVVIP|")
+            .await;
+        wait_for_rooms(&repo, &[("101", "R-1")]).await;
+        {
+            let tables = repo.tables();
+            assert_eq!(tables.hotel_rooms[0].name, "John Smith");
+            assert_eq!(
+                tables.hotel_rooms[0].checkout_date.date().to_string(),
+                "2025-11-22"
+            );
+        }
+
+        // A sharer does not take the room over.
+        pms.send("GI|RN101|G#R-2|GNDoe|GA251120|GD251122|GSY|")
+            .await;
+        pms.send("GC|RN102|RO101|G#R-1|GNSmith|GFJohn|GA251120|GD251125|")
+            .await;
+        wait_for_rooms(&repo, &[("102", "R-1")]).await;
+        assert_eq!(
+            repo.tables().hotel_rooms[0]
+                .checkout_date
+                .date()
+                .to_string(),
+            "2025-11-25"
+        );
+
+        pms.send("GO|RN102|G#R-2|GSY|").await;
+        pms.send("GO|RN102|G#R-1|").await;
+        wait_for_rooms(&repo, &[]).await;
+        assert!(repo.tables().radcheck.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resync_refreshes_known_rooms_and_checks_in_new_ones() {
+        let (repo, mut pms) = start(FiasSettings {
+            resync_on_link: true,
+            ..settings()
+        })
+        .await;
+        pms.handshake().await;
+        assert_eq!(pms.receive(1).await[0].record_type, "DR");
+
+        pms.send("GI|RN101|G#R-1|GNSmith|GA251120|GD251122|").await;
+        wait_for_rooms(&repo, &[("101", "R-1")]).await;
+
+        pms.send("DS|DA251120|TI120000|").await;
+        pms.send("GI|RN101|G#R-1|GNSmith|GA251120|GD251124|").await;
+        pms.send("GI|RN103|G#R-3|GNDoe|GA251120|GD251123|").await;
+        pms.send("DE|DA251120|TI120001|").await;
+        wait_for_rooms(&repo, &[("101", "R-1"), ("103", "R-3")]).await;
+
+        let deadline = tokio::time::Instant::now() + WAIT;
+        loop {
+            let checkout = repo
+                .tables()
+                .hotel_rooms
+                .iter()
+                .find(|r| r.room_number == "101")
+                .map(|r| r.checkout_date.date().to_string());
+            if checkout.as_deref() == Some("2025-11-24") {
+                break;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "{:?}", checkout);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_link_gets_heartbeats() {
+        let (_, mut pms) = start(FiasSettings {
+            heartbeat: Duration::from_millis(50),
+            ..settings()
+        })
+        .await;
+        pms.handshake().await;
+
+        let heartbeats = pms.receive(2).await;
+        assert!(heartbeats.iter().all(|r| r.record_type == "LA"));
+        assert!(heartbeats[0].get("DA").is_some());
+    }
+
+    #[tokio::test]
+    async fn link_records_are_stamped_on_the_property_clock() {
+        let mut service_settings = ServiceSettings::default();
+        service_settings.dates.timezone = Some("Pacific/Kiritimati".parse().unwrap());
+        let service = Arc::new(BookingService::new(
+            Arc::new(InMemoryBookingRepository::with_hotel_service(1, "Basic")),
+            Arc::new(service_settings),
+        ));
+        let mut session = FiasSession::new(service.clone(), false);
+
+        let stamp = |r: &FiasRecord| format!("{}{}", r.get("DA").unwrap(), r.get("TI").unwrap());
+        let before = service.now().format("%y%m%d%H%M%S").to_string();
+        let records = session
+            .handle(&FiasRecord::parse("LS|DA251120|TI120000|").unwrap())
+            .await;
+        let after = service.now().format("%y%m%d%H%M%S").to_string();
+        let stamped = stamp(&records[0]);
+        assert!(
+            before <= stamped && stamped <= after,
+            "{} not within {}..{}",
+            stamped,
+            before,
+            after
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_peers_outside_the_allowlist() {
+        let (repo, mut pms) = start(FiasSettings {
+            allowed_ips: parse_allowed_ips("10.0.0.0/8").unwrap(),
+            ..settings()
+        })
+        .await;
+        pms.send("GI|RN101|G#R-1|GNSmith|GA251120|GD251122|").await;
+
+        let mut buf = [0u8; 64];
+        let read = tokio::time::timeout(WAIT, pms.stream.read(&mut buf))
+            .await
+            .expect("connection left open");
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert!(repo.tables().hotel_rooms.is_empty());
+    }
+}
@@ -1,20 +1,289 @@
-use anyhow::Result;
+use crate::config::var;
+use anyhow::{Result, anyhow, bail};
 use once_cell::sync::OnceCell;
-use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
+use sqlx::mysql::{MySql, MySqlPool};
+use sqlx::pool::PoolOptions;
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use std::time::Duration;
 
-pub static DB_POOL: OnceCell<MySqlPool> = OnceCell::new();
+#[derive(Debug)]
+pub enum DbPool {
+    MySql(MySqlPool),
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+pub static DB_POOL: OnceCell<DbPool> = OnceCell::new();
+
+/// Pool sizing and how hard startup tries to reach the database.
+#[derive(Debug, Clone)]
+pub struct DbSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long a query waits for a free connection; also bounds each
+    /// connection attempt at startup.
+    pub acquire_timeout: Duration,
+    /// Idle connections above `min_connections` are closed after this.
+    pub idle_timeout: Option<Duration>,
+    /// Connection attempts at startup; `0` retries forever.
+    pub connect_attempts: u32,
+    /// Delay after the first failed attempt, doubled up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for DbSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            connect_attempts: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl DbSettings {
+    /// Reads `DB_MAX_CONNECTIONS`, `DB_MIN_CONNECTIONS`,
+    /// `DB_ACQUIRE_TIMEOUT_SECS`, `DB_IDLE_TIMEOUT_SECS` (`0` keeps idle
+    /// connections), `DB_CONNECT_ATTEMPTS`, `DB_CONNECT_BACKOFF_MS` and
+    /// `DB_CONNECT_MAX_BACKOFF_MS`.
+    pub fn from_env() -> Result<Self> {
+        let mut settings = Self::default();
+        if let Some(v) = env_number("DB_MAX_CONNECTIONS")? {
+            if v == 0 {
+                bail!("invalid DB_MAX_CONNECTIONS {:?}", v.to_string());
+            }
+            settings.max_connections = v as u32;
+        }
+        if let Some(v) = env_number("DB_MIN_CONNECTIONS")? {
+            settings.min_connections = v as u32;
+        }
+        if settings.min_connections > settings.max_connections {
+            bail!("DB_MIN_CONNECTIONS must not exceed DB_MAX_CONNECTIONS");
+        }
+        if let Some(v) = env_number("DB_ACQUIRE_TIMEOUT_SECS")? {
+            settings.acquire_timeout = Duration::from_secs(v);
+        }
+        if let Some(v) = env_number("DB_IDLE_TIMEOUT_SECS")? {
+            settings.idle_timeout = (v > 0).then(|| Duration::from_secs(v));
+        }
+        if let Some(v) = env_number("DB_CONNECT_ATTEMPTS")? {
+            settings.connect_attempts = v as u32;
+        }
+        if let Some(v) = env_number("DB_CONNECT_BACKOFF_MS")? {
+            settings.initial_backoff = Duration::from_millis(v);
+        }
+        if let Some(v) = env_number("DB_CONNECT_MAX_BACKOFF_MS")? {
+            settings.max_backoff = Duration::from_millis(v);
+        }
+        Ok(settings)
+    }
+
+    /// Delay before retrying after `attempt` (1-based) failed.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
 
-pub async fn init_db_pool(database_url: &str) -> Result<()> {
-    let pool = MySqlPoolOptions::new()
-        .max_connections(5)
-        .connect(database_url)
-        .await?;
+    fn pool_options<DB: sqlx::Database>(&self) -> PoolOptions<DB> {
+        PoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+    }
+}
+
+fn env_number(name: &str) -> Result<Option<u64>> {
+    var(name)
+        .map(|v| {
+            v.trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid {} {:?}", name, v))
+        })
+        .transpose()
+}
+
+/// Connect to the backend selected by the `DATABASE_URL` scheme
+/// (`mysql://`, `postgres://` / `postgresql://` or `sqlite://`), retrying
+/// with backoff while the database is unreachable.
+pub async fn init_db_pool(database_url: &str, settings: &DbSettings) -> Result<()> {
+    let mut attempt = 1;
+    let pool = loop {
+        match connect(database_url, settings).await {
+            Ok(pool) => break pool,
+            Err(ConnectError::Fatal(err)) => return Err(err),
+            Err(ConnectError::Unreachable(err)) => {
+                if settings.connect_attempts != 0 && attempt >= settings.connect_attempts {
+                    return Err(anyhow!(
+                        "database unreachable after {} attempt(s): {}",
+                        attempt,
+                        err
+                    ));
+                }
+                let delay = settings.backoff(attempt);
+                tracing::warn!(
+                    "database unreachable (attempt {}): {}; retrying in {:?}",
+                    attempt,
+                    err,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    };
     DB_POOL
         .set(pool)
         .expect("❌ DB_POOL is already initialized");
     Ok(())
 }
 
-pub fn db_pool() -> &'static MySqlPool {
+enum ConnectError {
+    /// Retrying cannot help, e.g. an unsupported scheme.
+    Fatal(anyhow::Error),
+    Unreachable(sqlx::Error),
+}
+
+async fn connect(database_url: &str, settings: &DbSettings) -> Result<DbPool, ConnectError> {
+    let scheme = database_url.split(':').next().unwrap_or_default();
+    let pool = match scheme {
+        "mysql" => DbPool::MySql(
+            settings
+                .pool_options::<MySql>()
+                .connect(database_url)
+                .await
+                .map_err(classify)?,
+        ),
+        "postgres" | "postgresql" => DbPool::Postgres(
+            settings
+                .pool_options::<Postgres>()
+                .connect(database_url)
+                .await
+                .map_err(classify)?,
+        ),
+        "sqlite" => DbPool::Sqlite(
+            settings
+                .pool_options::<Sqlite>()
+                .connect(database_url)
+                .await
+                .map_err(classify)?,
+        ),
+        other => {
+            return Err(ConnectError::Fatal(anyhow!(
+                "unsupported DATABASE_URL scheme {:?}",
+                other
+            )));
+        }
+    };
+    Ok(pool)
+}
+
+/// A malformed URL will not fix itself; anything else may be a database
+/// that is still starting.
+fn classify(err: sqlx::Error) -> ConnectError {
+    match err {
+        sqlx::Error::Configuration(_) => ConnectError::Fatal(err.into()),
+        other => ConnectError::Unreachable(other),
+    }
+}
+
+/// Run a trivial query to confirm the pool can reach the database.
+pub async fn ping_db() -> Result<()> {
+    match db_pool() {
+        DbPool::MySql(pool) => sqlx::query("SELECT 1").execute(pool).await.map(drop)?,
+        DbPool::Postgres(pool) => sqlx::query("SELECT 1").execute(pool).await.map(drop)?,
+        DbPool::Sqlite(pool) => sqlx::query("SELECT 1").execute(pool).await.map(drop)?,
+    }
+    Ok(())
+}
+
+pub fn db_pool() -> &'static DbPool {
     DB_POOL.get().expect("❌ DB_POOL is not initialized")
 }
+
+/// Close every connection once those in use are returned.
+pub async fn close_db_pool() {
+    if let Some(pool) = DB_POOL.get() {
+        match pool {
+            DbPool::MySql(pool) => pool.close().await,
+            DbPool::Postgres(pool) => pool.close().await,
+            DbPool::Sqlite(pool) => pool.close().await,
+        }
+    }
+}
+
+/// Open connections, idle connections and the configured maximum.
+pub fn pool_stats() -> (u32, usize, u32) {
+    match db_pool() {
+        DbPool::MySql(pool) => (
+            pool.size(),
+            pool.num_idle(),
+            pool.options().get_max_connections(),
+        ),
+        DbPool::Postgres(pool) => (
+            pool.size(),
+            pool.num_idle(),
+            pool.options().get_max_connections(),
+        ),
+        DbPool::Sqlite(pool) => (
+            pool.size(),
+            pool.num_idle(),
+            pool.options().get_max_connections(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let settings = DbSettings {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(3),
+            ..DbSettings::default()
+        };
+
+        assert_eq!(settings.backoff(1), Duration::from_millis(500));
+        assert_eq!(settings.backoff(2), Duration::from_secs(1));
+        assert_eq!(settings.backoff(3), Duration::from_secs(2));
+        assert_eq!(settings.backoff(4), Duration::from_secs(3));
+        assert_eq!(settings.backoff(60), Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_configured_attempts() {
+        let settings = DbSettings {
+            connect_attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..DbSettings::default()
+        };
+
+        let err = init_db_pool("sqlite:///nonexistent-dir/vhp.db", &settings)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("database unreachable after 2 attempt(s)"),
+            "{}",
+            err
+        );
+
+        let err = init_db_pool("redis://localhost", &settings)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"unsupported DATABASE_URL scheme "redis""#
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use crate::config::var;
+use crate::domain::{
+    entities::RadiusSession,
+    nas::{DisconnectOutcome, NasClient},
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const DISCONNECT_REQUEST: u8 = 40;
+const DISCONNECT_ACK: u8 = 41;
+const DISCONNECT_NAK: u8 = 42;
+
+const ATTR_USER_NAME: u8 = 1;
+const ATTR_NAS_IP_ADDRESS: u8 = 4;
+const ATTR_FRAMED_IP_ADDRESS: u8 = 8;
+const ATTR_CALLING_STATION_ID: u8 = 31;
+const ATTR_ACCT_SESSION_ID: u8 = 44;
+const ATTR_ERROR_CAUSE: u8 = 101;
+
+/// RFC 5176 default dynamic authorization port.
+const DEFAULT_DAE_PORT: u16 = 3799;
+
+pub static NAS_CLIENT: OnceCell<Arc<UdpNasClient>> = OnceCell::new();
+
+#[derive(Clone)]
+pub struct NasEntry {
+    pub addr: SocketAddr,
+    pub secret: String,
+}
+
+impl std::fmt::Debug for NasEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NasEntry")
+            .field("addr", &self.addr)
+            .field("secret", &"***")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NasSettings {
+    /// NAS IP address as stored in `radacct.nasipaddress` -> DAE endpoint.
+    pub clients: HashMap<IpAddr, NasEntry>,
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl NasSettings {
+    /// Returns `None` when `RADIUS_NAS_CLIENTS` is not set, i.e. no
+    /// Disconnect-Requests are sent.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(raw) = var("RADIUS_NAS_CLIENTS") else {
+            return Ok(None);
+        };
+
+        let timeout = match var("RADIUS_DISCONNECT_TIMEOUT_MS") {
+            Some(v) => v
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid RADIUS_DISCONNECT_TIMEOUT_MS {:?}", v))?,
+            None => 2000,
+        };
+
+        let retries = match var("RADIUS_DISCONNECT_RETRIES") {
+            Some(v) => v
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| anyhow!("invalid RADIUS_DISCONNECT_RETRIES {:?}", v))?,
+            None => 2,
+        };
+
+        Ok(Some(Self {
+            clients: parse_nas_clients(&raw)?,
+            timeout: Duration::from_millis(timeout),
+            retries,
+        }))
+    }
+}
+
+/// Parse `10.0.0.1=secret;10.0.0.2:1700=secret2`.
+fn parse_nas_clients(raw: &str) -> Result<HashMap<IpAddr, NasEntry>> {
+    raw.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (host, secret) = entry
+                .split_once('=')
+                .filter(|(_, secret)| !secret.is_empty())
+                .ok_or_else(|| anyhow!("invalid nas client {:?}", entry))?;
+
+            let addr = host
+                .trim()
+                .parse::<SocketAddr>()
+                .or_else(|_| {
+                    host.trim()
+                        .parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, DEFAULT_DAE_PORT))
+                })
+                .map_err(|_| anyhow!("invalid nas address {:?}", host))?;
+
+            Ok((
+                addr.ip(),
+                NasEntry {
+                    addr,
+                    secret: secret.to_string(),
+                },
+            ))
+        })
+        .collect()
+}
+
+pub fn init_nas_client(settings: NasSettings) {
+    NAS_CLIENT
+        .set(Arc::new(UdpNasClient::new(settings)))
+        .expect("❌ NAS_CLIENT is already initialized");
+}
+
+/// The configured NAS client, if any NAS was configured.
+pub fn nas_client() -> Option<Arc<dyn NasClient>> {
+    NAS_CLIENT
+        .get()
+        .map(|client| client.clone() as Arc<dyn NasClient>)
+}
+
+#[derive(Debug)]
+pub struct UdpNasClient {
+    settings: NasSettings,
+    next_id: AtomicU8,
+}
+
+impl UdpNasClient {
+    pub fn new(settings: NasSettings) -> Self {
+        Self {
+            settings,
+            next_id: AtomicU8::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl NasClient for UdpNasClient {
+    async fn disconnect(&self, session: &RadiusSession) -> Result<DisconnectOutcome> {
+        let nas = match session
+            .nas_ip_address
+            .parse::<IpAddr>()
+            .ok()
+            .and_then(|ip| self.settings.clients.get(&ip))
+        {
+            Some(nas) => nas,
+            None => return Ok(DisconnectOutcome::UnknownNas),
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = encode_disconnect_request(id, session, &nas.secret);
+
+        let bind_addr = if nas.addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(nas.addr).await?;
+
+        let mut buf = [0u8; 4096];
+        for _ in 0..=self.settings.retries {
+            socket.send(&request).await?;
+
+            let n = match tokio::time::timeout(self.settings.timeout, socket.recv(&mut buf)).await {
+                Ok(read) => read?,
+                Err(_) => continue,
+            };
+
+            match decode_response(&buf[..n], &request, &nas.secret) {
+                Some(outcome) => return Ok(outcome),
+                None => tracing::warn!("invalid disconnect response from {}", nas.addr),
+            }
+        }
+
+        Ok(DisconnectOutcome::Timeout)
+    }
+}
+
+fn push_attr(attrs: &mut Vec<u8>, attr_type: u8, value: &[u8]) {
+    // Attribute values are limited to 253 octets.
+    let value = &value[..value.len().min(253)];
+    attrs.push(attr_type);
+    attrs.push(value.len() as u8 + 2);
+    attrs.extend_from_slice(value);
+}
+
+fn push_ipv4_attr(attrs: &mut Vec<u8>, attr_type: u8, value: &str) {
+    if let Ok(IpAddr::V4(ip)) = value.parse::<IpAddr>() {
+        push_attr(attrs, attr_type, &ip.octets());
+    }
+}
+
+/// Build a Disconnect-Request identifying the session; the Request
+/// Authenticator is MD5(Code + Identifier + Length + 16 zero octets +
+/// Attributes + Secret) as defined by RFC 5176 section 3.
+pub fn encode_disconnect_request(id: u8, session: &RadiusSession, secret: &str) -> Vec<u8> {
+    let mut attrs = Vec::new();
+    push_attr(&mut attrs, ATTR_USER_NAME, session.username.as_bytes());
+    push_attr(
+        &mut attrs,
+        ATTR_ACCT_SESSION_ID,
+        session.acct_session_id.as_bytes(),
+    );
+    push_ipv4_attr(&mut attrs, ATTR_NAS_IP_ADDRESS, &session.nas_ip_address);
+    if let Some(ip) = &session.framed_ip_address {
+        push_ipv4_attr(&mut attrs, ATTR_FRAMED_IP_ADDRESS, ip);
+    }
+    if let Some(mac) = &session.calling_station_id {
+        push_attr(&mut attrs, ATTR_CALLING_STATION_ID, mac.as_bytes());
+    }
+
+    let length = (20 + attrs.len()) as u16;
+    let mut packet = Vec::with_capacity(length as usize);
+    packet.push(DISCONNECT_REQUEST);
+    packet.push(id);
+    packet.extend_from_slice(&length.to_be_bytes());
+    packet.extend_from_slice(&[0u8; 16]);
+    packet.extend_from_slice(&attrs);
+
+    let mut signed = packet.clone();
+    signed.extend_from_slice(secret.as_bytes());
+    let authenticator = md5::compute(&signed);
+    packet[4..20].copy_from_slice(&authenticator.0);
+    packet
+}
+
+/// Response Authenticator: MD5(Code + Identifier + Length + Request
+/// Authenticator + Attributes + Secret).
+pub fn response_authenticator(response: &[u8], request_auth: &[u8], secret: &str) -> [u8; 16] {
+    let mut signed = Vec::with_capacity(response.len() + secret.len());
+    signed.extend_from_slice(&response[..4]);
+    signed.extend_from_slice(request_auth);
+    signed.extend_from_slice(&response[20..]);
+    signed.extend_from_slice(secret.as_bytes());
+    md5::compute(&signed).0
+}
+
+fn decode_response(response: &[u8], request: &[u8], secret: &str) -> Option<DisconnectOutcome> {
+    if response.len() < 20 || response[1] != request[1] {
+        return None;
+    }
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    if length < 20 || length > response.len() {
+        return None;
+    }
+    let response = &response[..length];
+
+    if response_authenticator(response, &request[4..20], secret) != response[4..20] {
+        return None;
+    }
+
+    match response[0] {
+        DISCONNECT_ACK => Some(DisconnectOutcome::Ack),
+        DISCONNECT_NAK => Some(DisconnectOutcome::Nak(error_cause(&response[20..]))),
+        _ => None,
+    }
+}
+
+fn error_cause(mut attrs: &[u8]) -> Option<u32> {
+    while attrs.len() >= 2 {
+        let (attr_type, len) = (attrs[0], attrs[1] as usize);
+        if len < 2 || len > attrs.len() {
+            return None;
+        }
+        if attr_type == ATTR_ERROR_CAUSE && len == 6 {
+            return Some(u32::from_be_bytes([attrs[2], attrs[3], attrs[4], attrs[5]]));
+        }
+        attrs = &attrs[len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "testing123";
+
+    fn session() -> RadiusSession {
+        RadiusSession {
+            username: "101".into(),
+            nas_ip_address: "127.0.0.1".into(),
+            acct_session_id: "80000001".into(),
+            framed_ip_address: Some("10.10.0.5".into()),
+            calling_station_id: Some("AA-BB-CC-DD-EE-FF".into()),
+        }
+    }
+
+    fn client(addr: SocketAddr) -> UdpNasClient {
+        UdpNasClient::new(NasSettings {
+            clients: [(
+                addr.ip(),
+                NasEntry {
+                    addr,
+                    secret: SECRET.into(),
+                },
+            )]
+            .into_iter()
+            .collect(),
+            timeout: Duration::from_millis(100),
+            retries: 1,
+        })
+    }
+
+    /// Stand-in NAS answering one request with `code` and optional attributes.
+    async fn stand_in_nas(
+        code: u8,
+        attrs: Vec<u8>,
+    ) -> (SocketAddr, tokio::task::JoinHandle<Vec<u8>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let request = buf[..n].to_vec();
+
+            let mut response = vec![code, request[1], 0, 0];
+            response.extend_from_slice(&[0u8; 16]);
+            response.extend_from_slice(&attrs);
+            let length = (response.len() as u16).to_be_bytes();
+            response[2..4].copy_from_slice(&length);
+            let auth = response_authenticator(&response, &request[4..20], SECRET);
+            response[4..20].copy_from_slice(&auth);
+
+            socket.send_to(&response, peer).await.unwrap();
+            request
+        });
+
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn disconnect_ack() {
+        let (addr, nas) = stand_in_nas(DISCONNECT_ACK, Vec::new()).await;
+
+        let outcome = client(addr).disconnect(&session()).await.unwrap();
+        assert_eq!(outcome, DisconnectOutcome::Ack);
+
+        let request = nas.await.unwrap();
+        assert_eq!(request[0], DISCONNECT_REQUEST);
+        assert_eq!(
+            u16::from_be_bytes([request[2], request[3]]) as usize,
+            request.len()
+        );
+
+        // Request Authenticator must verify with the shared secret.
+        let mut unsigned = request.clone();
+        unsigned[4..20].copy_from_slice(&[0u8; 16]);
+        unsigned.extend_from_slice(SECRET.as_bytes());
+        assert_eq!(md5::compute(&unsigned).0, request[4..20]);
+
+        let attrs = &request[20..];
+        assert_eq!(&attrs[..5], &[ATTR_USER_NAME, 5, b'1', b'0', b'1']);
+        assert!(
+            attrs
+                .windows(6)
+                .any(|w| w == [ATTR_FRAMED_IP_ADDRESS, 6, 10, 10, 0, 5])
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect_nak_with_error_cause() {
+        let (addr, _nas) =
+            stand_in_nas(DISCONNECT_NAK, vec![ATTR_ERROR_CAUSE, 6, 0, 0, 1, 247]).await;
+
+        let outcome = client(addr).disconnect(&session()).await.unwrap();
+        assert_eq!(outcome, DisconnectOutcome::Nak(Some(503)));
+    }
+
+    #[tokio::test]
+    async fn disconnect_times_out_without_answer() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let outcome = client(silent.local_addr().unwrap())
+            .disconnect(&session())
+            .await
+            .unwrap();
+        assert_eq!(outcome, DisconnectOutcome::Timeout);
+    }
+
+    #[tokio::test]
+    async fn disconnect_unknown_nas() {
+        let mut s = session();
+        s.nas_ip_address = "192.0.2.1".into();
+
+        let outcome = client("127.0.0.1:3799".parse().unwrap())
+            .disconnect(&s)
+            .await
+            .unwrap();
+        assert_eq!(outcome, DisconnectOutcome::UnknownNas);
+    }
+
+    #[test]
+    fn parses_nas_clients() {
+        let clients = parse_nas_clients("10.0.0.1=secret; 10.0.0.2:1700=s=2").unwrap();
+
+        let first = &clients[&"10.0.0.1".parse::<IpAddr>().unwrap()];
+        assert_eq!(first.addr, "10.0.0.1:3799".parse().unwrap());
+        assert_eq!(first.secret, "secret");
+
+        let second = &clients[&"10.0.0.2".parse::<IpAddr>().unwrap()];
+        assert_eq!(second.addr.port(), 1700);
+        assert_eq!(second.secret, "s=2");
+
+        assert!(parse_nas_clients("10.0.0.1").is_err());
+        assert!(parse_nas_clients("nas=secret").is_err());
+    }
+}
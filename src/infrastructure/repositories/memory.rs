@@ -0,0 +1,614 @@
+use crate::domain::{
+    entities::{
+        AuditEntry, AuditFilter, Booking, GuestFilter, GuestProfile, HotelService, PasswordStorage,
+        Property, RadiusAttribute, RadiusSchema, RadiusSession, ReconcilePlan, StoredCredential,
+    },
+    errors::StorageError,
+    repositories::BookingRepository,
+};
+use crate::infrastructure::repositories::{
+    check_leaving, check_moved, check_occupants, check_service,
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotelRoomRow {
+    pub property_id: Option<String>,
+    pub room_number: String,
+    pub username: String,
+    pub password: String,
+    pub name: String,
+    pub service_id: i32,
+    pub folio_number: String,
+    pub checkin_date: NaiveDateTime,
+    pub checkout_date: NaiveDateTime,
+    pub status: String,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// Row shape shared by radcheck and radreply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadCheckRow {
+    pub username: String,
+    pub attribute: String,
+    pub op: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadUserGroupRow {
+    pub username: String,
+    pub groupname: String,
+    pub priority: i32,
+    pub user_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceRow {
+    pub id: i32,
+    pub service_name: String,
+    pub cron: bool,
+    pub cron_type: String,
+    pub simultaneous_use: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadAcctRow {
+    pub username: String,
+    pub nasipaddress: String,
+    pub acctsessionid: String,
+    pub framedipaddress: Option<String>,
+    pub callingstationid: Option<String>,
+    pub acctstoptime: Option<NaiveDateTime>,
+}
+
+/// `pms_audit_log` row: the entry plus the property it was recorded for.
+#[derive(Debug, Clone)]
+pub struct AuditLogRow {
+    pub property_id: Option<String>,
+    pub entry: AuditEntry,
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryTables {
+    pub hotel_rooms: Vec<HotelRoomRow>,
+    pub radcheck: Vec<RadCheckRow>,
+    pub radreply: Vec<RadCheckRow>,
+    pub radusergroup: Vec<RadUserGroupRow>,
+    pub services: Vec<ServiceRow>,
+    pub radacct: Vec<RadAcctRow>,
+    pub pms_audit_log: Vec<AuditLogRow>,
+}
+
+/// `BookingRepository` backed by plain vectors, mirroring the SQL schema
+/// closely enough to exercise `BookingService` without a database.
+#[derive(Default)]
+pub struct InMemoryBookingRepository {
+    tables: Arc<Mutex<MemoryTables>>,
+    schema: RadiusSchema,
+    property: Option<Property>,
+}
+
+impl InMemoryBookingRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_schema(mut self, schema: RadiusSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Repository over the same tables, scoped to `property`.
+    pub fn for_property(&self, property: Property) -> Self {
+        Self {
+            tables: self.tables.clone(),
+            schema: self.schema.clone(),
+            property: Some(property),
+        }
+    }
+
+    fn in_scope(&self, property_id: &Option<String>) -> bool {
+        property_id.as_ref() == self.property.as_ref().map(|p| &p.id)
+    }
+
+    /// Guests of `room` as `(username, folio)`.
+    fn occupants(&self, tables: &MemoryTables, room: &str) -> Vec<(String, String)> {
+        tables
+            .hotel_rooms
+            .iter()
+            .filter(|r| self.in_scope(&r.property_id) && r.room_number == room)
+            .map(|r| (r.username.clone(), r.folio_number.clone()))
+            .collect()
+    }
+
+    /// Repository seeded with one active `cron_type = 'hotel'` service.
+    pub fn with_hotel_service(id: i32, service_name: &str) -> Self {
+        let repo = Self::new();
+        repo.add_hotel_service(id, service_name);
+        repo
+    }
+
+    pub fn add_hotel_service(&self, id: i32, service_name: &str) {
+        self.tables().services.push(ServiceRow {
+            id,
+            service_name: service_name.to_string(),
+            cron: true,
+            cron_type: "hotel".into(),
+            simultaneous_use: None,
+        });
+    }
+
+    pub fn tables(&self) -> MutexGuard<'_, MemoryTables> {
+        self.tables.lock().expect("memory tables poisoned")
+    }
+}
+
+#[async_trait]
+impl BookingRepository for InMemoryBookingRepository {
+    async fn checkin_repo(&self, booking: &Booking, occupants: &[GuestProfile]) -> Result<()> {
+        let mut tables = self.tables();
+        let found = self.occupants(&tables, &booking.room_number);
+        check_occupants(&booking.room_number, occupants, found)?;
+        if let Some(service) = &booking.service {
+            let active = tables
+                .services
+                .iter()
+                .any(|s| s.id == service.id && s.cron && s.cron_type == self.schema.service_type);
+            check_service(service, active)?;
+        }
+        insert_guest(&mut tables, &self.schema, &self.property, booking)
+    }
+
+    async fn checkout_repo(&self, booking: &Booking) -> Result<Vec<String>> {
+        let mut tables = self.tables();
+        let found = self.occupants(&tables, &booking.room_number);
+        check_leaving(
+            &booking.room_number,
+            booking.folio_number.as_deref(),
+            &found,
+        )?;
+        Ok(remove_guests(
+            &mut tables,
+            &self.property,
+            &booking.room_number,
+            booking.folio_number.as_deref(),
+        ))
+    }
+
+    async fn update_repo(
+        &self,
+        guest: &GuestProfile,
+        booking: &Booking,
+        occupants: &[GuestProfile],
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        let found = self.occupants(&tables, &booking.room_number);
+        check_occupants(&booking.room_number, occupants, found)?;
+        move_guest(&mut tables, &self.schema, &self.property, guest, booking)
+    }
+
+    async fn get_cron_hotel_service(&self) -> Result<Vec<HotelService>> {
+        Ok(self
+            .tables()
+            .services
+            .iter()
+            .filter(|s| s.cron && s.cron_type == self.schema.service_type)
+            .map(|s| HotelService {
+                id: s.id,
+                name: s.service_name.clone(),
+                simultaneous_use: s.simultaneous_use,
+            })
+            .collect())
+    }
+
+    async fn room_guests(&self, room_number: &str) -> Result<Vec<GuestProfile>> {
+        let mut rows: Vec<HotelRoomRow> = self
+            .tables()
+            .hotel_rooms
+            .iter()
+            .filter(|r| self.in_scope(&r.property_id) && r.room_number == room_number)
+            .cloned()
+            .collect();
+        rows.sort_by_key(|r| r.checkin_date);
+        Ok(rows.iter().map(HotelRoomRow::profile).collect())
+    }
+
+    async fn list_guests(&self, filter: &GuestFilter) -> Result<Vec<GuestProfile>> {
+        let mut rows: Vec<HotelRoomRow> = self
+            .tables()
+            .hotel_rooms
+            .iter()
+            .filter(|r| self.in_scope(&r.property_id))
+            .filter(|r| filter.service_id.is_none_or(|id| r.service_id == id))
+            .filter(|r| {
+                filter
+                    .checkout_from
+                    .is_none_or(|from| r.checkout_date >= from)
+            })
+            .filter(|r| filter.checkout_to.is_none_or(|to| r.checkout_date < to))
+            .cloned()
+            .collect();
+        rows.sort_by(|a, b| {
+            (&a.room_number, a.checkin_date).cmp(&(&b.room_number, b.checkin_date))
+        });
+        Ok(rows.iter().map(HotelRoomRow::profile).collect())
+    }
+
+    async fn hotel_credentials(&self) -> Result<Vec<StoredCredential>> {
+        let tables = self.tables();
+        let mut credentials: Vec<StoredCredential> = tables
+            .radusergroup
+            .iter()
+            .filter(|g| g.user_type == self.schema.user_type)
+            .filter(|g| {
+                self.property
+                    .as_ref()
+                    .is_none_or(|p| g.username.ends_with(&p.realm))
+            })
+            .map(|g| {
+                let password = tables.radcheck.iter().find(|c| {
+                    c.username == g.username
+                        && PasswordStorage::ALL
+                            .iter()
+                            .any(|s| s.attribute() == c.attribute)
+                });
+                StoredCredential {
+                    username: g.username.clone(),
+                    password_attribute: password.map(|c| c.attribute.clone()),
+                    password: password.map(|c| c.value.clone()),
+                    groupname: g.groupname.clone(),
+                }
+            })
+            .collect();
+        credentials.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(credentials)
+    }
+
+    async fn apply_reconciliation(&self, plan: &ReconcilePlan) -> Result<Vec<String>> {
+        let mut tables = self.tables();
+        for (room, occupants) in &plan.occupants {
+            let found = self.occupants(&tables, room);
+            check_occupants(room, occupants, found)?;
+        }
+        // Stands in for the transaction: restored when any step fails.
+        let backup = (
+            tables.hotel_rooms.clone(),
+            tables.radcheck.clone(),
+            tables.radreply.clone(),
+            tables.radusergroup.clone(),
+        );
+
+        let mut released = Vec::new();
+        for guest in &plan.checkouts {
+            let folio = guest.folio_number.as_deref().unwrap_or("");
+            released.extend(remove_guests(
+                &mut tables,
+                &self.property,
+                &guest.room_number,
+                Some(folio),
+            ));
+        }
+        for username in &plan.orphans {
+            delete_credential(&mut tables, username);
+            released.push(username.clone());
+        }
+        let result = plan
+            .updates
+            .iter()
+            .try_for_each(|(guest, booking)| {
+                move_guest(&mut tables, &self.schema, &self.property, guest, booking)
+            })
+            .and_then(|_| {
+                plan.checkins.iter().try_for_each(|booking| {
+                    insert_guest(&mut tables, &self.schema, &self.property, booking)
+                })
+            });
+
+        if let Err(err) = result {
+            (
+                tables.hotel_rooms,
+                tables.radcheck,
+                tables.radreply,
+                tables.radusergroup,
+            ) = backup;
+            return Err(err);
+        }
+        Ok(released)
+    }
+
+    async fn open_sessions(&self, username: &str) -> Result<Vec<RadiusSession>> {
+        Ok(self
+            .tables()
+            .radacct
+            .iter()
+            .filter(|r| r.username == username && r.acctstoptime.is_none())
+            .map(|r| RadiusSession {
+                username: r.username.clone(),
+                nas_ip_address: r.nasipaddress.clone(),
+                acct_session_id: r.acctsessionid.clone(),
+                framed_ip_address: r.framedipaddress.clone(),
+                calling_station_id: r.callingstationid.clone(),
+            })
+            .collect())
+    }
+
+    async fn record_audit(&self, entry: &AuditEntry) -> Result<()> {
+        let mut tables = self.tables();
+        let id = tables.pms_audit_log.len() as i64 + 1;
+        tables.pms_audit_log.push(AuditLogRow {
+            property_id: self.property.as_ref().map(|p| p.id.clone()),
+            entry: AuditEntry {
+                id: Some(id),
+                ..entry.clone()
+            },
+        });
+        Ok(())
+    }
+
+    async fn audit_history(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let matches = |e: &AuditEntry| {
+            filter.room.as_ref().is_none_or(|room| {
+                e.room_number.as_ref() == Some(room) || e.old_room.as_ref() == Some(room)
+            }) && filter
+                .folio_number
+                .as_ref()
+                .is_none_or(|folio| e.folio_number.as_ref() == Some(folio))
+                && filter.from.is_none_or(|from| e.created_at >= from)
+                && filter.to.is_none_or(|to| e.created_at < to)
+        };
+
+        Ok(self
+            .tables()
+            .pms_audit_log
+            .iter()
+            .rev()
+            .filter(|r| self.in_scope(&r.property_id))
+            .map(|r| &r.entry)
+            .filter(|e| matches(e))
+            .take(filter.limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_replay(
+        &self,
+        idempotency_key: Option<&str>,
+        fingerprint: &str,
+        since: NaiveDateTime,
+    ) -> Result<Option<AuditEntry>> {
+        let tables = self.tables();
+        let successes: Vec<&AuditEntry> = tables
+            .pms_audit_log
+            .iter()
+            .rev()
+            .filter(|r| self.in_scope(&r.property_id))
+            .map(|r| &r.entry)
+            .filter(|e| e.outcome == "success")
+            .collect();
+        let touches = |later: &AuditEntry, e: &AuditEntry| {
+            let rooms = [e.room_number.as_ref(), e.old_room.as_ref()];
+            (later.folio_number.is_some() && later.folio_number == e.folio_number)
+                || [later.room_number.as_ref(), later.old_room.as_ref()]
+                    .iter()
+                    .any(|room| room.is_some() && rooms.contains(room))
+        };
+
+        Ok(successes
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.created_at >= since)
+            .find(|(i, e)| match idempotency_key {
+                Some(key) => e.idempotency_key.as_deref() == Some(key),
+                None => {
+                    e.fingerprint.as_deref() == Some(fingerprint)
+                        && !successes[..*i].iter().any(|later| touches(later, e))
+                }
+            })
+            .map(|(_, e)| (*e).clone()))
+    }
+
+    async fn overdue_rooms(&self, cutoff: NaiveDateTime) -> Result<Vec<GuestProfile>> {
+        let tables = self.tables();
+        let mut rows: Vec<&HotelRoomRow> = tables
+            .hotel_rooms
+            .iter()
+            .filter(|r| self.in_scope(&r.property_id) && r.checkout_date < cutoff)
+            .filter(|r| {
+                tables.services.iter().any(|s| {
+                    s.id == r.service_id && s.cron && s.cron_type == self.schema.service_type
+                })
+            })
+            .collect();
+        rows.sort_by_key(|r| r.checkout_date);
+        Ok(rows.into_iter().map(HotelRoomRow::profile).collect())
+    }
+
+    async fn is_room_active(&self, room_number: &str) -> Result<bool> {
+        Ok(self
+            .tables()
+            .hotel_rooms
+            .iter()
+            .any(|r| self.in_scope(&r.property_id) && r.room_number == room_number))
+    }
+}
+
+impl HotelRoomRow {
+    fn profile(&self) -> GuestProfile {
+        GuestProfile {
+            room_number: self.room_number.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+            name: Some(self.name.clone()).filter(|n| !n.is_empty()),
+            folio_number: Some(self.folio_number.clone()).filter(|f| !f.is_empty()),
+            service_id: self.service_id,
+            checkin_date: self.checkin_date,
+            checkout_date: self.checkout_date,
+        }
+    }
+}
+
+fn insert_guest(
+    tables: &mut MemoryTables,
+    schema: &RadiusSchema,
+    property: &Option<Property>,
+    booking: &Booking,
+) -> Result<()> {
+    let property_id = property.as_ref().map(|p| p.id.clone());
+    let service = booking
+        .service
+        .as_ref()
+        .ok_or_else(|| anyhow!("No hotel service selected for checkin"))?;
+
+    let folio = booking.folio_number.clone().unwrap_or_default();
+    if tables.hotel_rooms.iter().any(|r| {
+        r.property_id == property_id
+            && r.room_number == booking.room_number
+            && r.folio_number == folio
+    }) {
+        return Err(anyhow::Error::new(StorageError::Conflict).context(format!(
+            "duplicate entry {} for hotel_rooms",
+            booking.room_number
+        )));
+    }
+
+    tables.hotel_rooms.push(HotelRoomRow {
+        property_id,
+        room_number: booking.room_number.clone(),
+        username: booking.username.clone(),
+        password: booking.password.clone(),
+        name: booking.name.clone().unwrap_or_default(),
+        service_id: service.id,
+        folio_number: folio,
+        checkin_date: booking.checkin_date,
+        checkout_date: booking.checkout_date,
+        status: "active".into(),
+        updated_at: None,
+    });
+    write_credential(tables, schema, booking, service);
+    Ok(())
+}
+
+fn remove_guests(
+    tables: &mut MemoryTables,
+    property: &Option<Property>,
+    room: &str,
+    folio: Option<&str>,
+) -> Vec<String> {
+    let property_id = property.as_ref().map(|p| &p.id);
+    let leaving = |r: &HotelRoomRow| {
+        r.property_id.as_ref() == property_id
+            && r.room_number == room
+            && folio.is_none_or(|folio| r.folio_number == folio)
+    };
+
+    let mut usernames: Vec<String> = tables
+        .hotel_rooms
+        .iter()
+        .filter(|r| leaving(r))
+        .map(|r| r.username.clone())
+        .collect();
+    usernames.dedup();
+    tables.hotel_rooms.retain(|r| !leaving(r));
+
+    usernames.retain(|u| !tables.hotel_rooms.iter().any(|r| &r.username == u));
+    for username in &usernames {
+        delete_credential(tables, username);
+    }
+    usernames
+}
+
+fn move_guest(
+    tables: &mut MemoryTables,
+    schema: &RadiusSchema,
+    property: &Option<Property>,
+    guest: &GuestProfile,
+    booking: &Booking,
+) -> Result<()> {
+    let service = booking
+        .service
+        .as_ref()
+        .ok_or_else(|| anyhow!("No hotel service selected for update"))?;
+
+    let now = booking.recorded_at;
+    let old_folio = guest.folio_number.clone().unwrap_or_default();
+    let property_id = property.as_ref().map(|p| &p.id);
+
+    let moving = |r: &HotelRoomRow| {
+        r.username == guest.username
+            && r.folio_number == old_folio
+            && r.property_id.as_ref() == property_id
+    };
+    let rows = tables.hotel_rooms.iter().filter(|r| moving(r)).count();
+    check_moved(guest, rows as u64)?;
+
+    for row in tables.hotel_rooms.iter_mut().filter(|r| moving(r)) {
+        row.room_number = booking.room_number.clone();
+        row.username = booking.username.clone();
+        row.password = booking.password.clone();
+        row.name = booking.name.clone().unwrap_or_default();
+        row.folio_number = booking.folio_number.clone().unwrap_or_default();
+        row.service_id = service.id;
+        row.checkin_date = booking.checkin_date;
+        row.checkout_date = booking.checkout_date;
+        row.updated_at = Some(now);
+    }
+
+    if booking.username != guest.username
+        && !tables
+            .hotel_rooms
+            .iter()
+            .any(|r| r.username == guest.username)
+    {
+        delete_credential(tables, &guest.username);
+    }
+    write_credential(tables, schema, booking, service);
+    Ok(())
+}
+
+fn write_credential(
+    tables: &mut MemoryTables,
+    schema: &RadiusSchema,
+    booking: &Booking,
+    service: &HotelService,
+) {
+    delete_credential(tables, &booking.username);
+    insert_radius_rows(tables, schema, booking);
+    tables.radusergroup.push(RadUserGroupRow {
+        username: booking.username.clone(),
+        groupname: service.name.clone(),
+        priority: 1,
+        user_type: schema.user_type.clone(),
+    });
+}
+
+fn delete_credential(tables: &mut MemoryTables, username: &str) {
+    tables.radcheck.retain(|r| r.username != username);
+    tables.radreply.retain(|r| r.username != username);
+    tables.radusergroup.retain(|r| r.username != username);
+}
+
+fn insert_radius_rows(tables: &mut MemoryTables, schema: &RadiusSchema, booking: &Booking) {
+    let row = |attr: &RadiusAttribute| RadCheckRow {
+        username: booking.username.clone(),
+        attribute: attr.attribute.clone(),
+        op: attr.op.clone(),
+        value: attr.value.clone(),
+    };
+
+    let password = RadiusAttribute::new(
+        schema.password_storage.attribute(),
+        ":=",
+        booking.password.clone(),
+    );
+    tables.radcheck.push(row(&password));
+    tables
+        .radcheck
+        .extend(booking.check_attributes.iter().map(row));
+    tables
+        .radreply
+        .extend(booking.reply_attributes.iter().map(row));
+}
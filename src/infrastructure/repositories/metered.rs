@@ -0,0 +1,112 @@
+use super::classify;
+use crate::application::metrics::METRICS;
+use crate::domain::{
+    entities::{
+        AuditEntry, AuditFilter, Booking, GuestFilter, GuestProfile, HotelService, RadiusSession,
+        ReconcilePlan, StoredCredential,
+    },
+    repositories::BookingRepository,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Records the latency of every call to the wrapped repository and
+/// classifies the sqlx errors it fails with.
+pub struct MeteredRepository<R: BookingRepository + ?Sized> {
+    pub inner: Arc<R>,
+}
+
+#[async_trait]
+impl<R: BookingRepository + ?Sized> BookingRepository for MeteredRepository<R> {
+    async fn checkin_repo(&self, booking: &Booking, occupants: &[GuestProfile]) -> Result<()> {
+        timed("checkin_repo", self.inner.checkin_repo(booking, occupants)).await
+    }
+
+    async fn checkout_repo(&self, booking: &Booking) -> Result<Vec<String>> {
+        timed("checkout_repo", self.inner.checkout_repo(booking)).await
+    }
+
+    async fn update_repo(
+        &self,
+        guest: &GuestProfile,
+        booking: &Booking,
+        occupants: &[GuestProfile],
+    ) -> Result<()> {
+        timed(
+            "update_repo",
+            self.inner.update_repo(guest, booking, occupants),
+        )
+        .await
+    }
+
+    async fn get_cron_hotel_service(&self) -> Result<Vec<HotelService>> {
+        timed(
+            "get_cron_hotel_service",
+            self.inner.get_cron_hotel_service(),
+        )
+        .await
+    }
+
+    async fn is_room_active(&self, room_number: &str) -> Result<bool> {
+        timed("is_room_active", self.inner.is_room_active(room_number)).await
+    }
+
+    async fn room_guests(&self, room_number: &str) -> Result<Vec<GuestProfile>> {
+        timed("room_guests", self.inner.room_guests(room_number)).await
+    }
+
+    async fn list_guests(&self, filter: &GuestFilter) -> Result<Vec<GuestProfile>> {
+        timed("list_guests", self.inner.list_guests(filter)).await
+    }
+
+    async fn overdue_rooms(&self, cutoff: NaiveDateTime) -> Result<Vec<GuestProfile>> {
+        timed("overdue_rooms", self.inner.overdue_rooms(cutoff)).await
+    }
+
+    async fn hotel_credentials(&self) -> Result<Vec<StoredCredential>> {
+        timed("hotel_credentials", self.inner.hotel_credentials()).await
+    }
+
+    async fn apply_reconciliation(&self, plan: &ReconcilePlan) -> Result<Vec<String>> {
+        timed(
+            "apply_reconciliation",
+            self.inner.apply_reconciliation(plan),
+        )
+        .await
+    }
+
+    async fn open_sessions(&self, username: &str) -> Result<Vec<RadiusSession>> {
+        timed("open_sessions", self.inner.open_sessions(username)).await
+    }
+
+    async fn record_audit(&self, entry: &AuditEntry) -> Result<()> {
+        timed("record_audit", self.inner.record_audit(entry)).await
+    }
+
+    async fn audit_history(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        timed("audit_history", self.inner.audit_history(filter)).await
+    }
+
+    async fn find_replay(
+        &self,
+        idempotency_key: Option<&str>,
+        fingerprint: &str,
+        since: NaiveDateTime,
+    ) -> Result<Option<AuditEntry>> {
+        timed(
+            "find_replay",
+            self.inner.find_replay(idempotency_key, fingerprint, since),
+        )
+        .await
+    }
+}
+
+async fn timed<T>(operation: &str, call: impl Future<Output = Result<T>>) -> Result<T> {
+    METRICS
+        .time_repository(operation, call)
+        .await
+        .map_err(classify)
+}
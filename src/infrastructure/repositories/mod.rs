@@ -0,0 +1,958 @@
+#[cfg(test)]
+pub mod memory;
+mod metered;
+mod mysql;
+mod postgres;
+mod sqlite;
+
+pub use metered::MeteredRepository;
+pub use mysql::MySqlBookingRepository;
+pub use postgres::PgBookingRepository;
+pub use sqlite::SqliteBookingRepository;
+
+use crate::application::settings::ServiceSettings;
+use crate::domain::{
+    entities::{
+        AuditEntry, AuditFilter, Booking, GuestFilter, GuestProfile, HotelService, PasswordStorage,
+        Property, RadiusAttribute, RadiusSchema, RadiusSession, ReconcilePlan, StoredCredential,
+    },
+    errors::StorageError,
+    repositories::BookingRepository,
+};
+use crate::infrastructure::database::{DbPool, db_pool};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::database::HasArguments;
+use sqlx::{
+    Arguments, ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool,
+    Transaction, Type,
+};
+use std::sync::Arc;
+
+/// Repository for whichever backend `DATABASE_URL` selected at startup,
+/// scoped to the property of `settings`, with call latencies recorded for
+/// `/metrics`.
+pub fn booking_repository(settings: &ServiceSettings) -> Arc<dyn BookingRepository> {
+    let schema = settings.radius.schema.clone();
+    let property = settings.property.clone();
+    let inner: Arc<dyn BookingRepository> = match db_pool() {
+        DbPool::MySql(pool) => Arc::new(MySqlBookingRepository {
+            pool: pool.clone(),
+            schema,
+            property,
+        }),
+        DbPool::Postgres(pool) => Arc::new(PgBookingRepository {
+            pool: pool.clone(),
+            schema,
+            property,
+        }),
+        DbPool::Sqlite(pool) => Arc::new(SqliteBookingRepository {
+            pool: pool.clone(),
+            schema,
+            property,
+        }),
+    };
+    Arc::new(MeteredRepository { inner })
+}
+
+/// `err` with a [`StorageError`] attached when sqlx reports a unique key
+/// violation, a deadlock or serialization failure, or a database it cannot
+/// reach, so the service answers 409 or 503 rather than 500.
+pub(crate) fn classify(err: anyhow::Error) -> anyhow::Error {
+    let kind = match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(db))
+            if db.is_unique_violation()
+                || matches!(db.code().as_deref(), Some("40001" | "40P01")) =>
+        {
+            StorageError::Conflict
+        }
+        Some(
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_),
+        ) => StorageError::Unavailable,
+        _ => return err,
+    };
+    err.context(kind)
+}
+
+/// Fails with [`StorageError::Stale`] unless the guests `found` in the
+/// room under the write's lock, as `(username, folio)`, are the `expected`
+/// guests the booking was planned against.
+pub(crate) fn check_occupants(
+    room: &str,
+    expected: &[GuestProfile],
+    mut found: Vec<(String, String)>,
+) -> anyhow::Result<()> {
+    let mut expected: Vec<(String, String)> = expected
+        .iter()
+        .map(|g| {
+            (
+                g.username.clone(),
+                g.folio_number.clone().unwrap_or_default(),
+            )
+        })
+        .collect();
+    expected.sort();
+    found.sort();
+    if expected == found {
+        Ok(())
+    } else {
+        Err(anyhow::Error::new(StorageError::Stale).context(format!(
+            "guests of room {} changed since they were read",
+            room
+        )))
+    }
+}
+
+/// Fails with [`StorageError::Stale`] when none of the guests a checkout of
+/// `room` (or of `folio` in it) was planned for is left under the lock.
+pub(crate) fn check_leaving(
+    room: &str,
+    folio: Option<&str>,
+    found: &[(String, String)],
+) -> anyhow::Result<()> {
+    if found
+        .iter()
+        .any(|(_, f)| folio.is_none_or(|folio| f == folio))
+    {
+        Ok(())
+    } else {
+        Err(anyhow::Error::new(StorageError::Stale)
+            .context(format!("guests of room {} left since they were read", room)))
+    }
+}
+
+/// Fails with [`StorageError::Stale`] unless the move rewrote exactly the
+/// one row of `guest`.
+pub(crate) fn check_moved(guest: &GuestProfile, rows: u64) -> anyhow::Result<()> {
+    if rows == 1 {
+        Ok(())
+    } else {
+        Err(anyhow::Error::new(StorageError::Stale).context(format!(
+            "guest {} of room {} changed since it was read",
+            guest.username, guest.room_number
+        )))
+    }
+}
+
+/// Fails with [`StorageError::InactiveService`] when the service a checkin
+/// was planned with is no longer an active hotel service.
+pub(crate) fn check_service(service: &HotelService, active: bool) -> anyhow::Result<()> {
+    if active {
+        Ok(())
+    } else {
+        Err(
+            anyhow::Error::new(StorageError::InactiveService).context(format!(
+                "service {} is no longer an active hotel service",
+                service.name
+            )),
+        )
+    }
+}
+
+/// `property_id` the rows of `property` are stored under, compared through
+/// `COALESCE(property_id, '')` so a single-property install matches `NULL`.
+pub(crate) fn property_scope(property: &Option<Property>) -> &str {
+    property.as_ref().map_or("", |p| p.id.as_str())
+}
+
+/// `LIKE` pattern of the RADIUS usernames of `property`.
+pub(crate) fn username_pattern(property: &Option<Property>) -> String {
+    format!("%{}", property.as_ref().map_or("", |p| p.realm.as_str()))
+}
+
+/// Columns of [`AuditRow`].
+pub(crate) const AUDIT_COLUMNS: &str = "id, mode, room_number, old_room, folio_number, params, outcome, code, message, idempotency_key, fingerprint, response, created_at";
+
+/// Condition on the `pms_audit_log a` entry a fingerprint matched: no later
+/// successful request for the same folio or rooms.
+pub(crate) const NOT_SUPERSEDED: &str = "NOT EXISTS (
+            SELECT 1 FROM pms_audit_log later
+            WHERE later.id > a.id AND later.outcome = 'success'
+              AND COALESCE(later.property_id, '') = COALESCE(a.property_id, '')
+              AND (later.folio_number = a.folio_number
+                OR later.room_number IN (a.room_number, a.old_room)
+                OR later.old_room IN (a.room_number, a.old_room)))";
+
+/// `pms_audit_log` row as selected by the SQL repositories.
+#[derive(sqlx::FromRow)]
+pub(crate) struct AuditRow {
+    id: i64,
+    mode: String,
+    room_number: Option<String>,
+    old_room: Option<String>,
+    folio_number: Option<String>,
+    params: String,
+    outcome: String,
+    code: Option<String>,
+    message: String,
+    idempotency_key: Option<String>,
+    fingerprint: Option<String>,
+    response: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+impl AuditRow {
+    fn into_entry(self) -> AuditEntry {
+        AuditEntry {
+            id: Some(self.id),
+            mode: self.mode,
+            room_number: self.room_number,
+            old_room: self.old_room,
+            folio_number: self.folio_number,
+            params: self.params,
+            outcome: self.outcome,
+            code: self.code,
+            message: self.message,
+            idempotency_key: self.idempotency_key,
+            fingerprint: self.fingerprint,
+            response: self.response,
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// Columns of [`GuestRow`], selected from `hotel_rooms h`.
+pub(crate) const GUEST_COLUMNS: &str = "h.room_number, h.username, h.password, h.name, h.folio_number, h.service_id, h.checkin_date, h.checkout_date";
+
+/// `hotel_rooms` row as selected by the SQL repositories.
+#[derive(sqlx::FromRow)]
+pub(crate) struct GuestRow {
+    room_number: String,
+    username: String,
+    password: String,
+    name: Option<String>,
+    folio_number: Option<String>,
+    service_id: i32,
+    checkin_date: NaiveDateTime,
+    checkout_date: NaiveDateTime,
+}
+
+impl GuestRow {
+    fn into_profile(self) -> GuestProfile {
+        GuestProfile {
+            room_number: self.room_number,
+            username: self.username,
+            password: self.password,
+            name: self.name.filter(|n| !n.is_empty()),
+            folio_number: self.folio_number.filter(|f| !f.is_empty()),
+            service_id: self.service_id,
+            checkin_date: self.checkin_date,
+            checkout_date: self.checkout_date,
+        }
+    }
+}
+
+/// What differs between the SQL backends; everything else in
+/// [`SqlBookingRepository`] is written once.
+pub(crate) trait Dialect: Database {
+    /// Writes the statement that serializes writes to `room` until the
+    /// transaction ends, when reading its guests with
+    /// [`Self::ROOM_READ_LOCK`] does not.
+    fn room_lock<'q>(_query: &mut Statement<'q, Self>, _scope: &'q str, _room: &'q str) {}
+    /// Appended to the read of a room's guests under its lock.
+    const ROOM_READ_LOCK: &'static str;
+    /// Appended to the read of a service a checkin is about to use.
+    const SHARE_LOCK: &'static str;
+    /// `radacct` columns of a session, addresses as text.
+    const SESSION_COLUMNS: &'static str;
+
+    fn rows_affected(result: &Self::QueryResult) -> u64;
+}
+
+/// SQL text with its bound arguments, numbered the way the backend expects.
+///
+/// Unlike `sqlx::QueryBuilder` it is taken apart before it runs, which code
+/// generic over the database needs to satisfy the borrow checker.
+pub(crate) struct Statement<'q, DB: Database> {
+    sql: String,
+    args: <DB as HasArguments<'q>>::Arguments,
+}
+
+impl<'q, DB: Database> Statement<'q, DB> {
+    pub(crate) fn new(sql: impl Into<String>) -> Self {
+        Self {
+            sql: sql.into(),
+            args: Default::default(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    pub(crate) fn push_bind<T>(&mut self, value: T) -> &mut Self
+    where
+        T: 'q + Send + Encode<'q, DB> + Type<DB>,
+    {
+        self.args.add(value);
+        self.args
+            .format_placeholder(&mut self.sql)
+            .expect("writing to a String cannot fail");
+        self
+    }
+
+    /// `values` bound as a comma separated list.
+    fn push_list<T>(&mut self, values: impl IntoIterator<Item = T>) -> &mut Self
+    where
+        T: 'q + Send + Encode<'q, DB> + Type<DB>,
+    {
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.push_bind(value);
+        }
+        self
+    }
+
+    /// The SQL, to be borrowed by `sqlx::query_with`, and its arguments.
+    fn into_parts(self) -> (String, <DB as HasArguments<'q>>::Arguments) {
+        (self.sql, self.args)
+    }
+}
+
+/// Booking repository over any [`Dialect`].
+pub struct SqlBookingRepository<DB: Database> {
+    pub pool: Pool<DB>,
+    pub schema: RadiusSchema,
+    pub property: Option<Property>,
+}
+
+#[async_trait]
+impl<DB> BookingRepository for SqlBookingRepository<DB>
+where
+    DB: Dialect,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<&'q str>: Encode<'q, DB>,
+    for<'q> String: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> i32: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> i64: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> NaiveDateTime: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'r> GuestRow: FromRow<'r, DB::Row>,
+    for<'r> AuditRow: FromRow<'r, DB::Row>,
+{
+    async fn checkin_repo(&self, booking: &Booking, occupants: &[GuestProfile]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let found = self.lock_room(&mut tx, &booking.room_number).await?;
+        check_occupants(&booking.room_number, occupants, found)?;
+        if let Some(service) = &booking.service {
+            let active = self.service_active(&mut tx, service.id).await?;
+            check_service(service, active)?;
+        }
+        self.insert_guest(&mut tx, booking).await?;
+        tx.commit().await?;
+        tracing::debug!("transaction committed");
+        Ok(())
+    }
+
+    async fn checkout_repo(&self, booking: &Booking) -> Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let found = self.lock_room(&mut tx, &booking.room_number).await?;
+        check_leaving(
+            &booking.room_number,
+            booking.folio_number.as_deref(),
+            &found,
+        )?;
+        let released = self
+            .remove_guests(
+                &mut tx,
+                &booking.room_number,
+                booking.folio_number.as_deref(),
+            )
+            .await?;
+        tx.commit().await?;
+        tracing::debug!("transaction committed");
+        Ok(released)
+    }
+
+    async fn update_repo(
+        &self,
+        guest: &GuestProfile,
+        booking: &Booking,
+        occupants: &[GuestProfile],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        // Both rooms, always in the same order, so that opposite moves
+        // cannot deadlock.
+        let mut rooms = vec![guest.room_number.as_str(), booking.room_number.as_str()];
+        rooms.sort_unstable();
+        rooms.dedup();
+        let mut found = Vec::new();
+        for room in rooms {
+            let guests = self.lock_room(&mut tx, room).await?;
+            if room == booking.room_number {
+                found = guests;
+            }
+        }
+        check_occupants(&booking.room_number, occupants, found)?;
+        self.move_guest(&mut tx, guest, booking).await?;
+        tx.commit().await?;
+        tracing::debug!("transaction committed");
+        Ok(())
+    }
+
+    async fn get_cron_hotel_service(&self) -> Result<Vec<HotelService>> {
+        let mut query = Statement::<DB>::new(
+            "SELECT id, service_name, simultaneous_use FROM services WHERE cron = 1 AND cron_type = ",
+        );
+        query.push_bind(self.schema.service_type.as_str());
+        let (sql, args) = query.into_parts();
+        let rows: Vec<(i32, String, Option<i32>)> = sqlx::query_as_with(&sql, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| HotelService {
+                id: r.0,
+                name: r.1,
+                simultaneous_use: r.2,
+            })
+            .collect())
+    }
+
+    async fn is_room_active(&self, room_number: &str) -> Result<bool> {
+        let mut query = Statement::<DB>::new("SELECT COUNT(*) FROM hotel_rooms WHERE ");
+        self.push_room(&mut query, room_number);
+        let (sql, args) = query.into_parts();
+        let (count,): (i64,) = sqlx::query_as_with(&sql, args)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn room_guests(&self, room_number: &str) -> Result<Vec<GuestProfile>> {
+        let mut query = Statement::<DB>::new(format!(
+            "SELECT {} FROM hotel_rooms h WHERE ",
+            GUEST_COLUMNS
+        ));
+        self.push_room(&mut query, room_number);
+        query.push(" ORDER BY h.checkin_date");
+        let (sql, args) = query.into_parts();
+        let rows: Vec<GuestRow> = sqlx::query_as_with(&sql, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(GuestRow::into_profile).collect())
+    }
+
+    async fn list_guests(&self, filter: &GuestFilter) -> Result<Vec<GuestProfile>> {
+        let mut query = Statement::<DB>::new(format!(
+            "SELECT {} FROM hotel_rooms h WHERE COALESCE(h.property_id, '') = ",
+            GUEST_COLUMNS
+        ));
+        query.push_bind(property_scope(&self.property));
+        if let Some(service_id) = filter.service_id {
+            query.push(" AND h.service_id = ").push_bind(service_id);
+        }
+        if let Some(from) = filter.checkout_from {
+            query.push(" AND h.checkout_date >= ").push_bind(from);
+        }
+        if let Some(to) = filter.checkout_to {
+            query.push(" AND h.checkout_date < ").push_bind(to);
+        }
+        query.push(" ORDER BY h.room_number, h.checkin_date");
+        let (sql, args) = query.into_parts();
+        let rows: Vec<GuestRow> = sqlx::query_as_with(&sql, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(GuestRow::into_profile).collect())
+    }
+
+    async fn overdue_rooms(&self, cutoff: NaiveDateTime) -> Result<Vec<GuestProfile>> {
+        let mut query = Statement::<DB>::new(format!(
+            "SELECT {} FROM hotel_rooms h JOIN services s ON s.id = h.service_id WHERE s.cron = 1 AND s.cron_type = ",
+            GUEST_COLUMNS
+        ));
+        query
+            .push_bind(self.schema.service_type.as_str())
+            .push(" AND h.checkout_date < ")
+            .push_bind(cutoff)
+            .push(" AND COALESCE(h.property_id, '') = ")
+            .push_bind(property_scope(&self.property))
+            .push(" ORDER BY h.checkout_date");
+        let (sql, args) = query.into_parts();
+        let rows: Vec<GuestRow> = sqlx::query_as_with(&sql, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(GuestRow::into_profile).collect())
+    }
+
+    async fn hotel_credentials(&self) -> Result<Vec<StoredCredential>> {
+        // Any password form, so credentials written before a storage change
+        // are found too; only usernames in the property's realm.
+        let pattern = username_pattern(&self.property);
+        let mut query = Statement::<DB>::new(
+            "SELECT g.username, c.attribute, c.value, g.groupname FROM radusergroup g LEFT JOIN radcheck c ON c.username = g.username AND c.attribute IN (",
+        );
+        query
+            .push_list(PasswordStorage::ALL.iter().map(|s| s.attribute()))
+            .push(") WHERE g.user_type = ")
+            .push_bind(self.schema.user_type.as_str())
+            .push(" AND g.username LIKE ")
+            .push_bind(pattern)
+            .push(" ORDER BY g.username");
+        let (sql, args) = query.into_parts();
+        let rows: Vec<(String, Option<String>, Option<String>, String)> =
+            sqlx::query_as_with(&sql, args)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| StoredCredential {
+                username: r.0,
+                password_attribute: r.1,
+                password: r.2,
+                groupname: r.3,
+            })
+            .collect())
+    }
+
+    async fn apply_reconciliation(&self, plan: &ReconcilePlan) -> Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        for (room, occupants) in &plan.occupants {
+            let found = self.lock_room(&mut tx, room).await?;
+            check_occupants(room, occupants, found)?;
+        }
+
+        let mut released = Vec::new();
+        for guest in &plan.checkouts {
+            let folio = guest.folio_number.as_deref().unwrap_or("");
+            released.extend(
+                self.remove_guests(&mut tx, &guest.room_number, Some(folio))
+                    .await?,
+            );
+        }
+        for username in &plan.orphans {
+            delete_credential(&mut tx, username).await?;
+            released.push(username.clone());
+        }
+        for (guest, booking) in &plan.updates {
+            self.move_guest(&mut tx, guest, booking).await?;
+        }
+        for booking in &plan.checkins {
+            self.insert_guest(&mut tx, booking).await?;
+        }
+
+        tx.commit().await?;
+        tracing::debug!("transaction committed");
+        Ok(released)
+    }
+
+    async fn open_sessions(&self, username: &str) -> Result<Vec<RadiusSession>> {
+        let mut query = Statement::<DB>::new(format!(
+            "SELECT {} FROM radacct WHERE acctstoptime IS NULL AND username = ",
+            DB::SESSION_COLUMNS
+        ));
+        query.push_bind(username);
+        let (sql, args) = query.into_parts();
+        let rows: Vec<(String, String, String, Option<String>, Option<String>)> =
+            sqlx::query_as_with(&sql, args)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| RadiusSession {
+                username: r.0,
+                nas_ip_address: r.1,
+                acct_session_id: r.2,
+                framed_ip_address: r.3,
+                calling_station_id: r.4,
+            })
+            .collect())
+    }
+
+    async fn record_audit(&self, entry: &AuditEntry) -> Result<()> {
+        let mut query = Statement::<DB>::new(
+            "INSERT INTO pms_audit_log (property_id, mode, room_number, old_room, folio_number, params, outcome, code, message, idempotency_key, fingerprint, response, created_at) VALUES (",
+        );
+        query
+            .push_list([
+                self.property.as_ref().map(|p| p.id.as_str()),
+                Some(entry.mode.as_str()),
+                entry.room_number.as_deref(),
+                entry.old_room.as_deref(),
+                entry.folio_number.as_deref(),
+                Some(entry.params.as_str()),
+                Some(entry.outcome.as_str()),
+                entry.code.as_deref(),
+                Some(entry.message.as_str()),
+                entry.idempotency_key.as_deref(),
+                entry.fingerprint.as_deref(),
+                entry.response.as_deref(),
+            ])
+            .push(", ")
+            .push_bind(entry.created_at)
+            .push(")");
+        let (sql, args) = query.into_parts();
+        sqlx::query_with(&sql, args).execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn audit_history(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let mut query = Statement::<DB>::new(format!(
+            "SELECT {} FROM pms_audit_log WHERE COALESCE(property_id, '') = ",
+            AUDIT_COLUMNS
+        ));
+        query.push_bind(property_scope(&self.property));
+        if let Some(room) = filter.room.as_deref() {
+            query
+                .push(" AND (room_number = ")
+                .push_bind(room)
+                .push(" OR old_room = ")
+                .push_bind(room)
+                .push(")");
+        }
+        if let Some(folio) = filter.folio_number.as_deref() {
+            query.push(" AND folio_number = ").push_bind(folio);
+        }
+        if let Some(from) = filter.from {
+            query.push(" AND created_at >= ").push_bind(from);
+        }
+        if let Some(to) = filter.to {
+            query.push(" AND created_at < ").push_bind(to);
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(filter.limit);
+        let (sql, args) = query.into_parts();
+        let rows: Vec<AuditRow> = sqlx::query_as_with(&sql, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(AuditRow::into_entry).collect())
+    }
+
+    async fn find_replay(
+        &self,
+        idempotency_key: Option<&str>,
+        fingerprint: &str,
+        since: NaiveDateTime,
+    ) -> Result<Option<AuditEntry>> {
+        let (column, value, superseded) = match idempotency_key {
+            Some(key) => ("idempotency_key", key, String::new()),
+            None => (
+                "fingerprint",
+                fingerprint,
+                format!(" AND {}", NOT_SUPERSEDED),
+            ),
+        };
+
+        let mut query = Statement::<DB>::new(format!(
+            "SELECT {} FROM pms_audit_log a WHERE a.outcome = 'success' AND a.{} = ",
+            AUDIT_COLUMNS, column
+        ));
+        query
+            .push_bind(value)
+            .push(" AND a.created_at >= ")
+            .push_bind(since)
+            .push(" AND COALESCE(a.property_id, '') = ")
+            .push_bind(property_scope(&self.property))
+            .push(&superseded)
+            .push(" ORDER BY a.id DESC LIMIT 1");
+        let (sql, args) = query.into_parts();
+        let row: Option<AuditRow> = sqlx::query_as_with(&sql, args)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(AuditRow::into_entry))
+    }
+}
+
+impl<DB> SqlBookingRepository<DB>
+where
+    DB: Dialect,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<&'q str>: Encode<'q, DB>,
+    for<'q> String: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> i32: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> i64: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> NaiveDateTime: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    usize: ColumnIndex<DB::Row>,
+{
+    /// Appends the condition matching the property's guests of `room` in
+    /// `hotel_rooms`.
+    fn push_room<'q>(&'q self, query: &mut Statement<'q, DB>, room: &'q str) {
+        query
+            .push("room_number = ")
+            .push_bind(room)
+            .push(" AND COALESCE(property_id, '') = ")
+            .push_bind(property_scope(&self.property));
+    }
+
+    /// Serializes writes to `room` until the transaction ends, then reads
+    /// its guests as `(username, folio)`.
+    async fn lock_room(
+        &self,
+        tx: &mut Transaction<'_, DB>,
+        room: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut lock = Statement::<DB>::new("");
+        DB::room_lock(&mut lock, property_scope(&self.property), room);
+        let (sql, args) = lock.into_parts();
+        if !sql.is_empty() {
+            sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+        }
+
+        let mut query = Statement::<DB>::new(
+            "SELECT username, COALESCE(folio_number, '') FROM hotel_rooms WHERE ",
+        );
+        self.push_room(&mut query, room);
+        query.push(DB::ROOM_READ_LOCK);
+        let (sql, args) = query.into_parts();
+        Ok(sqlx::query_as_with(&sql, args).fetch_all(&mut **tx).await?)
+    }
+
+    /// Whether `service_id` is still an active hotel service; where the
+    /// backend can, the row is share-locked until the transaction ends.
+    async fn service_active(&self, tx: &mut Transaction<'_, DB>, service_id: i32) -> Result<bool> {
+        let mut query = Statement::<DB>::new("SELECT id FROM services WHERE id = ");
+        query
+            .push_bind(service_id)
+            .push(" AND cron = 1 AND cron_type = ")
+            .push_bind(self.schema.service_type.as_str())
+            .push(DB::SHARE_LOCK);
+        let (sql, args) = query.into_parts();
+        let row: Option<(i32,)> = sqlx::query_as_with(&sql, args)
+            .fetch_optional(&mut **tx)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    /// Adds the guest row and (re)writes its credential.
+    async fn insert_guest(&self, tx: &mut Transaction<'_, DB>, booking: &Booking) -> Result<()> {
+        let service = booking
+            .service
+            .as_ref()
+            .ok_or_else(|| anyhow!("No hotel service selected for checkin"))?;
+
+        let mut query = Statement::<DB>::new(
+            "INSERT INTO hotel_rooms (property_id, room_number, username, password, name, service_id, folio_number, checkin_date, checkout_date, status) VALUES (",
+        );
+        query
+            .push_bind(self.property.as_ref().map(|p| p.id.as_str()))
+            .push(", ")
+            .push_list([
+                booking.room_number.as_str(),
+                booking.username.as_str(),
+                booking.password.as_str(),
+                booking.name.as_deref().unwrap_or(""),
+            ])
+            .push(", ")
+            .push_bind(service.id)
+            .push(", ")
+            .push_bind(booking.folio_number.as_deref().unwrap_or(""))
+            .push(", ")
+            .push_list([booking.checkin_date, booking.checkout_date])
+            .push(", 'active')");
+        let (sql, args) = query.into_parts();
+        sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+
+        // A shared credential is rewritten for the latest stay.
+        self.write_credential(tx, booking, service).await
+    }
+
+    /// Removes the room's guests, or only the one with `folio`, returning
+    /// the usernames whose credentials were dropped.
+    async fn remove_guests(
+        &self,
+        tx: &mut Transaction<'_, DB>,
+        room: &str,
+        folio: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let guests = |verb: &str| {
+            let mut query = Statement::<DB>::new(format!("{} FROM hotel_rooms WHERE ", verb));
+            self.push_room(&mut query, room);
+            if let Some(folio) = folio {
+                query
+                    .push(" AND COALESCE(folio_number, '') = ")
+                    .push_bind(folio);
+            }
+            query.into_parts()
+        };
+
+        let (sql, args) = guests("SELECT DISTINCT username");
+        let usernames: Vec<(String,)> =
+            sqlx::query_as_with(&sql, args).fetch_all(&mut **tx).await?;
+
+        let (sql, args) = guests("DELETE");
+        sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+
+        // Credentials no remaining guest shares go with them.
+        let mut released = Vec::new();
+        for (username,) in usernames {
+            if !credential_in_use(tx, &username).await? {
+                delete_credential(tx, &username).await?;
+                released.push(username);
+            }
+        }
+
+        Ok(released)
+    }
+
+    /// Rewrites `guest` to the state in `booking`.
+    async fn move_guest(
+        &self,
+        tx: &mut Transaction<'_, DB>,
+        guest: &GuestProfile,
+        booking: &Booking,
+    ) -> Result<()> {
+        let service = booking
+            .service
+            .as_ref()
+            .ok_or_else(|| anyhow!("No hotel service selected for update"))?;
+
+        let mut query = Statement::<DB>::new("UPDATE hotel_rooms SET room_number = ");
+        query
+            .push_bind(booking.room_number.as_str())
+            .push(", username = ")
+            .push_bind(booking.username.as_str())
+            .push(", password = ")
+            .push_bind(booking.password.as_str())
+            .push(", name = ")
+            .push_bind(booking.name.as_deref())
+            .push(", folio_number = ")
+            .push_bind(booking.folio_number.as_deref().unwrap_or(""))
+            .push(", service_id = ")
+            .push_bind(service.id)
+            .push(", checkin_date = ")
+            .push_bind(booking.checkin_date)
+            .push(", checkout_date = ")
+            .push_bind(booking.checkout_date)
+            .push(", updated_at = ")
+            .push_bind(booking.recorded_at)
+            .push(" WHERE username = ")
+            .push_bind(guest.username.as_str())
+            .push(" AND COALESCE(folio_number, '') = ")
+            .push_bind(guest.folio_number.as_deref().unwrap_or(""))
+            .push(" AND COALESCE(property_id, '') = ")
+            .push_bind(property_scope(&self.property));
+        let (sql, args) = query.into_parts();
+        let done = sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+        check_moved(guest, DB::rows_affected(&done))?;
+
+        // The old credential goes once no guest uses it.
+        if booking.username != guest.username && !credential_in_use(tx, &guest.username).await? {
+            delete_credential(tx, &guest.username).await?;
+        }
+
+        self.write_credential(tx, booking, service).await
+    }
+
+    /// Replaces every radcheck / radreply / radusergroup row of
+    /// `booking.username`: the password, the stay's check and reply
+    /// attributes, and the service's group.
+    async fn write_credential(
+        &self,
+        tx: &mut Transaction<'_, DB>,
+        booking: &Booking,
+        service: &HotelService,
+    ) -> Result<()> {
+        delete_credential(tx, &booking.username).await?;
+
+        let password = RadiusAttribute::new(
+            self.schema.password_storage.attribute(),
+            ":=",
+            booking.password.as_str(),
+        );
+        let rows = std::iter::once(("radcheck", &password))
+            .chain(booking.check_attributes.iter().map(|a| ("radcheck", a)))
+            .chain(booking.reply_attributes.iter().map(|a| ("radreply", a)));
+        for (table, attr) in rows {
+            let mut query = Statement::<DB>::new(format!(
+                "INSERT INTO {} (username, attribute, op, value) VALUES (",
+                table
+            ));
+            query
+                .push_list([
+                    booking.username.as_str(),
+                    attr.attribute.as_str(),
+                    attr.op.as_str(),
+                    attr.value.as_str(),
+                ])
+                .push(")");
+            let (sql, args) = query.into_parts();
+            sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+        }
+
+        let mut query = Statement::<DB>::new(
+            "INSERT INTO radusergroup (username, groupname, priority, user_type) VALUES (",
+        );
+        query
+            .push_bind(booking.username.as_str())
+            .push(", ")
+            .push_bind(service.name.as_str())
+            .push(", 1, ")
+            .push_bind(self.schema.user_type.as_str())
+            .push(")");
+        let (sql, args) = query.into_parts();
+        sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+
+        Ok(())
+    }
+}
+
+async fn delete_credential<DB>(tx: &mut Transaction<'_, DB>, username: &str) -> Result<()>
+where
+    DB: Database,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+{
+    for table in ["radcheck", "radreply", "radusergroup"] {
+        let mut query = Statement::<DB>::new(format!("DELETE FROM {} WHERE username = ", table));
+        query.push_bind(username);
+        let (sql, args) = query.into_parts();
+        sqlx::query_with(&sql, args).execute(&mut **tx).await?;
+    }
+    Ok(())
+}
+
+async fn credential_in_use<DB>(tx: &mut Transaction<'_, DB>, username: &str) -> Result<bool>
+where
+    DB: Database,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+    for<'q> i64: Decode<'q, DB> + Type<DB>,
+    usize: ColumnIndex<DB::Row>,
+{
+    let mut query = Statement::<DB>::new("SELECT COUNT(*) FROM hotel_rooms WHERE username = ");
+    query.push_bind(username);
+    let (sql, args) = query.into_parts();
+    let (count,): (i64,) = sqlx::query_as_with(&sql, args).fetch_one(&mut **tx).await?;
+    Ok(count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_unreachable_database_only() {
+        let err = classify(anyhow::Error::new(sqlx::Error::PoolTimedOut).context("load guests"));
+        assert_eq!(
+            err.downcast_ref::<StorageError>(),
+            Some(&StorageError::Unavailable)
+        );
+
+        let err = classify(anyhow::Error::new(sqlx::Error::RowNotFound));
+        assert!(err.downcast_ref::<StorageError>().is_none());
+    }
+}
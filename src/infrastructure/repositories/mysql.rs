@@ -0,0 +1,17 @@
+use crate::infrastructure::repositories::{Dialect, SqlBookingRepository};
+use sqlx::mysql::{MySql, MySqlQueryResult};
+
+pub type MySqlBookingRepository = SqlBookingRepository<MySql>;
+
+impl Dialect for MySql {
+    /// Locks the rows of the room, and with them the gap a concurrent
+    /// checkin would insert into.
+    const ROOM_READ_LOCK: &'static str = " FOR UPDATE";
+    const SHARE_LOCK: &'static str = " LOCK IN SHARE MODE";
+    const SESSION_COLUMNS: &'static str =
+        "username, nasipaddress, acctsessionid, framedipaddress, callingstationid";
+
+    fn rows_affected(result: &MySqlQueryResult) -> u64 {
+        result.rows_affected()
+    }
+}
@@ -0,0 +1,24 @@
+use crate::infrastructure::repositories::{Dialect, SqlBookingRepository, Statement};
+use sqlx::postgres::{PgQueryResult, Postgres};
+
+pub type PgBookingRepository = SqlBookingRepository<Postgres>;
+
+impl Dialect for Postgres {
+    /// Serializes writes to the room on an advisory lock held until the
+    /// transaction ends; row locks would leave an empty room open.
+    fn room_lock<'q>(query: &mut Statement<'q, Self>, scope: &'q str, room: &'q str) {
+        query
+            .push("SELECT pg_advisory_xact_lock(hashtext(")
+            .push_bind(format!("hotel_rooms/{}/{}", scope, room))
+            .push("))");
+    }
+
+    const ROOM_READ_LOCK: &'static str = "";
+    const SHARE_LOCK: &'static str = " FOR SHARE";
+    const SESSION_COLUMNS: &'static str =
+        "username, host(nasipaddress), acctsessionid, host(framedipaddress), callingstationid";
+
+    fn rows_affected(result: &PgQueryResult) -> u64 {
+        result.rows_affected()
+    }
+}
@@ -0,0 +1,272 @@
+use crate::infrastructure::repositories::{Dialect, SqlBookingRepository, Statement};
+use sqlx::sqlite::{Sqlite, SqliteQueryResult};
+
+pub type SqliteBookingRepository = SqlBookingRepository<Sqlite>;
+
+impl Dialect for Sqlite {
+    /// Takes the database write lock, which SQLite holds until the
+    /// transaction ends, before the room's guests are read: a deferred
+    /// transaction that read first could not upgrade once another checkin
+    /// committed.
+    fn room_lock<'q>(query: &mut Statement<'q, Self>, scope: &'q str, room: &'q str) {
+        query
+            .push("UPDATE hotel_rooms SET room_number = room_number WHERE room_number = ")
+            .push_bind(room)
+            .push(" AND COALESCE(property_id, '') = ")
+            .push_bind(scope);
+    }
+
+    const ROOM_READ_LOCK: &'static str = "";
+    const SHARE_LOCK: &'static str = "";
+    const SESSION_COLUMNS: &'static str =
+        "username, nasipaddress, acctsessionid, framedipaddress, callingstationid";
+
+    fn rows_affected(result: &SqliteQueryResult) -> u64 {
+        result.rows_affected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::{
+        dtos::{HistoryQueryParams, PmsQueryParams},
+        errors::ErrorResponse,
+        services::BookingService,
+        settings::ServiceSettings,
+    };
+    use crate::domain::{entities::RadiusSchema, repositories::BookingRepository};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::sync::Arc;
+    use tokio::task::JoinSet;
+
+    /// FreeRADIUS and hotel tables as `sql/sqlite.sql` expects to find them.
+    const BASE_SCHEMA: &[&str] = &[
+        "CREATE TABLE hotel_rooms (id INTEGER PRIMARY KEY, room_number TEXT, password TEXT, name TEXT, service_id INTEGER, folio_number TEXT, checkin_date TEXT, checkout_date TEXT, status TEXT, updated_at TEXT)",
+        "CREATE TABLE radcheck (id INTEGER PRIMARY KEY, username TEXT, attribute TEXT, op TEXT, value TEXT)",
+        "CREATE TABLE radreply (id INTEGER PRIMARY KEY, username TEXT, attribute TEXT, op TEXT, value TEXT)",
+        "CREATE TABLE radusergroup (id INTEGER PRIMARY KEY, username TEXT, groupname TEXT, priority INTEGER, user_type TEXT)",
+        "CREATE TABLE services (id INTEGER PRIMARY KEY, service_name TEXT, cron INTEGER, cron_type TEXT)",
+        "INSERT INTO services VALUES (1, 'Basic', 1, 'hotel')",
+    ];
+
+    /// Repository on a fresh database file, so that concurrent requests get
+    /// connections of their own.
+    async fn file_repo(name: &str) -> (Arc<SqliteBookingRepository>, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("vhp-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(8)
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(&path)
+                    .create_if_missing(true),
+            )
+            .await
+            .unwrap();
+        let migration = include_str!("../../../sql/sqlite.sql");
+        let statements = BASE_SCHEMA
+            .iter()
+            .copied()
+            .chain(migration.split(';').filter(|s| !s.trim().is_empty()));
+        for statement in statements {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        let repo = Arc::new(SqliteBookingRepository {
+            pool,
+            schema: RadiusSchema::default(),
+            property: None,
+        });
+        (repo, path)
+    }
+
+    fn guest_query(mode: &str, room: &str, n: usize) -> PmsQueryParams {
+        PmsQueryParams {
+            mode: mode.into(),
+            room: Some(room.into()),
+            name: Some(format!("Guest {}", n)),
+            pass: Some(format!("Guest{}", n)),
+            rsvno: Some(format!("R-{}", n)),
+            cidate: Some("20/11/2025".into()),
+            codate: Some("22/11/2025".into()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_checkins_to_one_room_admit_each_guest_once() {
+        let (repo, path) = file_repo("checkin-race").await;
+        let pool = repo.pool.clone();
+        let mut settings = ServiceSettings::default();
+        settings.rooms.max_guests = 16;
+        let service = Arc::new(BookingService::new(repo.clone(), Arc::new(settings)));
+
+        // Eight guests, the last one sent twice.
+        let mut checkins = JoinSet::new();
+        for n in (0..8).chain([7]) {
+            let service = service.clone();
+            checkins.spawn(async move { service.process(guest_query("checkin", "101", n)).await });
+        }
+
+        let (mut admitted, mut conflicts) = (0, 0);
+        while let Some(result) = checkins.join_next().await {
+            match result.unwrap() {
+                Ok(_) => admitted += 1,
+                Err(ErrorResponse::Conflict(_)) => conflicts += 1,
+                Err(err) => panic!("expected a conflict, got {:?}", err),
+            }
+        }
+        assert_eq!((admitted, conflicts), (8, 1));
+
+        let guests = repo.room_guests("101").await.unwrap();
+        let mut folios: Vec<_> = guests
+            .iter()
+            .filter_map(|g| g.folio_number.clone())
+            .collect();
+        folios.sort();
+        assert_eq!(
+            folios,
+            (0..8).map(|n| format!("R-{}", n)).collect::<Vec<_>>()
+        );
+        let (passwords, usernames): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COUNT(DISTINCT username) FROM radcheck WHERE attribute = 'Cleartext-Password'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((passwords, usernames), (8, 8));
+
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn concurrent_moves_into_one_room_admit_one_guest() {
+        let (repo, path) = file_repo("move-race").await;
+        let service = Arc::new(BookingService::new(
+            repo.clone(),
+            Arc::new(ServiceSettings::default()),
+        ));
+        for (n, room) in [(0, "201"), (1, "202")] {
+            service
+                .process(guest_query("checkin", room, n))
+                .await
+                .unwrap();
+        }
+
+        let mut moves = JoinSet::new();
+        for (n, room) in [(0, "201"), (1, "202")] {
+            let service = service.clone();
+            moves.spawn(async move {
+                let mut query = guest_query("update", "301", n);
+                query.oldroom = Some(room.into());
+                service.process(query).await
+            });
+        }
+
+        let (mut moved, mut conflicts) = (0, 0);
+        while let Some(result) = moves.join_next().await {
+            match result.unwrap() {
+                Ok(_) => moved += 1,
+                Err(ErrorResponse::Conflict(_)) => conflicts += 1,
+                Err(err) => panic!("expected a conflict, got {:?}", err),
+            }
+        }
+        assert_eq!((moved, conflicts), (1, 1));
+        assert_eq!(repo.room_guests("301").await.unwrap().len(), 1);
+        let (passwords,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM radcheck WHERE username = '301' AND attribute = 'Cleartext-Password'",
+        )
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+        assert_eq!(passwords, 1);
+
+        repo.pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn moves_racing_checkouts_of_the_old_room_leave_no_credential_behind() {
+        let (repo, path) = file_repo("move-checkout-race").await;
+        let service = Arc::new(BookingService::new(
+            repo.clone(),
+            Arc::new(ServiceSettings::default()),
+        ));
+        let pairs: Vec<(usize, String)> = (0..8).map(|n| (n, format!("4{:02}", n))).collect();
+        for (n, room) in &pairs {
+            service
+                .process(guest_query("checkin", room, *n))
+                .await
+                .unwrap();
+        }
+
+        let mut requests = JoinSet::new();
+        for (n, room) in pairs {
+            let mut query = guest_query("update", &format!("5{:02}", n), n);
+            query.oldroom = Some(room.clone());
+            let mover = service.clone();
+            requests.spawn(async move { (n, "update", mover.process(query).await) });
+            let query = guest_query("checkout", &room, n);
+            let service = service.clone();
+            requests.spawn(async move { (n, "checkout", service.process(query).await) });
+        }
+
+        let mut succeeded = vec![Vec::new(); 8];
+        while let Some(result) = requests.join_next().await {
+            match result.unwrap() {
+                (n, mode, Ok(_)) => succeeded[n].push(mode),
+                (_, _, Err(ErrorResponse::Conflict(_) | ErrorResponse::NotFound(_))) => {}
+                (_, mode, Err(err)) => panic!("{} failed with {:?}", mode, err),
+            }
+        }
+        for modes in &succeeded {
+            assert_eq!(modes.len(), 1, "expected one winner, got {:?}", modes);
+        }
+        let (orphans,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM radcheck c WHERE NOT EXISTS (SELECT 1 FROM hotel_rooms h WHERE h.username = c.username)",
+        )
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+        assert_eq!(orphans, 0);
+
+        repo.pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn history_keeps_database_errors_private() {
+        let (repo, path) = file_repo("history-redaction").await;
+        let service = BookingService::new(repo.clone(), Arc::new(ServiceSettings::default()));
+        sqlx::query("DROP TABLE radcheck")
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        let err = service
+            .process(guest_query("checkin", "101", 0))
+            .await
+            .unwrap_err();
+        assert!(err.message().contains("radcheck"));
+
+        let history = service
+            .history(HistoryQueryParams {
+                room: None,
+                folio: None,
+                from: None,
+                to: None,
+                limit: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(history[0].code.as_deref(), Some("internal_error"));
+        let json = serde_json::to_string(&history).unwrap();
+        assert!(!json.contains("radcheck"), "{}", json);
+        assert!(json.contains("internal server error"));
+
+        repo.pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,207 @@
+//! Log output: level and format from the settings, every line scrubbed of
+//! passwords and client secrets before it is written, and request IDs that
+//! tie the lines of one PMS call together.
+
+use crate::config::var;
+use anyhow::{Result, anyhow};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::io::{self, Write};
+use tracing_subscriber::EnvFilter;
+
+/// Longest `X-Request-Id` taken from a caller; longer or odd ones are
+/// replaced.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// A secret setting or parameter and its value as it appears in a query
+/// string, JSON (escaped or not), `Debug` output or a colored log field.
+static SECRET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?i)(^|[^a-z0-9_]|\x1b\[[0-9;]*m)(pass|password|api_key|hmac_secret|secret)(\\?"?(?:\x1b\[[0-9;]*m)*\s*[:=](?:\x1b\[[0-9;]*m)*\s*(?:Some\()?)(\\?"[^"\\]*\\?"|[^&\s"\\,;)}\]]+)"#,
+    )
+    .expect("❌ invalid secret pattern")
+});
+
+/// Password in the user info of a URL such as `DATABASE_URL`.
+static URL_PASSWORD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(://[^:/@\s]+:)[^@/\s]+@").expect("❌ invalid url password pattern"));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    /// `EnvFilter` directives, e.g. `info` or `info,vhp_api=debug,sqlx=warn`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogSettings {
+    /// Reads `LOG_LEVEL` (default `info`) and `LOG_FORMAT`, `text` (default)
+    /// or `json`.
+    pub fn from_env() -> Result<Self> {
+        let mut settings = Self::default();
+
+        if let Some(v) = var("LOG_LEVEL") {
+            EnvFilter::try_new(v.trim()).map_err(|_| anyhow!("invalid LOG_LEVEL {:?}", v))?;
+            settings.level = v.trim().to_string();
+        }
+        if let Some(v) = var("LOG_FORMAT") {
+            settings.format = match v.trim().to_lowercase().as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => return Err(anyhow!("invalid LOG_FORMAT {:?}", v)),
+            };
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Installs the global subscriber writing redacted lines to stdout. JSON
+/// lines carry the enclosing spans, request ID included, as fields.
+pub fn init(settings: &LogSettings) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&settings.level))
+        .with_writer(|| Redacting(io::stdout()));
+    match settings.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
+
+/// `line` with the value of every secret, and any password in a URL,
+/// replaced by `***`; quotes around it are kept so JSON stays valid.
+pub fn redact(line: &str) -> Cow<'_, str> {
+    let line = URL_PASSWORD.replace_all(line, "${1}***@");
+    let masked = SECRET.replace_all(&line, |caps: &Captures| {
+        let value = &caps[4];
+        let masked = if value.ends_with('"') && value.len() > 1 {
+            let quote = if value.starts_with('\\') {
+                "\\\""
+            } else {
+                "\""
+            };
+            format!("{}***{}", quote, quote)
+        } else {
+            "***".to_string()
+        };
+        format!("{}{}{}{}", &caps[1], &caps[2], &caps[3], masked)
+    });
+    match masked {
+        Cow::Borrowed(_) => line,
+        Cow::Owned(owned) => Cow::Owned(owned),
+    }
+}
+
+/// Writer handing each formatted event, which the subscriber writes in one
+/// call, through [`redact`].
+struct Redacting<W: Write>(W);
+
+impl<W: Write> Write for Redacting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        self.0.write_all(redact(&line).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// ID for a PMS call: the caller's `X-Request-Id` when it is short and
+/// plain, else a new random one.
+pub fn request_id(given: Option<&str>) -> String {
+    match given.map(str::trim) {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) =>
+        {
+            id.to_string()
+        }
+        _ => {
+            let mut bytes = [0u8; 12];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets_in_every_shape() {
+        assert_eq!(
+            redact("GET /vhp?mode=checkin&room=101&pass=Smith!&rsvno=R-1"),
+            "GET /vhp?mode=checkin&room=101&pass=***&rsvno=R-1"
+        );
+        assert_eq!(
+            redact(r#"{"pass":"John Smith","room":"101"}"#),
+            r#"{"pass":"***","room":"101"}"#
+        );
+        assert_eq!(
+            redact(r#"{"message":"body {\"password\": \"s3cret\"}"}"#),
+            r#"{"message":"body {\"password\": \"***\"}"}"#
+        );
+        assert_eq!(
+            redact(r#"PmsQueryParams { pass: Some("Smith"), room: Some("101") }"#),
+            r#"PmsQueryParams { pass: Some("***"), room: Some("101") }"#
+        );
+        assert_eq!(
+            redact("\x1b[3mapi_key\x1b[0m\x1b[2m=\x1b[0mk3y rest"),
+            "\x1b[3mapi_key\x1b[0m\x1b[2m=\x1b[0m*** rest"
+        );
+        assert_eq!(redact("HMAC_SECRET=abc"), "HMAC_SECRET=***");
+        assert_eq!(
+            redact("connecting to postgres://vhp:hunter2@db:5432/radius"),
+            "connecting to postgres://vhp:***@db:5432/radius"
+        );
+    }
+
+    #[test]
+    fn leaves_other_text_alone() {
+        for line in [
+            "password_storage=ssha512",
+            "password of 101 stored as NT-Password",
+            "checkin for room 101 with passphrase",
+            "bypass=1&room=101",
+        ] {
+            assert_eq!(redact(line), line);
+        }
+    }
+
+    #[test]
+    fn keeps_plain_request_ids() {
+        assert_eq!(request_id(Some(" opera-42.7 ")), "opera-42.7");
+        for given in [None, Some(""), Some("a b"), Some("x\ny")] {
+            let id = request_id(given);
+            assert_eq!(id.len(), 24, "{:?}", given);
+            assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+        assert_eq!(request_id(Some(&"a".repeat(65))).len(), 24);
+    }
+}
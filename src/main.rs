@@ -1,36 +1,292 @@
+use anyhow::{Context, anyhow};
+use application::dtos::SnapshotGuest;
+use application::services::BookingService;
+use application::settings::{
+    ServiceSettings, init_property_settings, init_service_settings, settings_for, tenant_settings,
+};
+use application::sweeper::ExpirySettings;
 use dotenvy::dotenv;
-use infrastructure::database::init_db_pool;
+use fias::server::FiasSettings;
+use infrastructure::database::{DbSettings, close_db_pool, init_db_pool};
+use infrastructure::radius::{NasSettings, init_nas_client, nas_client};
+use infrastructure::repositories::booking_repository;
+use logging::LogSettings;
+use presentation::auth::{AuthSettings, init_admin_auth_settings, init_auth_settings};
+use presentation::portal::{PortalSettings, init_portal_settings};
+use presentation::request_log::request_log;
 use presentation::routes::router;
 use salvo::prelude::*;
+use salvo::server::ServerHandle;
 use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod application;
+mod config;
 mod domain;
+mod fias;
 mod infrastructure;
+mod logging;
 mod presentation;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    tracing_subscriber::fmt().init();
+    // The config file may set the log level and format, so it is read before
+    // logging starts; its errors are reported once logging is up.
+    let loaded = config::init_config();
+    let logging = LogSettings::from_env();
+    logging::init(logging.as_ref().unwrap_or(&LogSettings::default()));
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    init_db_pool(&database_url)
+    if let Err(err) = run(loaded, logging).await {
+        tracing::error!("{:#}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(
+    loaded: anyhow::Result<Option<PathBuf>>,
+    logging: anyhow::Result<LogSettings>,
+) -> anyhow::Result<()> {
+    if let Some(path) = loaded? {
+        tracing::info!("loaded configuration from {}", path.display());
+    }
+    logging.context("Invalid log settings")?;
+
+    // Validate every setting before waiting on the database.
+    let database_url = config::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+    let db_settings = DbSettings::from_env().context("Invalid database settings")?;
+    let settings = ServiceSettings::from_env().context("Invalid service settings")?;
+    let properties = ServiceSettings::properties_from_env().context("Invalid property settings")?;
+    let nas = NasSettings::from_env().context("Invalid NAS settings")?;
+    let auth = AuthSettings::from_env().context("Invalid PMS auth settings")?;
+    let admin_auth = AuthSettings::admin_from_env().context("Invalid admin auth settings")?;
+    let portal = PortalSettings::from_env().context("Invalid portal settings")?;
+    let fias = FiasSettings::from_env().context("Invalid FIAS settings")?;
+    let expiry = ExpirySettings::from_env().context("Invalid expiry settings")?;
+    let server = ServerSettings::from_env().context("Invalid server settings")?;
+
+    // A client bound to a property must name one that is configured.
+    for (scope, clients) in [("PMS", &auth), ("ADMIN", &admin_auth)] {
+        for client in &clients.clients {
+            if let Some(property) = &client.property
+                && !properties
+                    .iter()
+                    .any(|p| p.property.as_ref().is_some_and(|p| &p.id == property))
+            {
+                return Err(anyhow!(
+                    "{} client {:?} is bound to unknown property {:?}",
+                    scope,
+                    client.name,
+                    property
+                ));
+            }
+        }
+    }
+
+    init_service_settings(settings);
+    init_property_settings(properties);
+    let fias = match fias {
+        Some(fias) => {
+            let tenant = settings_for(fias.property.as_deref())
+                .map_err(|err| anyhow!("Invalid FIAS settings: {}", err.message()))?;
+            Some((fias, tenant))
+        }
+        None => None,
+    };
+    if let Some(settings) = nas {
+        init_nas_client(settings);
+    }
+
+    init_db_pool(&database_url, &db_settings)
         .await
-        .expect("Failed to init DB Pool");
+        .context("Failed to init DB Pool")?;
+
+    // `vhp-api reconcile <snapshot.json|snapshot.csv|-> [--apply] [--property=<id>]`
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("reconcile") {
+        std::process::exit(reconcile(&args[1..]).await);
+    }
 
-    let app_port = std::env::var("APP_PORT")
-        .ok()
-        .filter(|s| !s.trim().is_empty())
-        .unwrap_or_else(|| "5800".to_string());
+    if auth.is_open() {
+        tracing::warn!("PMS_CLIENTS is not set, /vhp accepts unauthenticated requests");
+    }
+    init_auth_settings(auth);
 
-    let bind_addr = format!("0.0.0.0:{}", app_port);
+    if admin_auth.is_open() {
+        tracing::info!("ADMIN_CLIENTS is not set, /admin routes are disabled");
+    }
+    init_admin_auth_settings(admin_auth);
 
-    let acceptor = TcpListener::new(bind_addr).bind().await;
+    if portal.enabled {
+        tracing::info!(
+            "guest portal enabled, {} attempts per {:?}, {} failures lock out for {:?}",
+            portal.max_attempts,
+            portal.window,
+            portal.max_failures,
+            portal.lockout
+        );
+    }
+    init_portal_settings(portal);
+
+    if let Some((settings, tenant)) = fias {
+        let service = Arc::new(
+            BookingService::new(booking_repository(&tenant), tenant).with_nas_client(nas_client()),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = fias::server::run(settings, service).await {
+                tracing::error!("fias interface stopped: {}", err);
+            }
+        });
+    }
+
+    if let Some(settings) = expiry {
+        for tenant in tenant_settings() {
+            let service = Arc::new(
+                BookingService::new(booking_repository(&tenant), tenant)
+                    .with_nas_client(nas_client()),
+            );
+            tokio::spawn(application::sweeper::run(settings.clone(), service));
+        }
+    }
+
+    let acceptor = TcpListener::new(server.bind_addr.clone())
+        .try_bind()
+        .await
+        .with_context(|| format!("Failed to bind {}", server.bind_addr))?;
     let router = router();
+    tracing::debug!("routes:\n{:?}", router);
+
+    let http = Server::new(acceptor);
+    tokio::spawn(shutdown_on_signal(http.handle(), server.shutdown_grace));
+    http.serve(Service::new(router).hoop(request_log)).await;
+
+    // Let transactions still holding a connection (FIAS, sweeper) finish.
+    tracing::info!("server stopped, closing database connections");
+    close_db_pool().await;
+    Ok(())
+}
+
+struct ServerSettings {
+    bind_addr: String,
+    /// How long in-flight requests may take to finish after a signal.
+    shutdown_grace: Duration,
+}
+
+impl ServerSettings {
+    /// Reads `APP_HOST` (default `0.0.0.0`), `APP_PORT` (default 5800) and
+    /// `SHUTDOWN_GRACE_SECS` (default 30).
+    fn from_env() -> anyhow::Result<Self> {
+        let host = config::var("APP_HOST").unwrap_or_else(|| "0.0.0.0".to_string());
+        let port: u16 = match config::var("APP_PORT") {
+            Some(v) => v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid APP_PORT {:?}", v))?,
+            None => 5800,
+        };
+        let grace = match config::var("SHUTDOWN_GRACE_SECS") {
+            Some(v) => v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid SHUTDOWN_GRACE_SECS {:?}", v))?,
+            None => 30,
+        };
+
+        Ok(Self {
+            bind_addr: format!("{}:{}", host.trim(), port),
+            shutdown_grace: Duration::from_secs(grace),
+        })
+    }
+}
+
+/// Stops accepting connections on SIGTERM or SIGINT and gives in-flight
+/// requests up to `grace` to finish.
+async fn shutdown_on_signal(handle: ServerHandle, grace: Duration) {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!(
+        "shutdown requested, waiting up to {:?} for in-flight requests",
+        grace
+    );
+    handle.stop_graceful(grace);
+}
+
+/// Runs a reconciliation from the command line and prints the report as
+/// JSON. Returns the process exit code.
+async fn reconcile(args: &[String]) -> i32 {
+    let apply = args.iter().any(|a| a == "--apply");
+    let property = args.iter().find_map(|a| a.strip_prefix("--property="));
+    let Some(path) = args.iter().find(|a| !a.starts_with("--")) else {
+        eprintln!(
+            "usage: vhp-api reconcile <snapshot.json|snapshot.csv|-> [--apply] [--property=<id>]"
+        );
+        return 2;
+    };
+    let settings = match settings_for(property) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err.message());
+            return 2;
+        }
+    };
 
-    println!("{:?}", router);
+    let body = if path == "-" {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let snapshot = match body
+        .map_err(anyhow::Error::from)
+        .and_then(|b| SnapshotGuest::parse_snapshot(&b))
+    {
+        Ok(s) => s,
+        Err(err) => {
+            eprintln!("invalid snapshot {}: {}", path, err);
+            return 2;
+        }
+    };
 
-    Server::new(acceptor).serve(router).await;
+    let service =
+        BookingService::new(booking_repository(&settings), settings).with_nas_client(nas_client());
+    match service.reconcile(snapshot, apply).await {
+        Ok(report) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).unwrap_or_default()
+            );
+            0
+        }
+        Err(err) => {
+            eprintln!("reconciliation failed: {}", err);
+            1
+        }
+    }
 }
@@ -0,0 +1,270 @@
+use crate::application::dtos::{
+    AdminCheckoutRequest, AdminExtendRequest, AdminPasswordRequest, PmsResponse, RoomListParams,
+    RoomView,
+};
+use crate::presentation::handlers::{render_result, request_service};
+use salvo::oapi::extract::PathParam;
+use salvo::prelude::*;
+use serde::de::DeserializeOwned;
+
+#[endpoint(
+    tags("admin"),
+    parameters(RoomListParams),
+    responses(
+        (status_code = 200, body = Vec<RoomView>, description = "occupied rooms, ordered by room"),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "validation_error",
+            "message": "unknown service gold",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn list_rooms_handler(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let params = match req.parse_queries::<RoomListParams>() {
+        Ok(q) => q,
+        Err(_) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(PmsResponse::error(
+                "invalid_request",
+                "invalid query params",
+            )));
+            return;
+        }
+    };
+
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.list_rooms(params).await);
+}
+
+#[endpoint(
+    tags("admin"),
+    responses(
+        (status_code = 200, body = RoomView, description = "guests staying in the room"),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 404, body = PmsResponse, description = "not found", example = json!({
+            "status": "error",
+            "code": "not_found",
+            "message": "room 101 not found",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn room_handler(
+    room: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) {
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.room(&room).await);
+}
+
+#[endpoint(
+    tags("admin"),
+    request_body(content = AdminCheckoutRequest, description = "optional"),
+    responses(
+        (status_code = 200, body = PmsResponse, description = "success", example = json!({
+            "status": "success",
+            "message": "room 101 successfully checkout",
+        })),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "invalid_request",
+            "message": "invalid json body",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 404, body = PmsResponse, description = "not found", example = json!({
+            "status": "error",
+            "code": "not_found",
+            "message": "room 101 not found for checkout",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn checkout_room_handler(
+    room: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) {
+    // The body is optional: without one the whole room is checked out.
+    let body = if req.payload().await.is_ok_and(|b| b.is_empty()) {
+        AdminCheckoutRequest::default()
+    } else {
+        match parse_body::<AdminCheckoutRequest>(req, res).await {
+            Some(body) => body,
+            None => return,
+        }
+    };
+
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.force_checkout(&room, body.rsvno).await);
+}
+
+#[endpoint(
+    tags("admin"),
+    request_body = AdminPasswordRequest,
+    responses(
+        (status_code = 200, body = PmsResponse, description = "success", example = json!({
+            "status": "success",
+            "message": "room 101 successfully updated",
+        })),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "validation_error",
+            "message": "rsvno is required for room 101 with several guests",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 404, body = PmsResponse, description = "not found", example = json!({
+            "status": "error",
+            "code": "not_found",
+            "message": "room 101 not found",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn reset_password_handler(
+    room: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) {
+    let Some(body) = parse_body::<AdminPasswordRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.reset_password(&room, body).await);
+}
+
+#[endpoint(
+    tags("admin"),
+    request_body = AdminExtendRequest,
+    responses(
+        (status_code = 200, body = PmsResponse, description = "success", example = json!({
+            "status": "success",
+            "message": "room 101 successfully updated",
+        })),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "validation_error",
+            "message": "new checkout 2025-11-20 13:00:00 is not after current checkout 2025-11-21 13:00:00",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 404, body = PmsResponse, description = "not found", example = json!({
+            "status": "error",
+            "code": "not_found",
+            "message": "room 101 not found",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn extend_stay_handler(
+    room: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) {
+    let Some(body) = parse_body::<AdminExtendRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.extend_stay(&room, body).await);
+}
+
+/// JSON body of an admin action or portal login, rendering a 400 when it
+/// is invalid.
+pub(crate) async fn parse_body<T: DeserializeOwned>(
+    req: &mut Request,
+    res: &mut Response,
+) -> Option<T> {
+    match req.parse_json::<T>().await {
+        Ok(body) => Some(body),
+        Err(err) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(PmsResponse::error(
+                "invalid_request",
+                format!("invalid json body: {}", err),
+            )));
+            None
+        }
+    }
+}
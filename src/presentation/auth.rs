@@ -0,0 +1,668 @@
+use crate::application::dtos::PmsResponse;
+use crate::config::{env_name, var};
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use once_cell::sync::OnceCell;
+use salvo::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Depot key holding the property of the authenticated client, if bound.
+pub const CLIENT_PROPERTY: &str = "client_property";
+
+pub static AUTH_SETTINGS: OnceCell<Arc<AuthSettings>> = OnceCell::new();
+pub static ADMIN_AUTH_SETTINGS: OnceCell<Arc<AuthSettings>> = OnceCell::new();
+
+/// One PMS (or, for `/admin`, one staff tool) allowed to call the API;
+/// every configured check must pass.
+#[derive(Clone, Default)]
+pub struct PmsClient {
+    pub name: String,
+    /// Expected value of the `X-Api-Key` header.
+    pub api_key: Option<String>,
+    /// Secret for the hex HMAC-SHA256 in `X-Signature`.
+    pub hmac_secret: Option<String>,
+    /// Source addresses or networks; empty allows any source.
+    pub allowed_ips: Vec<IpNet>,
+    /// Property the client may act for; `None` allows any.
+    pub property: Option<String>,
+}
+
+impl std::fmt::Debug for PmsClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PmsClient")
+            .field("name", &self.name)
+            .field("api_key", &self.api_key.as_ref().map(|_| "***"))
+            .field("hmac_secret", &self.hmac_secret.as_ref().map(|_| "***"))
+            .field("allowed_ips", &self.allowed_ips)
+            .field("property", &self.property)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthSettings {
+    /// No clients leaves the PMS routes open and the admin routes unmounted.
+    pub clients: Vec<PmsClient>,
+    /// How far `X-Timestamp` may drift from the server clock.
+    pub max_skew_secs: i64,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            clients: Vec::new(),
+            max_skew_secs: 300,
+        }
+    }
+}
+
+impl AuthSettings {
+    /// Reads `PMS_CLIENTS=opera,protel` and, per client, `PMS_CLIENT_<NAME>_API_KEY`,
+    /// `PMS_CLIENT_<NAME>_HMAC_SECRET`, `PMS_CLIENT_<NAME>_ALLOWED_IPS`
+    /// (`10.0.0.5,192.168.1.0/24`) and `PMS_CLIENT_<NAME>_PROPERTY`, plus
+    /// `PMS_AUTH_MAX_SKEW_SECS`.
+    pub fn from_env() -> Result<Self> {
+        Self::from_env_prefixed("PMS")
+    }
+
+    /// Same variables with the `ADMIN` prefix, e.g. `ADMIN_CLIENTS=frontdesk`.
+    pub fn admin_from_env() -> Result<Self> {
+        Self::from_env_prefixed("ADMIN")
+    }
+
+    fn from_env_prefixed(scope: &str) -> Result<Self> {
+        let mut settings = Self::default();
+
+        let skew_var = format!("{}_AUTH_MAX_SKEW_SECS", scope);
+        if let Some(v) = var(&skew_var) {
+            settings.max_skew_secs = v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid {} {:?}", skew_var, v))?;
+        }
+
+        let names = var(&format!("{}_CLIENTS", scope)).unwrap_or_default();
+        for name in names.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let prefix = format!("{}_CLIENT_{}", scope, env_name(name));
+            let var = |suffix: &str| {
+                var(&format!("{}_{}", prefix, suffix))
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+            };
+
+            let client = PmsClient {
+                name: name.to_string(),
+                api_key: var("API_KEY"),
+                hmac_secret: var("HMAC_SECRET"),
+                allowed_ips: match var("ALLOWED_IPS") {
+                    Some(v) => parse_allowed_ips(&v)?,
+                    None => Vec::new(),
+                },
+                property: var("PROPERTY"),
+            };
+
+            if client.api_key.is_none()
+                && client.hmac_secret.is_none()
+                && client.allowed_ips.is_empty()
+            {
+                return Err(anyhow!(
+                    "{} client {:?} has no credentials configured",
+                    scope,
+                    name
+                ));
+            }
+            settings.clients.push(client);
+        }
+
+        Ok(settings)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Property the client called `name` is bound to.
+    pub fn property_of(&self, name: &str) -> Option<&str> {
+        self.clients
+            .iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.property.as_deref())
+    }
+
+    /// Name of the client accepting `request`. When none does, a source
+    /// rejection (403) wins over a credential rejection (401).
+    pub fn authorize(&self, request: &Credentials, now: i64) -> Result<&str, AuthError> {
+        let mut rejection = None;
+
+        for client in &self.clients {
+            match client.check(request, now, self.max_skew_secs) {
+                Ok(()) => return Ok(&client.name),
+                Err(err @ AuthError::Forbidden(_)) => rejection = Some(err),
+                Err(err) => {
+                    rejection.get_or_insert(err);
+                }
+            }
+        }
+
+        Err(rejection.unwrap_or_else(|| AuthError::Unauthorized("no client matched".into())))
+    }
+}
+
+impl PmsClient {
+    fn check(&self, request: &Credentials, now: i64, max_skew_secs: i64) -> Result<(), AuthError> {
+        if let Some(expected) = &self.api_key {
+            match &request.api_key {
+                Some(key) if secret_eq(key, expected) => {}
+                Some(_) => return Err(AuthError::Unauthorized("invalid api key".into())),
+                None => return Err(AuthError::Unauthorized("missing api key".into())),
+            }
+        }
+
+        if let Some(secret) = &self.hmac_secret {
+            let (Some(timestamp), Some(signature)) = (&request.timestamp, &request.signature)
+            else {
+                return Err(AuthError::Unauthorized("missing request signature".into()));
+            };
+            let ts: i64 = timestamp
+                .trim()
+                .parse()
+                .map_err(|_| AuthError::Unauthorized("invalid request timestamp".into()))?;
+            if (now - ts).abs() > max_skew_secs {
+                return Err(AuthError::Unauthorized("stale request timestamp".into()));
+            }
+            if !secret_eq(&signature.to_lowercase(), &request.sign(secret)) {
+                return Err(AuthError::Unauthorized("invalid request signature".into()));
+            }
+        }
+
+        if !self.allowed_ips.is_empty() {
+            let allowed = request
+                .ip
+                .is_some_and(|ip| self.allowed_ips.iter().any(|net| net.contains(&ip)));
+            if !allowed {
+                return Err(AuthError::Forbidden(format!(
+                    "source {} is not allowed",
+                    request
+                        .ip
+                        .map(|ip| ip.to_string())
+                        .unwrap_or_else(|| "unknown".into())
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum AuthError {
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+}
+
+/// What a request presents to [`AuthSettings::authorize`].
+#[derive(Debug, Default)]
+pub struct Credentials {
+    pub ip: Option<IpAddr>,
+    pub api_key: Option<String>,
+    pub timestamp: Option<String>,
+    pub signature: Option<String>,
+    pub method: String,
+    /// Path and query exactly as sent, e.g. `/vhp?mode=checkout&room=101`.
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+impl Credentials {
+    /// Hex HMAC-SHA256 of `timestamp\nMETHOD\npath?query\nbody`.
+    pub fn sign(&self, secret: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(self.timestamp.as_deref().unwrap_or_default().as_bytes());
+        mac.update(b"\n");
+        mac.update(self.method.as_bytes());
+        mac.update(b"\n");
+        mac.update(self.path.as_bytes());
+        mac.update(b"\n");
+        mac.update(&self.body);
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+pub fn init_auth_settings(settings: AuthSettings) {
+    AUTH_SETTINGS
+        .set(Arc::new(settings))
+        .expect("❌ AUTH_SETTINGS is already initialized");
+}
+
+pub fn auth_settings() -> Arc<AuthSettings> {
+    AUTH_SETTINGS
+        .get()
+        .expect("❌ AUTH_SETTINGS is not initialized")
+        .clone()
+}
+
+pub fn init_admin_auth_settings(settings: AuthSettings) {
+    ADMIN_AUTH_SETTINGS
+        .set(Arc::new(settings))
+        .expect("❌ ADMIN_AUTH_SETTINGS is already initialized");
+}
+
+pub fn admin_auth_settings() -> Arc<AuthSettings> {
+    ADMIN_AUTH_SETTINGS
+        .get()
+        .expect("❌ ADMIN_AUTH_SETTINGS is not initialized")
+        .clone()
+}
+
+/// Upper bound on remembered signatures; past it the one closest to going
+/// stale is forgotten first.
+const MAX_SEEN_SIGNATURES: usize = 100_000;
+
+/// Signatures accepted while their timestamp is fresh, so that a signed
+/// request sent again within the skew window is logged. They are kept per
+/// process: a repeat reaching another replica goes unnoticed.
+#[derive(Default)]
+struct SeenSignatures {
+    /// `(stale after, signature)`, soonest first.
+    by_expiry: BTreeSet<(i64, String)>,
+    known: HashSet<String>,
+}
+
+impl SeenSignatures {
+    /// Remembers `signature` until `expires`; false when it was seen before.
+    fn insert(&mut self, signature: &str, expires: i64, now: i64) -> bool {
+        if self.known.contains(signature) {
+            return false;
+        }
+        while let Some((stale_after, _)) = self.by_expiry.first() {
+            if *stale_after >= now && self.known.len() < MAX_SEEN_SIGNATURES {
+                break;
+            }
+            if let Some((_, old)) = self.by_expiry.pop_first() {
+                self.known.remove(&old);
+            }
+        }
+        self.by_expiry.insert((expires, signature.to_string()));
+        self.known.insert(signature.to_string());
+        true
+    }
+}
+
+/// Rejects requests no configured client accepts.
+pub struct PmsAuth {
+    settings: Arc<AuthSettings>,
+    /// Names the callers in logs, `PMS` or `admin`.
+    label: &'static str,
+    /// Refuse every request, rather than admit it, while no client is
+    /// configured.
+    required: bool,
+    seen: Mutex<SeenSignatures>,
+}
+
+impl PmsAuth {
+    pub fn new(settings: Arc<AuthSettings>) -> Self {
+        Self {
+            settings,
+            label: "PMS",
+            required: false,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// For routes that must not be open: without configured clients they
+    /// answer 403 instead of admitting everyone.
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// The outcome of a request while no client is configured, `None` when
+    /// some are and credentials decide.
+    fn open_access(&self) -> Option<Result<(), AuthError>> {
+        if !self.settings.is_open() {
+            return None;
+        }
+        Some(if self.required {
+            Err(AuthError::Forbidden(format!(
+                "no {} clients are configured for this endpoint",
+                self.label
+            )))
+        } else {
+            Ok(())
+        })
+    }
+
+    /// Whether `client` already sent the signature of `request`. A repeat
+    /// is still admitted: the timestamp bounds how long it can be replayed,
+    /// and a PMS resending after a timeout gets the original response from
+    /// idempotency handling rather than a 401.
+    fn repeats_signature(&self, client: &str, request: &Credentials, now: i64) -> bool {
+        let signed = self
+            .settings
+            .clients
+            .iter()
+            .any(|c| c.name == client && c.hmac_secret.is_some());
+        match (signed, &request.timestamp, &request.signature) {
+            (true, Some(timestamp), Some(signature)) => {
+                let ts: i64 = timestamp.trim().parse().unwrap_or(now);
+                !self.seen.lock().expect("seen signatures lock").insert(
+                    &signature.to_lowercase(),
+                    ts + self.settings.max_skew_secs,
+                    now,
+                )
+            }
+            _ => false,
+        }
+    }
+}
+
+#[handler]
+impl PmsAuth {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        match self.open_access() {
+            Some(Ok(())) => return,
+            Some(Err(err)) => {
+                tracing::warn!(
+                    "refused {} request {} {}: {}",
+                    self.label,
+                    req.method(),
+                    req.uri().path(),
+                    err
+                );
+                reject(res, ctrl, err);
+                return;
+            }
+            None => {}
+        }
+
+        let signed = self
+            .settings
+            .clients
+            .iter()
+            .any(|c| c.hmac_secret.is_some());
+        let body = if signed {
+            req.payload().await.map(|b| b.to_vec()).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        let credentials = Credentials {
+            ip: req
+                .remote_addr()
+                .clone()
+                .into_std()
+                .map(|addr| addr.ip().to_canonical()),
+            api_key: header(req, API_KEY_HEADER),
+            timestamp: header(req, TIMESTAMP_HEADER),
+            signature: header(req, SIGNATURE_HEADER),
+            method: req.method().to_string(),
+            path: req
+                .uri()
+                .path_and_query()
+                .map(|p| p.as_str().to_string())
+                .unwrap_or_default(),
+            body,
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        match self.settings.authorize(&credentials, now) {
+            Ok(client) => {
+                tracing::debug!(
+                    "{} request {} {} from client {}",
+                    self.label,
+                    credentials.method,
+                    credentials.path,
+                    client
+                );
+                if self.repeats_signature(client, &credentials, now) {
+                    tracing::info!(
+                        "{} request {} {} from client {} repeats an earlier signature",
+                        self.label,
+                        credentials.method,
+                        credentials.path,
+                        client
+                    );
+                }
+                if let Some(property) = self.settings.property_of(client) {
+                    depot.insert(CLIENT_PROPERTY, property.to_string());
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "rejected {} request {} {} from {}: {}",
+                    self.label,
+                    credentials.method,
+                    credentials.path,
+                    credentials
+                        .ip
+                        .map(|ip| ip.to_string())
+                        .unwrap_or_else(|| "unknown".into()),
+                    err
+                );
+                reject(res, ctrl, err);
+            }
+        }
+    }
+}
+
+/// Answers `err` in the `PmsResponse` shape and stops the request.
+fn reject(res: &mut Response, ctrl: &mut FlowCtrl, err: AuthError) {
+    let (status, code, message) = match err {
+        AuthError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, "unauthorized", msg),
+        AuthError::Forbidden(msg) => (StatusCode::FORBIDDEN, "forbidden", msg),
+    };
+    res.status_code(status);
+    res.render(Json(PmsResponse::error(code, message)));
+    ctrl.skip_rest();
+}
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.header::<String>(name)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Compares digests so the time taken does not reveal matching prefixes.
+fn secret_eq(given: &str, expected: &str) -> bool {
+    Sha256::digest(given.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+pub(crate) fn parse_allowed_ips(raw: &str) -> Result<Vec<IpNet>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow!("invalid allowed ip {:?}", entry))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_760_000_000;
+
+    fn client(name: &str) -> PmsClient {
+        PmsClient {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn settings(clients: Vec<PmsClient>) -> AuthSettings {
+        AuthSettings {
+            clients,
+            ..Default::default()
+        }
+    }
+
+    fn request() -> Credentials {
+        Credentials {
+            ip: Some("10.0.0.5".parse().unwrap()),
+            method: "GET".into(),
+            path: "/vhp?mode=checkout&room=101".into(),
+            ..Default::default()
+        }
+    }
+
+    fn signed(secret: &str, ts: i64) -> Credentials {
+        let mut req = request();
+        req.timestamp = Some(ts.to_string());
+        req.signature = Some(req.sign(secret));
+        req
+    }
+
+    #[test]
+    fn checks_api_key() {
+        let auth = settings(vec![PmsClient {
+            api_key: Some("k3y".into()),
+            ..client("opera")
+        }]);
+
+        let mut req = request();
+        assert_eq!(
+            auth.authorize(&req, NOW),
+            Err(AuthError::Unauthorized("missing api key".into()))
+        );
+        req.api_key = Some("nope".into());
+        assert_eq!(
+            auth.authorize(&req, NOW),
+            Err(AuthError::Unauthorized("invalid api key".into()))
+        );
+        req.api_key = Some("k3y".into());
+        assert_eq!(auth.authorize(&req, NOW), Ok("opera"));
+    }
+
+    #[test]
+    fn checks_signature_and_timestamp() {
+        let auth = settings(vec![PmsClient {
+            hmac_secret: Some("s3cret".into()),
+            ..client("opera")
+        }]);
+
+        assert_eq!(
+            auth.authorize(&signed("s3cret", NOW - 60), NOW),
+            Ok("opera")
+        );
+        assert_eq!(
+            auth.authorize(&signed("s3cret", NOW - 301), NOW),
+            Err(AuthError::Unauthorized("stale request timestamp".into()))
+        );
+        assert_eq!(
+            auth.authorize(&signed("other", NOW), NOW),
+            Err(AuthError::Unauthorized("invalid request signature".into()))
+        );
+
+        let mut tampered = signed("s3cret", NOW);
+        tampered.path = "/vhp?mode=checkout&room=102".into();
+        assert!(auth.authorize(&tampered, NOW).is_err());
+        assert_eq!(
+            auth.authorize(&request(), NOW),
+            Err(AuthError::Unauthorized("missing request signature".into()))
+        );
+    }
+
+    #[test]
+    fn required_routes_refuse_requests_without_clients() {
+        let open = || Arc::new(settings(Vec::new()));
+        assert_eq!(PmsAuth::new(open()).open_access(), Some(Ok(())));
+        assert_eq!(
+            PmsAuth::new(open()).required().open_access(),
+            Some(Err(AuthError::Forbidden(
+                "no PMS clients are configured for this endpoint".into()
+            )))
+        );
+
+        let closed = Arc::new(settings(vec![client("opera")]));
+        assert_eq!(PmsAuth::new(closed).required().open_access(), None);
+    }
+
+    #[test]
+    fn admits_repeated_signatures_within_the_window() {
+        let auth = PmsAuth::new(Arc::new(settings(vec![PmsClient {
+            hmac_secret: Some("s3cret".into()),
+            ..client("opera")
+        }])));
+
+        let req = signed("s3cret", NOW - 60);
+        assert_eq!(auth.settings.authorize(&req, NOW), Ok("opera"));
+        assert!(!auth.repeats_signature("opera", &req, NOW));
+        assert_eq!(auth.settings.authorize(&req, NOW + 30), Ok("opera"));
+        assert!(auth.repeats_signature("opera", &req, NOW + 30));
+        assert!(!auth.repeats_signature("opera", &signed("s3cret", NOW), NOW));
+    }
+
+    #[test]
+    fn forgets_signatures_once_stale() {
+        let mut seen = SeenSignatures::default();
+        assert!(seen.insert("a", NOW + 300, NOW));
+        assert!(!seen.insert("a", NOW + 300, NOW + 10));
+        assert!(seen.insert("b", NOW + 600, NOW + 301));
+        assert_eq!(seen.known, HashSet::from(["b".to_string()]));
+    }
+
+    #[test]
+    fn valid_key_from_unlisted_source_is_forbidden() {
+        let auth = settings(vec![PmsClient {
+            api_key: Some("k3y".into()),
+            allowed_ips: parse_allowed_ips("192.168.1.0/24, 10.0.0.9").unwrap(),
+            ..client("opera")
+        }]);
+
+        let mut req = request();
+        req.api_key = Some("k3y".into());
+        assert_eq!(
+            auth.authorize(&req, NOW),
+            Err(AuthError::Forbidden(
+                "source 10.0.0.5 is not allowed".into()
+            ))
+        );
+        req.ip = Some("192.168.1.20".parse().unwrap());
+        assert_eq!(auth.authorize(&req, NOW), Ok("opera"));
+    }
+
+    #[test]
+    fn any_configured_client_may_accept() {
+        let auth = settings(vec![
+            PmsClient {
+                allowed_ips: parse_allowed_ips("192.168.1.0/24").unwrap(),
+                ..client("opera")
+            },
+            PmsClient {
+                allowed_ips: parse_allowed_ips("10.0.0.0/8").unwrap(),
+                ..client("protel")
+            },
+        ]);
+
+        assert_eq!(auth.authorize(&request(), NOW), Ok("protel"));
+        assert!(settings(Vec::new()).is_open());
+        assert!(parse_allowed_ips("10.0.0.300").is_err());
+    }
+}
@@ -1,10 +1,21 @@
 use crate::application::{
-    dtos::{PmsQueryParams, PmsResponse},
+    dtos::{
+        HistoryEntry, HistoryQueryParams, PmsEvent, PmsQueryParams, PmsResponse, ReconcileParams,
+        ReconcileReport, SnapshotGuest,
+    },
     errors::ErrorResponse,
+    metrics::METRICS,
     services::BookingService,
+    settings::{ServiceSettings, settings_for, tenant_settings},
 };
-use crate::infrastructure::{database::db_pool, repositories::MySqlBookingRepository};
+use crate::domain::{entities::GuestFilter, repositories::BookingRepository};
+use crate::infrastructure::{
+    database::pool_stats, radius::nas_client, repositories::booking_repository,
+};
+use crate::presentation::auth::CLIENT_PROPERTY;
+use salvo::http::header::CONTENT_TYPE;
 use salvo::prelude::*;
+use serde::Serialize;
 use std::sync::Arc;
 
 #[endpoint(
@@ -16,63 +27,352 @@ use std::sync::Arc;
         })),
         (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
             "status": "error",
-            "message": "bad request",
+            "code": "invalid_field",
+            "message": "invalid checkout date 31/02/2025",
+            "field": "codate",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
         })),
         (status_code = 404, body = PmsResponse, description = "not found", example = json!({
             "status": "error",
+            "code": "not_found",
             "message": "not found",
         })),
-        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+        (status_code = 409, body = PmsResponse, description = "conflict with the current occupancy", example = json!({
+            "status": "error",
+            "code": "conflict",
+            "message": "room 101 is in use",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error or no hotel service", example = json!({
             "status": "error",
+            "code": "internal_error",
             "message": "internal server error",
         })),
+        (status_code = 503, body = PmsResponse, description = "database unavailable", example = json!({
+            "status": "error",
+            "code": "database_unavailable",
+            "message": "database unavailable, retry later",
+        })),
     )
 )]
-pub async fn pms_handler(req: &mut Request, res: &mut Response) {
-    let pool = db_pool();
-    let repo = Arc::new(MySqlBookingRepository { pool: pool.clone() });
-    let service = BookingService::new(repo);
-
-    let query = match req.parse_queries::<PmsQueryParams>() {
+pub async fn pms_handler(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let mut query = match req.parse_queries::<PmsQueryParams>() {
         Ok(q) => q,
         Err(_) => {
             res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Json(PmsResponse {
-                status: "error".into(),
-                message: "invalid query params".into(),
-            }));
+            res.render(Json(PmsResponse::error(
+                "invalid_request",
+                "invalid query params",
+            )));
             return;
         }
     };
 
-    match service.process(query).await {
-        Ok(resp) => {
-            res.status_code(StatusCode::OK);
-            res.render(Json(resp));
+    if let Some(key) = idempotency_key(req) {
+        query.idempotency_key = Some(key);
+    }
+
+    let Some(service) = request_service(req, depot, res, query.property.as_deref()) else {
+        return;
+    };
+    render_result(res, service.process(query).await);
+}
+
+#[endpoint(
+    request_body = PmsEvent,
+    responses(
+        (status_code = 200, body = PmsResponse, description = "success", example = json!({
+            "status": "success",
+            "message": "room {} successfully checkin|checkout|update",
+        })),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "invalid_request",
+            "message": "invalid json body",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 404, body = PmsResponse, description = "not found", example = json!({
+            "status": "error",
+            "code": "not_found",
+            "message": "not found",
+        })),
+        (status_code = 409, body = PmsResponse, description = "conflict with the current occupancy", example = json!({
+            "status": "error",
+            "code": "conflict",
+            "message": "room 101 is in use",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error or no hotel service", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+        (status_code = 503, body = PmsResponse, description = "database unavailable", example = json!({
+            "status": "error",
+            "code": "database_unavailable",
+            "message": "database unavailable, retry later",
+        })),
+    )
+)]
+pub async fn pms_event_handler(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let event = match req.parse_json::<PmsEvent>().await {
+        Ok(e) => e,
+        Err(err) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(PmsResponse::error(
+                "invalid_request",
+                format!("invalid json body: {}", err),
+            )));
+            return;
         }
+    };
 
-        Err(ErrorResponse::Validation(msg)) => {
+    let mut query: PmsQueryParams = event.into();
+    query.idempotency_key = idempotency_key(req);
+    query.property = req.query("property");
+
+    let Some(service) = request_service(req, depot, res, query.property.as_deref()) else {
+        return;
+    };
+    render_result(res, service.process(query).await);
+}
+
+#[endpoint(
+    parameters(HistoryQueryParams),
+    responses(
+        (status_code = 200, body = Vec<HistoryEntry>, description = "audit trail, newest first"),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "validation_error",
+            "message": "invalid date 2025-13-01",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn history_handler(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let params = match req.parse_queries::<HistoryQueryParams>() {
+        Ok(q) => q,
+        Err(_) => {
             res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Json(PmsResponse {
-                status: "error".into(),
-                message: msg,
-            }));
+            res.render(Json(PmsResponse::error(
+                "invalid_request",
+                "invalid query params",
+            )));
+            return;
         }
+    };
+
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.history(params).await);
+}
+
+#[endpoint(
+    parameters(ReconcileParams),
+    request_body(
+        content = Vec<SnapshotGuest>,
+        description = "in-house guests as a JSON array, or CSV with a header row (room,name,pass,rsvno,cidate,codate,cotime,gtype)"
+    ),
+    responses(
+        (status_code = 200, body = ReconcileReport, description = "differences found, and whether they were fixed"),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "validation_error",
+            "message": "guest 3: invalid checkout date format",
+        })),
+        (status_code = 401, body = PmsResponse, description = "unauthorized", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "invalid api key",
+        })),
+        (status_code = 403, body = PmsResponse, description = "forbidden", example = json!({
+            "status": "error",
+            "code": "forbidden",
+            "message": "source 10.0.0.5 is not allowed",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn reconcile_handler(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let apply = req
+        .parse_queries::<ReconcileParams>()
+        .ok()
+        .and_then(|p| p.apply)
+        .unwrap_or(false);
 
-        Err(ErrorResponse::NotFound(msg)) => {
-            res.status_code(StatusCode::NOT_FOUND);
-            res.render(Json(PmsResponse {
-                status: "error".into(),
-                message: msg,
-            }));
+    let body = req.payload().await.map(|b| b.to_vec()).unwrap_or_default();
+    let snapshot = match std::str::from_utf8(&body)
+        .map_err(anyhow::Error::from)
+        .and_then(SnapshotGuest::parse_snapshot)
+    {
+        Ok(s) => s,
+        Err(err) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(PmsResponse::error(
+                "invalid_request",
+                format!("invalid snapshot: {}", err),
+            )));
+            return;
         }
+    };
+
+    let Some(service) = request_service(req, depot, res, None) else {
+        return;
+    };
+    render_result(res, service.reconcile(snapshot, apply).await);
+}
 
-        Err(ErrorResponse::InternalServerErr(msg)) => {
+/// Prometheus scrape target; occupancy and pool gauges are refreshed on
+/// every scrape.
+#[handler]
+pub async fn metrics_handler(res: &mut Response) {
+    let (size, idle, max) = pool_stats();
+    METRICS.set_pool(size, idle, max);
+
+    let mut guests = Vec::new();
+    for settings in tenant_settings() {
+        match booking_repository(&settings)
+            .list_guests(&GuestFilter::default())
+            .await
+        {
+            Ok(found) => guests.push(found),
+            Err(err) => tracing::warn!("metrics: failed to count active rooms: {}", err),
+        }
+    }
+    METRICS.set_occupancy(&guests);
+
+    match METRICS.render() {
+        Ok((content_type, body)) => {
+            let _ = res.add_header(CONTENT_TYPE, content_type, true);
+            res.render(body);
+        }
+        Err(err) => {
+            tracing::error!("metrics: failed to encode: {}", err);
             res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Json(PmsResponse {
-                status: "error".into(),
-                message: msg,
-            }));
         }
     }
 }
+
+fn idempotency_key(req: &Request) -> Option<String> {
+    req.header::<String>("Idempotency-Key")
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+}
+
+fn booking_service(settings: Arc<ServiceSettings>) -> BookingService<dyn BookingRepository> {
+    BookingService::new(booking_repository(&settings), settings).with_nas_client(nas_client())
+}
+
+/// Service for the property a request is for: the `{property}` path
+/// segment, else `requested` or the `property` query parameter, else the
+/// property the client is bound to, which it may not step outside of.
+/// Renders the error and returns `None` when no property applies.
+pub(crate) fn request_service(
+    req: &Request,
+    depot: &Depot,
+    res: &mut Response,
+    requested: Option<&str>,
+) -> Option<BookingService<dyn BookingRepository>> {
+    match resolve_settings(req, depot, requested) {
+        Ok(settings) => Some(booking_service(settings)),
+        Err(err) => {
+            render_error(res, err);
+            None
+        }
+    }
+}
+
+fn resolve_settings(
+    req: &Request,
+    depot: &Depot,
+    requested: Option<&str>,
+) -> Result<Arc<ServiceSettings>, ErrorResponse> {
+    let bound = depot
+        .get::<String>(CLIENT_PROPERTY)
+        .ok()
+        .map(String::as_str);
+    let property = req
+        .param::<String>("property")
+        .or_else(|| requested.map(str::to_string))
+        .or_else(|| req.query::<String>("property"))
+        .filter(|p| !p.trim().is_empty());
+
+    let property = match (property.as_deref(), bound) {
+        (Some(asked), Some(bound)) if asked != bound => {
+            return Err(ErrorResponse::Forbidden(format!(
+                "client may not act for property {}",
+                asked
+            )));
+        }
+        (asked, bound) => asked.or(bound),
+    };
+    settings_for(property)
+}
+
+pub(crate) fn render_result<T: Serialize + Send>(
+    res: &mut Response,
+    result: Result<T, ErrorResponse>,
+) {
+    match result {
+        Ok(resp) => {
+            res.status_code(StatusCode::OK);
+            res.render(Json(resp));
+        }
+        Err(err) => render_error(res, err),
+    }
+}
+
+fn render_error(res: &mut Response, err: ErrorResponse) {
+    let status = match err {
+        ErrorResponse::Validation(_) | ErrorResponse::InvalidField { .. } => {
+            StatusCode::BAD_REQUEST
+        }
+        ErrorResponse::NotFound(_) => StatusCode::NOT_FOUND,
+        ErrorResponse::Conflict(_) => StatusCode::CONFLICT,
+        ErrorResponse::Forbidden(_) => StatusCode::FORBIDDEN,
+        ErrorResponse::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorResponse::NotConfigured(_) | ErrorResponse::InternalServerErr(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    if let ErrorResponse::InternalServerErr(msg) = &err {
+        tracing::error!("{}", msg);
+    }
+    res.status_code(status);
+    res.render(Json(PmsResponse::from_error(&err)));
+}
@@ -0,0 +1,122 @@
+use crate::application::dtos::{HealthCheck, HealthReport};
+use crate::application::settings::tenant_settings;
+use crate::infrastructure::{database::ping_db, repositories::booking_repository};
+use anyhow::{Result, anyhow};
+use salvo::prelude::*;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// A check slower than this counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[endpoint(
+    tags("health"),
+    responses(
+        (status_code = 200, body = HealthReport, description = "the process is serving requests"),
+    )
+)]
+pub async fn live_handler(res: &mut Response) {
+    res.render(Json(HealthReport::new(Vec::new())));
+}
+
+#[endpoint(
+    tags("health"),
+    responses(
+        (status_code = 200, body = HealthReport, description = "ready to take PMS requests", example = json!({
+            "status": "ok",
+            "checks": [
+                { "name": "database", "status": "ok", "latency_ms": 0.8 },
+                { "name": "hotel_service", "status": "ok", "latency_ms": 1.2 },
+            ],
+        })),
+        (status_code = 503, body = HealthReport, description = "a check failed", example = json!({
+            "status": "error",
+            "checks": [
+                { "name": "database", "status": "ok", "latency_ms": 0.8 },
+                { "name": "hotel_service", "status": "error", "latency_ms": 1.2, "error": "no active hotel service" },
+            ],
+        })),
+    )
+)]
+pub async fn ready_handler(res: &mut Response) {
+    let report = HealthReport::new(vec![
+        run_check("database", ping_db()).await,
+        run_check("hotel_service", hotel_service_available()).await,
+    ]);
+
+    if !report.is_healthy() {
+        tracing::warn!("readiness check failed: {:?}", report.checks);
+        res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    res.render(Json(report));
+}
+
+/// Checkin needs at least one active hotel service to put guests on, in
+/// every property served.
+async fn hotel_service_available() -> Result<()> {
+    for settings in tenant_settings() {
+        if booking_repository(&settings)
+            .get_cron_hotel_service()
+            .await?
+            .is_empty()
+        {
+            return Err(match &settings.property {
+                Some(property) => anyhow!("no active hotel service for property {}", property.id),
+                None => anyhow!("no active hotel service"),
+            });
+        }
+    }
+    Ok(())
+}
+
+async fn run_check(name: &str, check: impl Future<Output = Result<()>>) -> HealthCheck {
+    let started = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("timed out after {:?}", CHECK_TIMEOUT)));
+
+    HealthCheck {
+        name: name.to_string(),
+        status: if result.is_ok() { "ok" } else { "error" }.into(),
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        error: result.err().map(|err| err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_each_check() {
+        let report = HealthReport::new(vec![
+            run_check("database", async { Ok(()) }).await,
+            run_check("hotel_service", async {
+                Err(anyhow!("no active hotel service"))
+            })
+            .await,
+        ]);
+
+        assert!(!report.is_healthy());
+        assert_eq!(report.status, "error");
+        assert_eq!(report.checks[0].status, "ok");
+        assert!(report.checks[0].error.is_none());
+        assert_eq!(report.checks[1].status, "error");
+        assert_eq!(
+            report.checks[1].error.as_deref(),
+            Some("no active hotel service")
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_checks() {
+        let check = run_check("database", std::future::pending()).await;
+        assert_eq!(check.status, "error");
+        assert!(check.latency_ms >= CHECK_TIMEOUT.as_millis() as f64);
+    }
+
+    #[test]
+    fn live_report_is_healthy() {
+        assert!(HealthReport::new(Vec::new()).is_healthy());
+    }
+}
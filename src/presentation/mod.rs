@@ -1,2 +1,8 @@
+pub mod admin_handlers;
+pub mod auth;
 pub mod handlers;
+pub mod health_handlers;
+pub mod portal;
+pub mod portal_handlers;
+pub mod request_log;
 pub mod routes;
@@ -0,0 +1,351 @@
+use crate::config::var;
+use crate::presentation::auth::parse_allowed_ips;
+use anyhow::{Result, anyhow};
+use ipnet::IpNet;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tracked keys above which expired entries are dropped on the next attempt.
+const PRUNE_AT: usize = 1024;
+
+pub static PORTAL_SETTINGS: OnceCell<Arc<PortalSettings>> = OnceCell::new();
+static PORTAL_LIMITER: OnceCell<LoginLimiter> = OnceCell::new();
+
+#[derive(Debug, Clone)]
+pub struct PortalSettings {
+    /// Mounts `/portal/login`; guests reach it unauthenticated.
+    pub enabled: bool,
+    /// Logins one client address may attempt per `window`.
+    pub max_attempts: u32,
+    pub window: Duration,
+    /// Failed logins, from one address or for one room, before it is locked
+    /// out for `lockout`.
+    pub max_failures: u32,
+    pub lockout: Duration,
+    /// Proxies whose `X-Forwarded-For` names the guest's address.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl Default for PortalSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: 10,
+            window: Duration::from_secs(60),
+            max_failures: 5,
+            lockout: Duration::from_secs(900),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl PortalSettings {
+    /// Reads `PORTAL_ENABLED` (default `false`), `PORTAL_MAX_ATTEMPTS`
+    /// (default 10) per `PORTAL_WINDOW_SECS` (default 60), `PORTAL_MAX_FAILURES`
+    /// (default 5), `PORTAL_LOCKOUT_SECS` (default 900) and
+    /// `PORTAL_TRUSTED_PROXIES` (`10.0.0.2,192.168.1.0/24`).
+    pub fn from_env() -> Result<Self> {
+        let mut settings = Self::default();
+
+        if let Some(v) = var("PORTAL_ENABLED") {
+            settings.enabled = v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid PORTAL_ENABLED {:?}", v))?;
+        }
+        if let Some(v) = positive("PORTAL_MAX_ATTEMPTS")? {
+            settings.max_attempts = v;
+        }
+        if let Some(v) = positive("PORTAL_WINDOW_SECS")? {
+            settings.window = Duration::from_secs(u64::from(v));
+        }
+        if let Some(v) = positive("PORTAL_MAX_FAILURES")? {
+            settings.max_failures = v;
+        }
+        if let Some(v) = positive("PORTAL_LOCKOUT_SECS")? {
+            settings.lockout = Duration::from_secs(u64::from(v));
+        }
+        if let Some(v) = var("PORTAL_TRUSTED_PROXIES") {
+            settings.trusted_proxies = parse_allowed_ips(&v)?;
+        }
+
+        Ok(settings)
+    }
+
+    /// Address of the guest behind `remote`: the last `X-Forwarded-For` hop
+    /// not itself a trusted proxy, when `remote` is one.
+    pub fn client_ip(&self, remote: Option<IpAddr>, forwarded: Option<&str>) -> Option<IpAddr> {
+        let trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|net| net.contains(ip));
+        let remote = remote?;
+        if !trusted(&remote) {
+            return Some(remote);
+        }
+
+        let hops = forwarded
+            .unwrap_or_default()
+            .split(',')
+            .rev()
+            .map(|hop| hop.trim().parse::<IpAddr>().map(|ip| ip.to_canonical()));
+        let mut client = remote;
+        for hop in hops {
+            match hop {
+                Ok(ip) => {
+                    client = ip;
+                    if !trusted(&ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        Some(client)
+    }
+}
+
+fn positive(name: &str) -> Result<Option<u32>> {
+    var(name)
+        .map(|v| {
+            v.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("invalid {} {:?}", name, v))
+        })
+        .transpose()
+}
+
+pub fn init_portal_settings(settings: PortalSettings) {
+    let settings = Arc::new(settings);
+    PORTAL_SETTINGS
+        .set(settings.clone())
+        .expect("❌ PORTAL_SETTINGS is already initialized");
+    PORTAL_LIMITER
+        .set(LoginLimiter::new(settings))
+        .unwrap_or_else(|_| panic!("❌ PORTAL_LIMITER is already initialized"));
+}
+
+pub fn portal_settings() -> Arc<PortalSettings> {
+    PORTAL_SETTINGS
+        .get()
+        .expect("❌ PORTAL_SETTINGS is not initialized")
+        .clone()
+}
+
+pub fn portal_limiter() -> &'static LoginLimiter {
+    PORTAL_LIMITER
+        .get()
+        .expect("❌ PORTAL_LIMITER is not initialized")
+}
+
+/// Throttles portal logins per client address and locks out addresses and
+/// rooms that keep failing, so room numbers and names cannot be guessed.
+pub struct LoginLimiter {
+    settings: Arc<PortalSettings>,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    /// Attempts per client address in its current window.
+    attempts: HashMap<IpAddr, Window>,
+    /// Failures per address or room since the first of them.
+    failures: HashMap<String, Window>,
+    /// End of the lockout per address or room.
+    locked: HashMap<String, Instant>,
+}
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+impl LoginLimiter {
+    pub fn new(settings: Arc<PortalSettings>) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(LimiterState::default()),
+        }
+    }
+
+    /// Counts an attempt by `ip` on `room` (`<property>/<room>`), or returns
+    /// how long until one is allowed again.
+    pub fn admit(&self, ip: IpAddr, room: &str, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        if state.attempts.len() + state.failures.len() + state.locked.len() > PRUNE_AT {
+            self.prune(&mut state, now);
+        }
+
+        let locked = [ip_key(ip), room_key(room)]
+            .iter()
+            .filter_map(|key| state.locked.get(key))
+            .filter(|until| **until > now)
+            .max()
+            .copied();
+        if let Some(until) = locked {
+            return Err(until - now);
+        }
+
+        let window = state.attempts.entry(ip).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        if now - window.started >= self.settings.window {
+            *window = Window {
+                started: now,
+                count: 0,
+            };
+        }
+        if window.count >= self.settings.max_attempts {
+            return Err(window.started + self.settings.window - now);
+        }
+        window.count += 1;
+        Ok(())
+    }
+
+    /// Records a failed login, locking out the address or room that reached
+    /// `max_failures`.
+    pub fn failed(&self, ip: IpAddr, room: &str, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        for key in [ip_key(ip), room_key(room)] {
+            let window = state.failures.entry(key.clone()).or_insert(Window {
+                started: now,
+                count: 0,
+            });
+            if now - window.started >= self.settings.lockout {
+                *window = Window {
+                    started: now,
+                    count: 0,
+                };
+            }
+            window.count += 1;
+            if window.count >= self.settings.max_failures {
+                state.failures.remove(&key);
+                state.locked.insert(key, now + self.settings.lockout);
+            }
+        }
+    }
+
+    /// Forgets the failures on `room` once its guest logs in. Those of the
+    /// address stay, or a guest could reset them with their own room.
+    pub fn succeeded(&self, room: &str) {
+        self.state.lock().unwrap().failures.remove(&room_key(room));
+    }
+
+    fn prune(&self, state: &mut LimiterState, now: Instant) {
+        let (window, lockout) = (self.settings.window, self.settings.lockout);
+        state.attempts.retain(|_, w| now - w.started < window);
+        state.failures.retain(|_, w| now - w.started < lockout);
+        state.locked.retain(|_, until| *until > now);
+    }
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip {}", ip)
+}
+
+fn room_key(room: &str) -> String {
+    format!("room {}", room.trim().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> LoginLimiter {
+        LoginLimiter::new(Arc::new(PortalSettings {
+            max_attempts: 3,
+            max_failures: 2,
+            ..Default::default()
+        }))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn throttles_attempts_per_address() {
+        let limiter = limiter();
+        let now = Instant::now();
+        for room in ["101", "102", "103"] {
+            assert!(limiter.admit(ip("10.0.0.5"), room, now).is_ok());
+        }
+        assert_eq!(
+            limiter.admit(ip("10.0.0.5"), "104", now + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        assert!(limiter.admit(ip("10.0.0.6"), "104", now).is_ok());
+        assert!(
+            limiter
+                .admit(ip("10.0.0.5"), "104", now + Duration::from_secs(60))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn locks_out_failing_addresses_and_rooms() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        // Two addresses failing on one room lock the room for everyone.
+        limiter.failed(ip("10.0.0.5"), "/101", now);
+        limiter.failed(ip("10.0.0.6"), "/101", now);
+        assert_eq!(
+            limiter.admit(ip("10.0.0.7"), "/101", now),
+            Err(Duration::from_secs(900))
+        );
+        assert!(limiter.admit(ip("10.0.0.7"), "/102", now).is_ok());
+        assert!(limiter.admit(ip("10.0.0.5"), "/102", now).is_ok());
+
+        // One address failing on two rooms is locked out of every room.
+        limiter.failed(ip("10.0.0.5"), "/103", now);
+        assert!(limiter.admit(ip("10.0.0.5"), "/104", now).is_err());
+        assert!(
+            limiter
+                .admit(ip("10.0.0.5"), "/104", now + Duration::from_secs(900))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn success_clears_only_the_room() {
+        let limiter = limiter();
+        let now = Instant::now();
+        limiter.failed(ip("10.0.0.5"), "madrid/101", now);
+        limiter.succeeded("madrid/101");
+        limiter.failed(ip("10.0.0.6"), "madrid/101", now);
+        assert!(limiter.admit(ip("10.0.0.7"), "madrid/101", now).is_ok());
+
+        limiter.failed(ip("10.0.0.5"), "madrid/102", now);
+        assert!(limiter.admit(ip("10.0.0.5"), "madrid/103", now).is_err());
+    }
+
+    #[test]
+    fn trusts_forwarded_address_only_from_proxies() {
+        let settings = PortalSettings {
+            trusted_proxies: parse_allowed_ips("10.0.0.2, 172.16.0.0/12").unwrap(),
+            ..Default::default()
+        };
+        let forwarded = Some("203.0.113.9, 192.168.1.50, 172.16.4.1");
+
+        assert_eq!(
+            settings.client_ip(Some(ip("10.0.0.2")), forwarded),
+            Some(ip("192.168.1.50"))
+        );
+        assert_eq!(
+            settings.client_ip(Some(ip("192.168.1.77")), forwarded),
+            Some(ip("192.168.1.77"))
+        );
+        assert_eq!(
+            settings.client_ip(Some(ip("10.0.0.2")), None),
+            Some(ip("10.0.0.2"))
+        );
+        assert_eq!(
+            settings.client_ip(Some(ip("10.0.0.2")), Some("bogus")),
+            Some(ip("10.0.0.2"))
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use crate::application::dtos::{PmsResponse, PortalLoginRequest, PortalSession};
+use crate::application::metrics::METRICS;
+use crate::presentation::admin_handlers::parse_body;
+use crate::presentation::handlers::{render_result, request_service};
+use crate::presentation::portal::{portal_limiter, portal_settings};
+use salvo::http::header::RETRY_AFTER;
+use salvo::prelude::*;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Instant;
+
+/// Header a trusted proxy names the guest's address in.
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+#[endpoint(
+    tags("portal"),
+    request_body = PortalLoginRequest,
+    responses(
+        (status_code = 200, body = PortalSession, description = "credential to log the guest in with"),
+        (status_code = 400, body = PmsResponse, description = "bad request", example = json!({
+            "status": "error",
+            "code": "validation_error",
+            "message": "room and last_name or rsvno are required",
+        })),
+        (status_code = 401, body = PmsResponse, description = "no guest matches", example = json!({
+            "status": "error",
+            "code": "unauthorized",
+            "message": "room or guest details do not match",
+        })),
+        (status_code = 404, body = PmsResponse, description = "unknown property", example = json!({
+            "status": "error",
+            "code": "not_found",
+            "message": "property madrid not found",
+        })),
+        (status_code = 429, body = PmsResponse, description = "too many attempts", example = json!({
+            "status": "error",
+            "code": "too_many_requests",
+            "message": "too many login attempts, retry in 900 seconds",
+        })),
+        (status_code = 500, body = PmsResponse, description = "internal server error", example = json!({
+            "status": "error",
+            "code": "internal_error",
+            "message": "internal server error",
+        })),
+    )
+)]
+pub async fn portal_login_handler(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Some(body) = parse_body::<PortalLoginRequest>(req, res).await else {
+        return;
+    };
+    let Some(service) = request_service(req, depot, res, body.property.as_deref()) else {
+        return;
+    };
+
+    let remote = req
+        .remote_addr()
+        .clone()
+        .into_std()
+        .map(|addr| addr.ip().to_canonical());
+    let forwarded = req.header::<String>(FORWARDED_FOR_HEADER);
+    let ip = portal_settings()
+        .client_ip(remote, forwarded.as_deref())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let room = format!(
+        "{}/{}",
+        service.property().map_or("", |p| p.id.as_str()),
+        body.room.trim()
+    );
+
+    let limiter = portal_limiter();
+    if let Err(wait) = limiter.admit(ip, &room, Instant::now()) {
+        tracing::warn!(
+            "portal login for room {} from {} refused: locked out",
+            room,
+            ip
+        );
+        METRICS.record_portal_login("locked");
+        let secs = wait.as_secs().max(1);
+        res.status_code(StatusCode::TOO_MANY_REQUESTS);
+        res.add_header(RETRY_AFTER, secs.to_string(), true).ok();
+        res.render(Json(PmsResponse::error(
+            "too_many_requests",
+            format!("too many login attempts, retry in {} seconds", secs),
+        )));
+        return;
+    }
+
+    match service.portal_login(&body).await {
+        Ok(Some(session)) => {
+            limiter.succeeded(&room);
+            METRICS.record_portal_login("success");
+            tracing::info!("portal login as {} from {}", session.username, ip);
+            render_result(res, Ok(session));
+        }
+        Ok(None) => {
+            limiter.failed(ip, &room, Instant::now());
+            METRICS.record_portal_login("failure");
+            tracing::warn!("portal login for room {} from {} did not match", room, ip);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(PmsResponse::error(
+                "unauthorized",
+                "room or guest details do not match",
+            )));
+        }
+        Err(err) => {
+            METRICS.record_portal_login(err.code());
+            render_result::<PortalSession>(res, Err(err));
+        }
+    }
+}
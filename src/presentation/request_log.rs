@@ -0,0 +1,42 @@
+use crate::logging::request_id;
+use salvo::prelude::*;
+use std::time::Instant;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Runs each request in a `request` span named by its ID, echoed back in
+/// `X-Request-Id`, and logs its status and duration once it is answered.
+/// Probes and scrapes are only logged at debug level.
+#[handler]
+pub async fn request_log(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let id = request_id(req.header::<String>(REQUEST_ID_HEADER).as_deref());
+    res.add_header(REQUEST_ID_HEADER, &id, true).ok();
+
+    let path = req.uri().path().to_string();
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %path
+    );
+    async {
+        let started = Instant::now();
+        ctrl.call_next(req, depot, res).await;
+
+        let status = res.status_code.unwrap_or(StatusCode::OK).as_u16();
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        if path.starts_with("/health") || path == "/metrics" {
+            tracing::debug!(status, elapsed_ms, "request finished");
+        } else {
+            tracing::info!(status, elapsed_ms, "request finished");
+        }
+    }
+    .instrument(span)
+    .await;
+}
@@ -1,14 +1,84 @@
-use crate::presentation::handlers::pms_handler;
+use crate::presentation::admin_handlers::{
+    checkout_room_handler, extend_stay_handler, list_rooms_handler, reset_password_handler,
+    room_handler,
+};
+use crate::presentation::auth::{AuthSettings, PmsAuth, admin_auth_settings, auth_settings};
+use crate::presentation::handlers::{
+    history_handler, metrics_handler, pms_event_handler, pms_handler, reconcile_handler,
+};
+use crate::presentation::health_handlers::{live_handler, ready_handler};
+use crate::presentation::portal::portal_settings;
+use crate::presentation::portal_handlers::portal_login_handler;
 use salvo::oapi::OpenApi;
 use salvo::prelude::*;
+use std::sync::Arc;
 
 pub fn router() -> Router {
-    let api_router = Router::with_path("/vhp").get(pms_handler);
+    // `/properties/{property}/...` names the hotel of a multi-property
+    // install in the path rather than the query or the client binding.
+    let api_router = Router::new()
+        .push(vhp_router("/vhp"))
+        .push(vhp_router("/properties/{property}/vhp"));
 
-    let doc = OpenApi::default().merge_router(&api_router);
+    // Probes stay unauthenticated for load balancers and watchdogs.
+    let health_router = Router::with_path("/health")
+        .push(Router::with_path("live").get(live_handler))
+        .push(Router::with_path("ready").get(ready_handler));
 
-    Router::new()
-        .push(api_router)
+    let mut router = Router::new().push(api_router).push(health_router);
+
+    // Without admin clients the staff routes are not exposed at all.
+    let admin = admin_auth_settings();
+    if !admin.is_open() {
+        router = router
+            .push(admin_router("/admin/rooms", admin.clone()))
+            .push(admin_router("/properties/{property}/admin/rooms", admin));
+    }
+
+    // Guests call the portal unauthenticated, so it is opt-in.
+    if portal_settings().enabled {
+        router = router
+            .push(Router::with_path("/portal/login").post(portal_login_handler))
+            .push(
+                Router::with_path("/properties/{property}/portal/login").post(portal_login_handler),
+            );
+    }
+
+    let doc = OpenApi::default().merge_router(&router);
+
+    router
+        .push(Router::with_path("/metrics").get(metrics_handler))
         .push(doc.into_router("/api-doc/openapi.json"))
         .push(SwaggerUi::new("/api-doc/openapi.json").into_router("/documentation"))
 }
+
+fn vhp_router(path: &str) -> Router {
+    Router::with_path(path)
+        .push(
+            Router::new()
+                .hoop(PmsAuth::new(auth_settings()))
+                .get(pms_handler)
+                .push(Router::with_path("events").post(pms_event_handler)),
+        )
+        // History and reconciliation read and rewrite every guest, so they
+        // are refused rather than left open while no PMS client is set up.
+        .push(
+            Router::new()
+                .hoop(PmsAuth::new(auth_settings()).required())
+                .push(Router::with_path("history").get(history_handler))
+                .push(Router::with_path("reconcile").post(reconcile_handler)),
+        )
+}
+
+fn admin_router(path: &str, settings: Arc<AuthSettings>) -> Router {
+    Router::with_path(path)
+        .hoop(PmsAuth::new(settings).with_label("admin"))
+        .get(list_rooms_handler)
+        .push(
+            Router::with_path("{room}")
+                .get(room_handler)
+                .push(Router::with_path("checkout").post(checkout_room_handler))
+                .push(Router::with_path("password").post(reset_password_handler))
+                .push(Router::with_path("extend").post(extend_stay_handler)),
+        )
+}